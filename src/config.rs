@@ -1,8 +1,98 @@
-#[derive(Serialize, Deserialize, Debug)]
+use url::Url;
+
+use util::{format_cents, parse_cents, parse_duration_units, sec_to_short_string};
+
+fn default_true() -> bool {
+    true
+}
+
+/* Days of the week as working_days names them, starting on Monday */
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/* Built-in stylesheets the theme setting can name */
+pub const THEMES: &[&str] = &["light", "dark", "print"];
+
+fn all_weekdays() -> Vec<String> {
+    WEEKDAYS.iter().map(|day| day.to_string()).collect()
+}
+
+/** How a session's work time is shared among the authors of its commits */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Attribution {
+    /* In proportion to the number of commits by each author */
+    Commits,
+    /* Equally among everyone who committed */
+    Equal,
+}
+
+impl Default for Attribution {
+    fn default() -> Attribution {
+        Attribution::Commits
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub show_commits: bool,
     pub repository: Option<String>,
     pub user_name: Option<String>,
+    /* Write timesheet.html, session.html and stylesheets on every change */
+    #[serde(default = "default_true")]
+    pub html_output: bool,
+    /* Record files changed and lines added/removed per commit (one more git call) */
+    #[serde(default)]
+    pub commit_stats: bool,
+    /* Start a session if a note is added while none is running */
+    #[serde(default)]
+    pub auto_start_on_note: bool,
+    /* Record the current branch with each commit and show it before the message */
+    #[serde(default)]
+    pub commit_branch_prefix: bool,
+    /* Salt for anonymized exports, a random one is used per export if unset */
+    #[serde(default)]
+    pub anon_salt: Option<String>,
+    /* Sessions without recorded commit authors count for user_name */
+    #[serde(default)]
+    pub author_attribution: Attribution,
+    /* Link for session issues, with {} where the issue id goes */
+    #[serde(default)]
+    pub issue_url: Option<String>,
+    /* Days of the week that count for averages per working day, e.g. mon to fri */
+    #[serde(default = "all_weekdays")]
+    pub working_days: Vec<String>,
+    /* Begin a session for a commit made while none is running */
+    #[serde(default = "default_true")]
+    pub auto_session_on_commit: bool,
+    /* Commits left out by auto_session_on_commit go to the next session
+     * if it begins within this many minutes */
+    #[serde(default = "default_pending_commit_window")]
+    pub pending_commit_window: u64,
+    /* One of THEMES, inlined into the reports instead of linking style.css */
+    #[serde(default)]
+    pub theme: Option<String>,
+    /* Show the work time in the page title, e.g. "Timesheet for Alice — 1d 18h" */
+    #[serde(default = "default_true")]
+    pub title_totals: bool,
+    /* Minutes to round the times shown in reports and csv exports to, 0 for exact */
+    #[serde(default)]
+    pub round_to: u64,
+    /* Tags and a note every new session begins with, e.g. the client */
+    #[serde(default)]
+    pub default_tags: Vec<String>,
+    #[serde(default)]
+    pub default_note: Option<String>,
+    /* Seconds to work per day, for trk status --remaining */
+    #[serde(default)]
+    pub daily_target: Option<u64>,
+    /* What an hour of work is billed in invoices, and in which currency */
+    #[serde(default)]
+    pub rate_cents_per_hour: Option<u64>,
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+fn default_pending_commit_window() -> u64 {
+    60
 }
 
 impl Config {
@@ -11,6 +101,297 @@ impl Config {
             show_commits: true,
             repository: None,
             user_name: None,
+            html_output: true,
+            commit_stats: false,
+            auto_start_on_note: false,
+            commit_branch_prefix: false,
+            anon_salt: None,
+            author_attribution: Attribution::Commits,
+            issue_url: None,
+            working_days: all_weekdays(),
+            auto_session_on_commit: true,
+            pending_commit_window: default_pending_commit_window(),
+            theme: None,
+            title_totals: true,
+            round_to: 0,
+            default_tags: Vec::new(),
+            default_note: None,
+            daily_target: None,
+            rate_cents_per_hour: None,
+            currency: None,
+        }
+    }
+
+    /** Whether the day of the week (0 for Monday) is one of the working_days */
+    pub fn is_working_day(&self, weekday: u32) -> bool {
+        let name = WEEKDAYS[weekday as usize % 7];
+        self.working_days.iter().any(|day| day == name)
+    }
+}
+
+/* Settings as `trk config` names them, with author and repo as the familiar names */
+const KEYS: &[&str] = &[
+    "author",
+    "repo",
+    "show_commits",
+    "html_output",
+    "commit_stats",
+    "auto_start_on_note",
+    "commit_branch_prefix",
+    "anon_salt",
+    "author_attribution",
+    "issue_url",
+    "working_days",
+    "auto_session_on_commit",
+    "pending_commit_window",
+    "theme",
+    "title_totals",
+    "round_to",
+    "default_tags",
+    "default_note",
+    "daily_target",
+    "rate",
+    "currency",
+];
+
+fn parse_on_off(value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        _ => Err(format!(
+            "What do you mean by {}? Should be either 'on' or 'off'.",
+            value
+        )),
+    }
+}
+
+fn on_off(value: bool) -> String {
+    String::from(if value { "on" } else { "off" })
+}
+
+/* e.g. "mon,tue,wed,thu,fri", kept in the order of the week */
+fn parse_weekdays(value: &str) -> Result<Vec<String>, String> {
+    let days: Vec<&str> = value.split(',').map(|day| day.trim()).collect();
+    if let Some(day) = days.iter().find(|&&day| !WEEKDAYS.contains(&day)) {
+        return Err(format!(
+            "What do you mean by {}? Days are {}, separated by commas.",
+            day,
+            WEEKDAYS.join(", ")
+        ));
+    }
+    Ok(WEEKDAYS
+        .iter()
+        .filter(|&&day| days.contains(&day))
+        .map(|day| day.to_string())
+        .collect())
+}
+
+/** e.g. "acme, support" as ["acme", "support"], nothing for "" */
+pub fn parse_tags(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_string())
+        .collect()
+}
+
+/* An empty value unsets optional settings */
+fn optional(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+impl Config {
+    /** The setting called key, empty if unset */
+    pub fn get(&self, key: &str) -> Result<String, String> {
+        Ok(match key {
+            "author" => self.user_name.clone().unwrap_or_default(),
+            "repo" => self.repository.clone().unwrap_or_default(),
+            "show_commits" => on_off(self.show_commits),
+            "html_output" => on_off(self.html_output),
+            "commit_stats" => on_off(self.commit_stats),
+            "auto_start_on_note" => on_off(self.auto_start_on_note),
+            "commit_branch_prefix" => on_off(self.commit_branch_prefix),
+            "anon_salt" => self.anon_salt.clone().unwrap_or_default(),
+            "author_attribution" => match self.author_attribution {
+                Attribution::Commits => String::from("commits"),
+                Attribution::Equal => String::from("equal"),
+            },
+            "issue_url" => self.issue_url.clone().unwrap_or_default(),
+            "working_days" => self.working_days.join(","),
+            "auto_session_on_commit" => on_off(self.auto_session_on_commit),
+            "pending_commit_window" => self.pending_commit_window.to_string(),
+            "theme" => self.theme.clone().unwrap_or_default(),
+            "title_totals" => on_off(self.title_totals),
+            "round_to" => self.round_to.to_string(),
+            "default_tags" => self.default_tags.join(","),
+            "default_note" => self.default_note.clone().unwrap_or_default(),
+            "daily_target" => self.daily_target.map_or(String::new(), sec_to_short_string),
+            "rate" => self
+                .rate_cents_per_hour
+                .map_or(String::new(), |cents| format_cents(cents, None)),
+            "currency" => self.currency.clone().unwrap_or_default(),
+            _ => return Err(Config::unknown_key(key)),
+        })
+    }
+
+    /** Checks value and sets the setting called key to it */
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "author" => {
+                if value.is_empty() {
+                    return Err(String::from("Empty name not permitted."));
+                }
+                self.user_name = Some(value.to_string());
+            }
+            "repo" => {
+                if !value.is_empty() && Url::parse(value).is_err() && !value.contains(':') {
+                    return Err(format!(
+                        "{} is neither a URL nor an scp-like address (user@host:path).",
+                        value
+                    ));
+                }
+                self.repository = optional(value);
+            }
+            "show_commits" => self.show_commits = parse_on_off(value)?,
+            "html_output" => self.html_output = parse_on_off(value)?,
+            "commit_stats" => self.commit_stats = parse_on_off(value)?,
+            "auto_start_on_note" => self.auto_start_on_note = parse_on_off(value)?,
+            "commit_branch_prefix" => self.commit_branch_prefix = parse_on_off(value)?,
+            "anon_salt" => self.anon_salt = optional(value),
+            "author_attribution" => {
+                self.author_attribution = match value {
+                    "commits" => Attribution::Commits,
+                    "equal" => Attribution::Equal,
+                    _ => {
+                        return Err(format!(
+                            "What do you mean by {}? Should be either 'commits' or 'equal'.",
+                            value
+                        ))
+                    }
+                }
+            }
+            "issue_url" => {
+                if !value.is_empty()
+                    && (!value.contains("{}") || Url::parse(&value.replace("{}", "1")).is_err())
+                {
+                    return Err(format!(
+                        "{} is not a URL with {{}} where the issue id goes.",
+                        value
+                    ));
+                }
+                self.issue_url = optional(value);
+            }
+            "working_days" => self.working_days = parse_weekdays(value)?,
+            "auto_session_on_commit" => self.auto_session_on_commit = parse_on_off(value)?,
+            "pending_commit_window" => {
+                self.pending_commit_window = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} is not a number of minutes.", value))?
+            }
+            "theme" => {
+                if !value.is_empty() && !THEMES.contains(&value) {
+                    return Err(format!(
+                        "There is no theme {}. There are: {}.",
+                        value,
+                        THEMES.join(", ")
+                    ));
+                }
+                self.theme = optional(value);
+            }
+            "title_totals" => self.title_totals = parse_on_off(value)?,
+            "round_to" => {
+                self.round_to = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} is not a number of minutes.", value))?
+            }
+            "default_tags" => self.default_tags = parse_tags(value),
+            "default_note" => self.default_note = optional(value),
+            "daily_target" => {
+                self.daily_target = match value {
+                    "" => None,
+                    _ => Some(parse_duration_units(value).ok_or_else(|| {
+                        format!("What do you mean by {}? Should be e.g. 8h or 7h30m.", value)
+                    })?),
+                }
+            }
+            "rate" => {
+                self.rate_cents_per_hour = match value {
+                    "" => None,
+                    _ => Some(parse_cents(value).ok_or_else(|| {
+                        format!("What do you mean by {}? Should be e.g. 85 or 85.50.", value)
+                    })?),
+                }
+            }
+            "currency" => self.currency = optional(value),
+            _ => return Err(Config::unknown_key(key)),
         }
+        Ok(())
+    }
+
+    /** All settings with their values */
+    pub fn list(&self) -> Vec<(&'static str, String)> {
+        KEYS.iter()
+            .map(|&key| (key, self.get(key).unwrap_or_default()))
+            .collect()
+    }
+
+    fn unknown_key(key: &str) -> String {
+        format!("No setting {}. There are: {}.", key, KEYS.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_checks_values() {
+        let mut config = Config::new();
+        assert!(config.set("show_commits", "off").is_ok());
+        assert_eq!(config.get("show_commits"), Ok(String::from("off")));
+        assert!(config.set("show_commits", "maybe").is_err());
+        assert!(config
+            .set("repo", "git@github.com:medium-endian/trk.git")
+            .is_ok());
+        assert!(config.set("repo", "not a repo").is_err());
+        assert_eq!(
+            config.get("repo"),
+            Ok(String::from("git@github.com:medium-endian/trk.git"))
+        );
+        assert!(config.set("author_attribution", "equal").is_ok());
+        assert!(config
+            .set("issue_url", "https://jira.example.com/browse/{}")
+            .is_ok());
+        assert!(config
+            .set("issue_url", "https://jira.example.com/browse/")
+            .is_err());
+        assert!(config.set("working_days", "fri, mon,tue").is_ok());
+        assert_eq!(config.get("working_days"), Ok(String::from("mon,tue,fri")));
+        assert!(config.is_working_day(4) && !config.is_working_day(5));
+        assert!(config.set("working_days", "").is_err());
+        assert!(config.set("working_days", "monday").is_err());
+        assert!(config.set("theme", "print").is_ok());
+        assert!(config.set("theme", "neon").is_err());
+        assert_eq!(config.get("theme"), Ok(String::from("print")));
+        assert!(config.set("title_totals", "off").is_ok());
+        assert!(!config.title_totals);
+        assert!(config.set("round_to", "5").is_ok());
+        assert!(config.set("round_to", "-5").is_err());
+        assert!(config.set("default_tags", " acme,, support ").is_ok());
+        assert_eq!(config.get("default_tags"), Ok(String::from("acme,support")));
+        assert!(config.set("daily_target", "7h30m").is_ok());
+        assert_eq!(config.get("daily_target"), Ok(String::from("7h30m")));
+        assert!(config.set("daily_target", "8").is_err());
+        assert!(config.set("rate", "85.5").is_ok());
+        assert_eq!(config.rate_cents_per_hour, Some(8550));
+        assert_eq!(config.get("rate"), Ok(String::from("85.50")));
+        assert!(config.set("rate", "85,50").is_err());
+        assert!(config.set("timezone", "UTC").is_err());
+        assert_eq!(config.list().len(), KEYS.len());
     }
 }
@@ -7,6 +7,13 @@ use clap::AppSettings::SubcommandRequiredElseHelp;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+use serde_json::to_string;
+#[cfg(feature = "yaml-status")]
+extern crate serde_yaml;
+#[cfg(feature = "toml-status")]
+extern crate toml;
+#[cfg(feature = "idle")]
+extern crate user_idle;
 
 /* For parsing time strings */
 #[macro_use]
@@ -15,22 +22,152 @@ extern crate nom;
 /* For time handling */
 extern crate chrono;
 
-/* To open link to report in browser */
+/* For links to commits, issues and the reports */
 extern crate url;
-extern crate url_open;
 
 /* For process termination */
 use std::process;
+use std::time::Duration;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
 
 use util::{
-    get_seconds, git_commit_trk, git_pull, git_push, parse_hhmm_to_seconds, set_to_trk_dir,
+    current_repo_name, find_trk_root, get_seconds, git_commit_trk, git_init_trk, git_pull,
+    git_push, global_trk_root, indent_html, paint, parse_cents, parse_duration_units,
+    parse_relative, parse_timestamp, parse_until, set_color, set_to_trk_dir, ts_to_date, Color,
+    Period, Round, TrkLock,
 };
 
 mod config;
 mod sheet;
+#[cfg(test)]
+mod test_util;
 mod util;
 
-use sheet::timesheet::Timesheet;
+use config::{parse_tags, Attribution};
+use sheet::timesheet::{LoadError, Timesheet, TrkError};
+
+/* Point in time given either by --at or by how long ago (see parse_relative) */
+fn timestamp_arg(arg: &clap::ArgMatches) -> Option<u64> {
+    match arg.value_of("at") {
+        Some(at) => match parse_timestamp(at) {
+            Ok(timestamp) => Some(timestamp),
+            Err(e) => {
+                println!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => arg.value_of("ago").map(|ago| match parse_relative(ago) {
+            Some(timestamp) => timestamp,
+            None => {
+                println!(
+                    "What do you mean by {}? Should be how long ago, e.g. 30m, 1h30m or 01:30, \
+                     or a point in time like yesterday 14:00 or 2017-04-07T09:12.",
+                    ago
+                );
+                process::exit(1);
+            }
+        }),
+    }
+}
+
+/* Tells the user why the command failed and exits with an error code */
+fn fail(e: TrkError) -> ! {
+    println!("{}", e);
+    process::exit(1);
+}
+
+/* New timesheet in base_dir, with the .trk git repo next to it */
+fn init_here(base_dir: &Path, name: Option<&str>, project: Option<&str>) {
+    if let Err(e) = Timesheet::init_project(base_dir, name, project) {
+        fail(e);
+    }
+    if !git_init_trk(base_dir) {
+        fail(TrkError::GitFailure(String::from(
+            "Could not initialise the .trk git repository.",
+        )));
+    }
+}
+
+/* The timesheet chosen by --project or else TRK_PROJECT (for git hooks),
+ * None for the default one */
+fn project_arg(arguments: &clap::ArgMatches) -> Option<String> {
+    let project = arguments
+        .value_of("project")
+        .map(|project| project.to_string())
+        .or_else(|| env::var("TRK_PROJECT").ok())
+        .filter(|project| !project.is_empty() && project != "default");
+    if let Some(ref project) = project {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if !project.chars().all(valid) {
+            println!(
+                "What do you mean by {}? Project names can have letters, digits, - and _.",
+                project
+            );
+            process::exit(1);
+        }
+    }
+    project
+}
+
+/* How long to wait for another trk process to finish with the timesheet */
+const LOCK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/* Commands that leave timesheet.json alone only need a shared lock, unless
+ * they pull first (see no_git_pull), which changes .trk as well. Those
+ * writing reports or other files take the exclusive one, so that two of
+ * them don't write the same file at once. */
+fn is_read_only(arguments: &clap::ArgMatches) -> bool {
+    match arguments.subcommand() {
+        ("config", Some(arg)) => arg.subcommand_name() != Some("set"),
+        (name, _) => [
+            "milestones",
+            "prompt",
+            "status",
+            "branches",
+            "summary",
+            "extremes",
+            "stats",
+            "today",
+            "totals",
+            "session_json",
+            "events",
+            "replay",
+            "projects",
+            "install_git_hook",
+            "uninstall_git_hook",
+        ]
+        .contains(&name),
+    }
+}
+
+/* Commands that need to be fast skip pulling new changes */
+fn no_git_pull(arguments: &clap::ArgMatches) -> bool {
+    arguments.subcommand_matches("prompt").is_some()
+        || arguments
+            .subcommand_matches("status")
+            .map_or(false, |status| status.is_present("no_git"))
+}
+
+fn period_arg(text: &str) -> Option<Period> {
+    match text {
+        "week" => Some(Period::Week),
+        "month" => Some(Period::Month),
+        "year" => Some(Period::Year),
+        _ => None,
+    }
+}
+
+/* The sheet restricted to the sessions of --repo, if given */
+fn repo_view(sheet: &Timesheet, arg: &clap::ArgMatches) -> Timesheet {
+    match arg.value_of("repo") {
+        Some(repo) => sheet.for_repo(repo),
+        None => sheet.clone(),
+    }
+}
 
 fn main() {
     /* Handle command line arguments with clap */
@@ -41,6 +178,12 @@ fn main() {
         (about: "Create timesheets from git history and meta info")
             /* (@arg CONFIG: -c --config +takes_value "[UNUSED] Sets a custom config file") */
             /* (@arg debug: -d ... "[UNUSED] Sets the level of debugging information") */
+            (@arg color: --color +takes_value "auto (default), always or never. Auto colors
+                terminal output unless NO_COLOR is set.")
+            (@arg global: --global "Use the timesheet in $XDG_DATA_HOME/trk, which spans all
+                repositories and records which one each session was begun in")
+            (@arg project: --project +takes_value "Use the timesheet of this project,
+                .trk/timesheet-<project>.json, instead of the default one (also TRK_PROJECT)")
 
             (@subcommand init =>
                 (about: "Initialise trk in this directory and give name (should match git user name)")
@@ -53,14 +196,22 @@ fn main() {
                 (version: "0.1")
                 (author:  "Rafael B. <mediumendian@gmail.com>")
                 (@arg ago: "Optional: begin in the past, specify how long ago.
+                    e.g. 30m, 1h30m, 01:30 or yesterday 14:00.
                     Time must be after the last event though.")
+                (@arg at: --at +takes_value "Optional: point in time instead of ago, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
+                (@arg tags: --tags +takes_value "Tags instead of the default_tags, separated by commas")
+                (@arg note: --note +takes_value "Note instead of the default_note, \"\" for none")
             )
             (@subcommand end =>
                 (about: "End session")
                 (version: "0.1")
                 (author:  "Rafael B. <mediumendian@gmail.com>")
                 (@arg ago: "Optional: end in the past, specify how long ago.
+                    e.g. 30m, 1h30m, 01:30 or yesterday 14:00.
                     Time must be after the last event though.")
+                (@arg at: --at +takes_value "Optional: point in time instead of ago, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
                 )
             (@subcommand pause =>
                 (about: "Pause current session")
@@ -68,22 +219,37 @@ fn main() {
                 (author: "mediumendian@gmail.com")
                 (@arg note_text: "Optional: Pause note")
                 (@arg ago: "Optional: pause in the past, specify how long ago.
+                    e.g. 30m, 1h30m, 01:30 or yesterday 14:00.
                     Time must be after the last event though.")
+                (@arg at: --at +takes_value "Optional: point in time instead of ago, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
             )
             (@subcommand resume =>
                 (about: "Resume currently paused session")
                 (version: "0.1")
                 (author: "mediumendian@gmail.com")
                 (@arg ago: "Optional: resume in the past, specify how long ago.
+                    e.g. 30m, 1h30m, 01:30 or yesterday 14:00.
                     Time must be after the last event though.")
+                (@arg at: --at +takes_value "Optional: point in time instead of ago, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
             )
             (@subcommand note =>
                 (about: "Add a note about current work or pause")
                 (version: "0.1")
                 (author: "mediumendian@gmail.com")
-                (@arg note_text: +required "Note text")
+                (@arg note_text: "Note text, unless read with --from_stdin")
+                (@arg from_stdin: --from_stdin "Read the note from stdin until EOF, keeping line breaks")
                 (@arg ago: "Optional: Add a note in the past, specify how long ago.
+                    e.g. 30m, 1h30m, 01:30 or yesterday 14:00.
                     Time must be after the last event though.")
+                (@arg at: --at +takes_value "Optional: point in time instead of ago, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
+            )
+            (@subcommand undo =>
+                (about: "Remove the last event (note, pause, resume or commit) of the running session")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
             )
             (@subcommand commit =>
                 (about: "Add a commit to the event list")
@@ -97,31 +263,316 @@ fn main() {
                 (author: "mediumendian@gmail.com")
                 (@arg name: +required "branch name")
             )
+            (@subcommand mark =>
+                (about: "Mark a commit as a milestone, e.g. a release")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg hash: +required "Commit hash id or its beginning")
+            )
+            (@subcommand issue =>
+                (about: "Link the last session to an issue, e.g. JIRA-123")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg id: "Issue id, replaces the one linked before")
+                (@arg clear: --clear conflicts_with[id] "Unlink the session from its issue")
+            )
+            (@subcommand flush_commits =>
+                (about: "Add the commits made while no session was running (see the
+                    auto_session_on_commit setting) to the last session")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg discard: --discard "Drop them instead")
+            )
+            (@subcommand milestones =>
+                (about: "List the commits marked as milestones")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+            )
             (@subcommand set_show_commits =>
                     (about: "Show information about git commits/branches in the report")
                     (version: "0.1")
                     (author: "mediumendian@gmail.com")
                     (@arg on_off: +required "on or off")
             )
+            (@subcommand set_commit_branch_prefix =>
+                    (about: "Record the branch of each commit and show it before the commit message")
+                    (version: "0.1")
+                    (author: "mediumendian@gmail.com")
+                    (@arg on_off: +required "on or off")
+            )
+            (@subcommand set_auto_start_on_note =>
+                    (about: "Start a session when adding a note while no session is running")
+                    (version: "0.1")
+                    (author: "mediumendian@gmail.com")
+                    (@arg on_off: +required "on or off")
+            )
+            (@subcommand set_rate =>
+                    (about: "Set what an hour of work is billed in invoices")
+                    (version: "0.1")
+                    (author: "mediumendian@gmail.com")
+                    (@arg amount: +required "Per hour, e.g. 85 or 85.50, 'none' for only hours")
+                    (@arg currency: "e.g. EUR")
+            )
+            (@subcommand set_commit_stats =>
+                    (about: "Record files changed and lines added/removed for each commit")
+                    (version: "0.1")
+                    (author: "mediumendian@gmail.com")
+                    (@arg on_off: +required "on or off")
+            )
+            (@subcommand set_author_attribution =>
+                    (about: "How session time is shared among commit authors in the per-author breakdown")
+                    (version: "0.1")
+                    (author: "mediumendian@gmail.com")
+                    (@arg model: +required "commits (in proportion to their commits) or equal")
+            )
+            (@subcommand set_html_output =>
+                    (about: "Write html reports on every change, or only on 'trk report'")
+                    (version: "0.1")
+                    (author: "mediumendian@gmail.com")
+                    (@arg on_off: +required "on or off")
+            )
+            (@subcommand config =>
+                (about: "Show or change settings")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@subcommand get =>
+                    (about: "Print one setting")
+                    (@arg key: +required "Name of the setting, see 'trk config list'")
+                )
+                (@subcommand set =>
+                    (about: "Change one setting. An empty value unsets optional ones.")
+                    (@arg key: +required "Name of the setting, see 'trk config list'")
+                    (@arg value: +required "on/off for switches, text otherwise")
+                )
+                (@subcommand list =>
+                    (about: "Print all settings")
+                )
+            )
             (@subcommand set_repo_url =>
                     (about: "Set git repo url to use for turning commit hashes to links")
                     (version: "0.1")
                     (author: "mediumendian@gmail.com")
                     (@arg url: +required "url to repository")
             )
+            (@subcommand reclassify =>
+                (about: "Turn the git branches recorded in all sessions into tags (backs up the sheet first)")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg branches_to_tags: --branches_to_tags +required "Move branches to tags")
+                (@arg keep_branches: --keep_branches "Copy instead of move")
+            )
             (@subcommand status =>
                 (about: "Prints the current WIP for session or sheet")
                 (version: "0.1")
                 (author: "mediumendian@gmail.com")
-                (@arg sheet_or_session: +required "session or sheet")
+                (@arg sheet_or_session: required_unless[remaining] "session or sheet")
+                (@arg format: --format +takes_value
+                    "text (default), json, yaml or toml. All but text describe both sheet and session.")
+                (@arg remaining: --remaining +takes_value min_values(0)
+                    "Instead print how much is left of today's target, e.g. 8h. Default is the
+                    daily_target setting.")
+                (@arg segments: --segments
+                    "Break the last session into stretches of work and pause, e.g. 45m work, 10m pause")
+                (@arg no_git: --no_git
+                    "Only read the stored timesheet and don't run git, so changes made on other
+                    machines (git pull) don't show up yet")
+            )
+            (@subcommand prompt =>
+                (about: "Prints a short status for shell prompts, e.g. 'trk 1h5m', and nothing
+                    without a running session. Never runs git.")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+            )
+            (@subcommand doctor =>
+                (about: "Checks the timesheet for problems like overlapping sessions")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg repair: --repair "Try to fix the problems found (backs up the sheet first)")
+            )
+            (@subcommand checkpoint =>
+                (about: "Pauses the running session, backdated, if you have been idle for a while.
+                    Meant to be run periodically, e.g. from cron.")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg idle_minutes: --idle_minutes +takes_value "How long counts as idle. Default is 15.")
+            )
+            (@subcommand invoice =>
+                (about: "Write an invoice for this week, month or year as LaTeX and, if pdflatex is
+                    installed, as pdf")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg out: --out +takes_value "Pdf to write. Default is invoice.pdf.")
+                (@arg client: --client +takes_value "Who the invoice goes to")
+                (@arg rate: --rate +takes_value "Hourly rate, default the one from set_rate. Else only hours.")
+                (@arg html: --html "Write invoice.html (or --out) instead of LaTeX and pdf")
+                (@arg period: --period +takes_value "week, month (default) or year")
+                (@arg last: --last "Bill the previous period instead of the current one")
+                (@arg round: --round +takes_value "Round each day's hours: none (default), 15m or 1h")
+                (@arg repo: --repo +takes_value "Only sessions begun in this repository (global timesheet)")
+            )
+            (@subcommand extremes =>
+                (about: "Prints the longest and shortest session by work time")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg include_running: --include_running "Also consider the running session")
+            )
+            (@subcommand stats =>
+                (about: "Prints overall numbers like commits per day worked")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg json: --json "Print as JSON")
+                (@arg repo: --repo +takes_value "Only sessions begun in this repository (global timesheet)")
+            )
+            (@subcommand today =>
+                (about: "Prints work, pause and idle time since midnight")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg json: --json "Print as JSON, e.g. for dashboards")
+            )
+            (@subcommand totals =>
+                (about: "Prints hours worked per calendar day or week, splitting work across midnight")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg period: --period +takes_value "day or week. Default is day.")
+                (@arg repo: --repo +takes_value "Only sessions begun in this repository (global timesheet)")
+            )
+            (@subcommand prune =>
+                (about: "Remove finalized sessions shorter than a few seconds (backs up the sheet first)")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg empty: --empty +required "Remove sessions without work time")
+                (@arg min_seconds: --min_seconds +takes_value
+                    "Sessions with less work time count as empty. Default is 5.")
+                (@arg dry_run: --dry_run "Only list what would be removed")
+            )
+            (@subcommand branches =>
+                (about: "Prints the work time per branch, most first")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg json: --json "Print as JSON, e.g. for dashboards")
+                (@arg since: --since +takes_value "Only sessions from this time on, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
+            )
+            (@subcommand summary =>
+                (about: "Prints sessions, hours worked, commits and top branch per week, month or year")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg period: --period +takes_value "week, month or year. Default is month.")
+                (@arg json: --json "Print as JSON")
+                (@arg repo: --repo +takes_value "Only sessions begun in this repository (global timesheet)")
+            )
+            (@subcommand export =>
+                (about: "Prints the timesheet as csv (one line per session), json or html")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg format: --format +takes_value "csv (default), toggl, json, html, tex or md")
+                (@arg events: --events "One csv line per event instead of per session")
+                (@arg theme: --theme +takes_value "Built-in style for html: light, dark or print.
+                    Default is the theme setting.")
+                (@arg since: --since +takes_value "Only sessions (or events) from this time on, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
+                (@arg until: --until +takes_value "Only sessions (or events) before this time")
+                (@arg round_to: --round_to +takes_value
+                    "Round times to this many minutes, e.g. 5. Durations stay exact.")
+                (@arg split_by: --split_by +takes_value
+                    "month: instead write one html page per month and an index.html into --out")
+                (@arg out: --out +takes_value "File to write instead of printing, or the directory
+                    for --split_by (default timesheet_html)")
+                (@arg computed: --computed
+                    "Json with computed durations and counts instead of the stored form")
+                (@arg pretty: --pretty "Like --computed, but indented")
+                (@arg anon: --anon "Hash branches, tags and commit ids and leave out notes")
+                (@arg salt: --salt +takes_value
+                    "Salt for --anon. Default is the anon_salt setting, or a random one.")
+                (@arg repo: --repo +takes_value "Only sessions begun in this repository (global timesheet)")
+            )
+            (@subcommand import =>
+                (about: "Append finalized sessions from a csv file with columns start, end, branches, note,
+                    or from a Timewarrior export")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg format: --format +takes_value "csv (default) or timew for the output of 'timew export'")
+                (@arg force: --force "Import even if sessions would overlap")
+                (@arg file: +required "File to import")
+            )
+            (@subcommand session_json =>
+                (about: "Prints a session including work and pause time as JSON")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg index: "Optional: index of the session, starting at 0. Default is the last one.")
+            )
+            (@subcommand events =>
+                (about: "Lists the events of a session with their indices, e.g. for edit_note")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg index: "Optional: index of the session, starting at 0. Default is the last one.")
+            )
+            (@subcommand edit_note =>
+                (about: "Replace the note of a note, pause or commit (see 'trk events')")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg session: +required "Index of the session, starting at 0")
+                (@arg event: +required "Index of the event in the session, starting at 0")
+                (@arg note_text: +required "The new note, \"\" removes that of a pause")
             )
             (@subcommand report =>
                 (about:
 "Generate html report for current session or entire sheet and save it to {timesheet|session}.html")
                 (version: "0.1")
                 (author: "mediumendian@gmail.com")
-                (@arg sheet_or_session: +required "session or sheet")
+                (@arg sheet_or_session: "session or sheet")
                 (@arg ago: "How long the record should go back")
+                (@arg compare: --compare +takes_value
+                    "Instead compare this week, month or year so far to the same stretch of the last one")
+                (@arg at: --at +takes_value "Optional: start of the record instead of ago, e.g.
+                    1491549156, 2017-04-07T09:12, 09:12 (today), yesterday 14:00 or -30m.")
+                (@arg until: --until +takes_value "Optional: only sessions begun up to this time,
+                    or on this day for a date like 2017-04-07")
+                (@arg repo: --repo +takes_value "Only sessions begun in this repository (global timesheet)")
+                (@arg by_issue: --by_issue "Instead show the work time per issue")
+                (@arg daily: --daily "Instead show the hours worked and paused per day")
+                (@arg inline_css: --inline_css
+                    "Put the stylesheets into timesheet.html, e.g. to send it on as a single file")
+                (@arg no_open: --no_open "Only write the report and print its path, don't open a
+                    browser (also TRK_NO_OPEN)")
+                (@arg round_to: --round_to +takes_value "Show times rounded to this many minutes, e.g. 5")
+            )
+            (@subcommand replay =>
+                (about: "Shows which events would be accepted, and why not, without recording any")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+                (@arg inputs: +required +multiple "type@time, e.g. pause@09:12 or resume@1491549156.
+                    Types are begin, pause, resume, note, commit and end.")
+            )
+            (@subcommand rebuild_html =>
+                (about: "Regenerate timesheet.html and session.html without changing the timesheet")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+            )
+            (@subcommand rebuild_stats =>
+                (about: "Recompute the totals cached in timesheet.json for fast status output")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+            )
+            (@subcommand restore =>
+                (about: "Replace timesheet.json by the backup in .trk/timesheet.json.bak")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+            )
+            (@subcommand install_git_hook =>
+                (about: "Make git's post-commit hook call trk commit, keeping what the hook does already")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+            )
+            (@subcommand uninstall_git_hook =>
+                (about: "Take trk out of git's post-commit hook again")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
+            )
+            (@subcommand projects =>
+                (about: "List the projects with a timesheet in .trk, see --project")
+                (version: "0.1")
+                (author: "mediumendian@gmail.com")
             )
             (@subcommand clear =>
                 (about: "Temporary: clears all sessions and updates all timestamps")
@@ -131,7 +582,104 @@ fn main() {
        )
             .get_matches();
 
-    let sheet = Timesheet::load_from_file();
+    if let Err(e) = set_color(arguments.value_of("color").unwrap_or("auto")) {
+        println!("{}", e);
+        process::exit(1);
+    }
+
+    /* Finding the trk root moves there, remember where file arguments are relative to */
+    let invocation_dir = env::current_dir().unwrap();
+    let global = arguments.is_present("global");
+    let repo = if global { current_repo_name() } else { None };
+    /* Directory the timesheet is initialised in and the one holding its .trk
+     * (None before init). The global one is used by its path rather than by
+     * moving there, so that git still answers for the repository trk was
+     * called in, and isn't looked for further up. */
+    let (init_dir, root) = if global {
+        let root = match global_trk_root() {
+            Some(root) => root,
+            None => {
+                println!("Neither XDG_DATA_HOME nor HOME is set, so there's no global timesheet.");
+                process::exit(1);
+            }
+        };
+        if let Err(e) = fs::create_dir_all(&root) {
+            println!(
+                "Couldn't use {} for the global timesheet: {}",
+                root.display(),
+                e
+            );
+            process::exit(1);
+        }
+        let initialised = root.join(".trk").is_dir();
+        (root.clone(), if initialised { Some(root) } else { None })
+    } else {
+        (invocation_dir.clone(), find_trk_root())
+    };
+    /* Held from loading the timesheet until main returns */
+    let exclusive = !is_read_only(&arguments) || !no_git_pull(&arguments);
+    let _lock = match root {
+        Some(ref root) => match TrkLock::acquire(&root.join(".trk"), exclusive, LOCK_TIMEOUT) {
+            Ok(lock) => Some(lock),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                println!("Another trk process is running, try again when it is done.");
+                process::exit(1);
+            }
+            Err(e) => {
+                println!("Could not lock .trk/lock: {}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let project = project_arg(&arguments);
+    let project = project.as_deref();
+    if arguments.subcommand_matches("projects").is_some() {
+        let projects = root
+            .as_ref()
+            .map_or_else(Vec::new, |root| Timesheet::list_projects(root));
+        for name in projects {
+            let selected = name == project.unwrap_or("default");
+            println!("{} {}", if selected { "*" } else { " " }, name);
+        }
+        return;
+    }
+    let loaded = root.as_ref().map_or(Err(LoadError::Absent), |root| {
+        Timesheet::load_project(root, project)
+    });
+    let sheet = match loaded {
+        Ok(mut sheet) => {
+            sheet.set_session_repo(repo);
+            Some(sheet)
+        }
+        Err(LoadError::Absent) => None,
+        /* Don't let a broken file look like a missing one, that's how data gets lost */
+        Err(e) => {
+            let base_dir = root.clone().unwrap_or_else(|| init_dir.clone());
+            if arguments.subcommand_matches("restore").is_some() {
+                match Timesheet::restore_backup(&base_dir, project) {
+                    Ok(..) => {
+                        println!("Restored timesheet.json from timesheet.json.bak.");
+                        git_commit_trk(&base_dir, "restore timesheet from backup");
+                    }
+                    Err(e) => fail(format!("Could not restore the backup: {}", e).into()),
+                }
+                return;
+            }
+            if arguments.subcommand_matches("clear").is_none() {
+                println!("{}", e);
+                let backup = Timesheet::backup_path(&base_dir, project);
+                if backup.exists() {
+                    println!(
+                        "There is a backup, {}. Run 'trk restore' to go back to it.",
+                        backup.display()
+                    );
+                }
+                process::exit(1);
+            }
+            None
+        }
+    };
 
     /* Gets a value for config if supplied by user, or defaults to "default.conf" */
     /* let config = matches.value_of("config").unwrap_or("default.conf");
@@ -141,39 +689,42 @@ fn main() {
      * Also, check for .trk directory only after this */
     if let Some(command) = arguments.subcommand_matches("init") {
         match sheet {
-            Some(..) => println!("Already initialised."),
-            None => match Timesheet::init(command.value_of("name")) {
-                Some(..) => {
-                    println!("Init successful.");
-                    git_commit_trk("initialise trk");
-                }
-                None => println!("Could not initialize."),
-            },
+            Some(..) => fail(TrkError::AlreadyInitialized),
+            None => {
+                init_here(&init_dir, command.value_of("name"), project);
+                println!("Init successful.");
+                git_commit_trk(&init_dir, "initialise trk");
+            }
         }
         return;
     }
 
-    /* Set current dir to the next upper directory containing a .trk directory */
-    if !set_to_trk_dir() {
-        println!("Fatal: not a .trk directory (or subdirectory of one).");
-        process::exit(0);
+    /* Set current dir to the next upper directory containing a .trk directory,
+     * set_to_trk_dir says so if there is none. The global timesheet has its
+     * root already and stays out of the way of git, see above. */
+    if !global && !set_to_trk_dir() {
+        process::exit(1);
     }
+    let root = match root {
+        Some(root) => root,
+        None => fail(TrkError::NotInitialized),
+    };
 
     /* Special case for clear because t_sheet can be None when clearing (corrupt file) */
     if let Some(command) = arguments.subcommand_matches("clear") {
         match sheet {
             Some(..) => {
                 println!("Clearing timesheet.");
-                Timesheet::clear();
-                git_commit_trk("Cleared timesheet");
-            }
-            None => match Timesheet::init(command.value_of("name")) {
-                Some(..) => {
-                    println!("Reinitialised timesheet.");
-                    git_commit_trk("Reinitialised timesheet.");
+                if let Err(e) = Timesheet::clear(&root, project) {
+                    fail(e);
                 }
-                None => println!("Could not initialize."),
-            },
+                git_commit_trk(&root, "Cleared timesheet");
+            }
+            None => {
+                init_here(&root, command.value_of("name"), project);
+                println!("Reinitialised timesheet.");
+                git_commit_trk(&root, "Reinitialised timesheet.");
+            }
         }
         return;
     }
@@ -190,113 +741,796 @@ fn main() {
         }
     }
 
-    /* Pull new changes first */
-    git_pull();
+    /* Pull new changes first, unless the caller needs to be fast */
+    if !no_git_pull(&arguments) {
+        git_pull(&root);
+    }
     /* Variable to hold git commit message */
     let message;
     /* Unwrap the timesheet and continue only if timesheet file exists */
     let mut sheet = match sheet {
         Some(file) => file,
-        None => {
-            println!("No timesheet file! You might have to init first.");
-            return;
-        }
+        None => fail(TrkError::NotInitialized),
     };
 
     match arguments.subcommand() {
         ("begin", Some(arg)) => {
-            let timestamp: Option<u64> = parse_hhmm_to_seconds(arg.value_of("ago").unwrap_or(""))
-                .map(|ago| get_seconds() - ago);
-            sheet.new_session(timestamp);
+            let timestamp = timestamp_arg(arg);
+            if let Err(e) = sheet.new_session(timestamp) {
+                fail(e);
+            }
+            let tags = arg.value_of("tags").map(parse_tags);
+            let note = arg
+                .value_of("note")
+                .map(|note| Some(note.to_string()).filter(|note| !note.is_empty()));
+            sheet.set_session_defaults(tags.as_deref(), note);
             message = "begin new session";
         }
         ("end", Some(arg)) => {
-            let timestamp: Option<u64> = parse_hhmm_to_seconds(arg.value_of("ago").unwrap_or(""))
-                .map(|ago| get_seconds() - ago);
-            sheet.end_session(timestamp);
+            let timestamp = timestamp_arg(arg);
+            if let Err(e) = sheet.end_session(timestamp) {
+                fail(e);
+            }
             message = "end session";
         }
         ("pause", Some(arg)) => {
-            let timestamp: Option<u64> = parse_hhmm_to_seconds(arg.value_of("ago").unwrap_or(""))
-                .map(|ago| get_seconds() - ago);
+            let timestamp = timestamp_arg(arg);
             let note_text = arg.value_of("note_text");
-            match note_text {
-                Some(note_text) => sheet.pause(timestamp, Some(note_text.to_string())),
-                None => sheet.pause(timestamp, None),
+            if let Err(e) = sheet.pause(timestamp, note_text.map(|text| text.to_string())) {
+                fail(e);
             }
             message = "pause session";
         }
 
         ("resume", Some(arg)) => {
-            let timestamp: Option<u64> = parse_hhmm_to_seconds(arg.value_of("ago").unwrap_or(""))
-                .map(|ago| get_seconds() - ago);
-            sheet.resume(timestamp);
+            let timestamp = timestamp_arg(arg);
+            if let Err(e) = sheet.resume(timestamp) {
+                fail(e);
+            }
             message = "resume session";
         }
         ("note", Some(arg)) => {
-            let timestamp: Option<u64> = parse_hhmm_to_seconds(arg.value_of("ago").unwrap_or(""))
-                .map(|ago| get_seconds() - ago);
-            let note_text = arg.value_of("note_text").unwrap();
-            sheet.note(timestamp, note_text.to_string());
+            let timestamp = timestamp_arg(arg);
+            let note_text = if arg.is_present("from_stdin") {
+                let mut text = String::new();
+                if let Err(e) = io::stdin().read_to_string(&mut text) {
+                    fail(format!("Could not read the note from stdin: {}", e).into());
+                }
+                text.trim_end().to_string()
+            } else {
+                arg.value_of("note_text").unwrap_or("").to_string()
+            };
+            if note_text.is_empty() {
+                fail(String::from("Empty note, nothing added.").into());
+            }
+            if let Err(e) = sheet.note(timestamp, note_text) {
+                fail(e);
+            }
             message = "add note to session";
         }
+        ("undo", Some(..)) => {
+            match sheet.undo_last_event() {
+                Ok(event) => println!("Removed the {}", event.describe()),
+                Err(e) => fail(e),
+            }
+            message = "undo last event";
+        }
         ("commit", Some(arg)) => {
             let commit_hash = arg.value_of("hash").unwrap();
-            sheet.add_commit(commit_hash.to_string());
+            if let Err(e) = sheet.add_commit(commit_hash.to_string()) {
+                fail(e.into());
+            }
             message = "add commit to session";
         }
         ("branch", Some(arg)) => {
             let branch_name = arg.value_of("name").unwrap();
-            sheet.add_branch(branch_name.to_string());
+            if let Err(e) = sheet.add_branch(branch_name.to_string()) {
+                fail(e);
+            }
             message = "add branch to branchlist";
         }
+        ("mark", Some(arg)) => {
+            /* mark says why not */
+            if !sheet.mark(arg.value_of("hash").unwrap()) {
+                process::exit(1);
+            }
+            message = "mark milestone";
+        }
+        ("issue", Some(arg)) => {
+            let issue = match (arg.value_of("id"), arg.is_present("clear")) {
+                (Some(id), _) => Some(id.to_string()),
+                (None, true) => None,
+                (None, false) => {
+                    fail(String::from("Which issue? Use --clear to unlink the session.").into())
+                }
+            };
+            if let Err(e) = sheet.set_issue(issue) {
+                fail(e.into());
+            }
+            message = "set issue";
+        }
+        ("flush_commits", Some(arg)) => {
+            let discard = arg.is_present("discard");
+            let pending = sheet.pending_commits().len();
+            match sheet.flush_commits(discard) {
+                Ok(..) if pending == 0 => {
+                    println!("No pending commits.");
+                    return;
+                }
+                Ok(count) if discard => {
+                    println!("Discarded {} pending commit(s).", count);
+                    return;
+                }
+                Ok(count) => {
+                    println!("Added {} pending commit(s) to the last session.", count);
+                    if count < pending {
+                        println!("{} commit(s) are still pending.", pending - count);
+                    }
+                }
+                Err(e) => fail(e.into()),
+            }
+            message = "add pending commits";
+        }
+        ("milestones", Some(..)) => {
+            print!("{}", sheet.milestones_string());
+            return;
+        }
+        ("restore", Some(..)) => match Timesheet::restore_backup(&root, project) {
+            Ok(restored) => {
+                println!("Restored timesheet.json from timesheet.json.bak.");
+                sheet = restored;
+                message = "restore timesheet from backup";
+            }
+            Err(e) => fail(format!("Could not restore the backup: {}", e).into()),
+        },
+        ("reclassify", Some(arg)) => {
+            /* backup says why not */
+            if !sheet.backup() {
+                process::exit(1);
+            }
+            let (sessions, branches) = sheet.branches_to_tags(arg.is_present("keep_branches"));
+            println!(
+                "Reclassified {} branches in {} sessions as tags. \
+                 To undo, run 'trk restore'.",
+                branches, sessions
+            );
+            message = "reclassify branches as tags";
+        }
+        ("prompt", Some(..)) => {
+            let prompt = sheet.prompt_string();
+            if !prompt.is_empty() {
+                println!("{}", prompt);
+            }
+            return;
+        }
         ("status", Some(arg)) => {
+            if arg.is_present("remaining") {
+                let target = match arg.value_of("remaining") {
+                    Some(text) => match parse_duration_units(text) {
+                        Some(target) => target,
+                        None => fail(
+                            format!("What do you mean by {}? Should be e.g. 8h or 7h30m.", text)
+                                .into(),
+                        ),
+                    },
+                    None => match sheet.config().daily_target {
+                        Some(target) => target,
+                        None => fail(
+                            String::from(
+                                "Which target? Pass one like --remaining 8h or set daily_target.",
+                            )
+                            .into(),
+                        ),
+                    },
+                };
+                println!("{}", sheet.remaining_string(target));
+                return;
+            }
+            if arg.is_present("segments") {
+                println!("{}", sheet.last_session_segments());
+                return;
+            }
+            match arg.value_of("format") {
+                None | Some("text") => {}
+                Some(format) => {
+                    match sheet.status_formatted(format) {
+                        Ok(status) => println!("{}", status),
+                        Err(e) => fail(e.into()),
+                    }
+                    return;
+                }
+            }
             match arg.value_of("sheet_or_session") {
                 Some("session") => println!("{}", sheet.last_session_status()),
                 Some("sheet") => println!("{}", sheet.timesheet_status()),
-                Some(text) => {
-                    println!(
+                Some(text) => fail(
+                    format!(
                         "What do you mean by {}? Should be either 'sheet' or 'session'.",
                         text
                     )
-                }
+                    .into(),
+                ),
                 _ => unreachable!(),
             }
             return;
         }
-        ("report", Some(arg)) => {
-            match arg.value_of("sheet_or_session") {
-                Some("session") => sheet.report_last_session(),
-                Some("sheet") => {
-                    let timestamp: Option<u64> =
-                        parse_hhmm_to_seconds(arg.value_of("ago").unwrap_or(""))
-                            .map(|ago| get_seconds() - ago);
-                    sheet.report_sheet(timestamp);
+        ("doctor", Some(arg)) => {
+            let problems = sheet.problems();
+            if problems.is_empty() {
+                println!("No problems found.");
+                return;
+            }
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !arg.is_present("repair") {
+                return;
+            }
+            /* backup says why not */
+            if !sheet.backup() {
+                process::exit(1);
+            }
+            for problem in sheet.repair() {
+                println!("{}", problem);
+            }
+            message = "repair timesheet";
+        }
+        ("prune", Some(arg)) => {
+            let min_seconds = match arg.value_of("min_seconds").unwrap_or("5").parse::<u64>() {
+                Ok(min_seconds) => min_seconds,
+                Err(..) => fail(String::from("--min_seconds takes a number of seconds.").into()),
+            };
+            let empty = sheet.empty_sessions(min_seconds);
+            if empty.is_empty() || arg.is_present("dry_run") {
+                println!("{} session(s) to remove: {:?}", empty.len(), empty);
+                return;
+            }
+            /* backup says why not */
+            if !sheet.backup() {
+                process::exit(1);
+            }
+            println!("Removed {} session(s).", sheet.prune(min_seconds));
+            message = "prune empty sessions";
+        }
+        ("branches", Some(arg)) => {
+            let since = match arg.value_of("since").map(parse_timestamp) {
+                Some(Ok(since)) => since,
+                Some(Err(e)) => fail(format!("--since: {}", e).into()),
+                None => 0,
+            };
+            let sheet = sheet.between(since, u64::MAX);
+            if arg.is_present("json") {
+                println!("{}", to_string(&sheet.working_time_by_branch()).unwrap());
+            } else {
+                print!("{}", sheet.working_time_by_branch_string());
+            }
+            return;
+        }
+        ("summary", Some(arg)) => {
+            let sheet = repo_view(&sheet, arg);
+            let text = arg.value_of("period").unwrap_or("month");
+            let period = match period_arg(text) {
+                Some(period) => period,
+                None => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'week', 'month' or 'year'.",
+                        text
+                    )
+                    .into(),
+                ),
+            };
+            if arg.is_present("json") {
+                println!("{}", to_string(&sheet.summary(&period)).unwrap());
+            } else {
+                print!("{}", sheet.summary_table(&period));
+            }
+            return;
+        }
+        ("checkpoint", Some(arg)) => {
+            let idle_minutes = match arg.value_of("idle_minutes").unwrap_or("15").parse::<u64>() {
+                Ok(minutes) => minutes,
+                Err(e) => fail(format!("Invalid number of minutes: {}", e).into()),
+            };
+            if !sheet.checkpoint(idle_minutes * 60) {
+                return;
+            }
+            message = "pause idle session";
+        }
+        ("invoice", Some(arg)) => {
+            let sheet = repo_view(&sheet, arg);
+            let text = arg.value_of("period").unwrap_or("month");
+            let period = match period_arg(text) {
+                Some(period) => period,
+                None => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'week', 'month' or 'year'.",
+                        text
+                    )
+                    .into(),
+                ),
+            };
+            let rate = match arg.value_of("rate").map(|rate| (rate, parse_cents(rate))) {
+                Some((_, Some(cents))) => Some(cents),
+                Some((rate, None)) => fail(
+                    format!("What do you mean by {}? Should be e.g. 85 or 85.50.", rate).into(),
+                ),
+                None => sheet.config().rate_cents_per_hour,
+            };
+            let text = arg.value_of("round").unwrap_or("none");
+            let round = match Round::from_name(text) {
+                Some(round) => round,
+                None => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'none', '15m' or '1h'.",
+                        text
+                    )
+                    .into(),
+                ),
+            };
+            let now = get_seconds();
+            let (start, previous_start) = period.starts(now);
+            let (from, to) = if arg.is_present("last") {
+                (previous_start, start)
+            } else {
+                (start, now)
+            };
+            if arg.is_present("html") {
+                let out = invocation_dir.join(arg.value_of("out").unwrap_or("invoice.html"));
+                let html =
+                    indent_html(&sheet.generate_invoice_html(rate, from, to, round.minutes()));
+                match fs::write(&out, html) {
+                    Ok(()) => println!("Wrote {}.", out.display()),
+                    Err(e) => fail(format!("Could not write {}: {}", out.display(), e).into()),
                 }
-                Some(text) => {
-                    println!(
-                        "What do you mean by {}? Should be either 'sheet' or 'session'.",
+                return;
+            }
+            let out = invocation_dir.join(arg.value_of("out").unwrap_or("invoice.pdf"));
+            let client = arg.value_of("client").unwrap_or("");
+            /* write_invoice says why not */
+            if !sheet.write_invoice(&out, client, rate, from, to, round) {
+                process::exit(1);
+            }
+            return;
+        }
+        ("extremes", Some(arg)) => {
+            print!(
+                "{}",
+                sheet.extremes_string(arg.is_present("include_running"))
+            );
+            return;
+        }
+        ("stats", Some(arg)) => {
+            let sheet = repo_view(&sheet, arg);
+            if arg.is_present("json") {
+                println!("{}", to_string(&sheet.stats()).unwrap());
+            } else {
+                print!("{}", sheet.stats_string());
+            }
+            return;
+        }
+        ("today", Some(arg)) => {
+            if arg.is_present("json") {
+                println!("{}", to_string(&sheet.today()).unwrap());
+            } else {
+                print!("{}", sheet.today_string());
+            }
+            return;
+        }
+        ("totals", Some(arg)) => {
+            let sheet = repo_view(&sheet, arg);
+            let totals = match arg.value_of("period").unwrap_or("day") {
+                "day" => sheet.daily_totals(),
+                "week" => sheet.weekly_totals(),
+                text => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'day' or 'week'.",
                         text
                     )
+                    .into(),
+                ),
+            };
+            for (label, seconds) in totals {
+                println!("{:<10} {:>6.1}", label, seconds as f64 / 3600.0);
+            }
+            return;
+        }
+        ("export", Some(arg)) => {
+            let sheet = repo_view(&sheet, arg);
+            let sheet = if arg.is_present("anon") {
+                sheet.anonymized(arg.value_of("salt"))
+            } else {
+                sheet
+            };
+            let format = arg.value_of("format").unwrap_or("csv");
+            let time_arg = |name: &str, default: u64| match arg.value_of(name) {
+                Some(time) => parse_timestamp(time).unwrap_or_else(|e| {
+                    println!("--{}: {}", name, e);
+                    process::exit(1);
+                }),
+                None => default,
+            };
+            let (since, until) = (time_arg("since", 0), time_arg("until", u64::MAX));
+            let mut sheet = sheet.between(since, until);
+            for &key in &["theme", "round_to"] {
+                if let Some(value) = arg.value_of(key) {
+                    if let Err(e) = sheet.config_mut().set(key, value) {
+                        fail(e.into());
+                    }
                 }
-                _ => unreachable!(),
+            }
+            match (arg.value_of("split_by"), format) {
+                (None, _) => {}
+                (Some("month"), "html") => {
+                    let out = invocation_dir.join(arg.value_of("out").unwrap_or("timesheet_html"));
+                    match sheet.write_monthly_html(&out) {
+                        Ok(count) => println!(
+                            "Wrote {} monthly pages and index.html to {}.",
+                            count,
+                            out.display()
+                        ),
+                        Err(e) => {
+                            fail(format!("Could not write to {}: {}", out.display(), e).into())
+                        }
+                    }
+                    return;
+                }
+                (Some("month"), _) => {
+                    fail(String::from("Only html can be split, use --format html.").into())
+                }
+                (Some(text), _) => {
+                    fail(format!("What do you mean by {}? Can only split by 'month'.", text).into())
+                }
+            }
+            if let (false, "md", Some(out)) =
+                (arg.is_present("events"), format, arg.value_of("out"))
+            {
+                let out = invocation_dir.join(out);
+                /* write_to_markdown says why not */
+                if !sheet.write_to_markdown(Some(&out)) {
+                    process::exit(1);
+                }
+                println!("Wrote {}.", out.display());
+                return;
+            }
+            if let (false, "toggl", Some(out)) =
+                (arg.is_present("events"), format, arg.value_of("out"))
+            {
+                let out = invocation_dir.join(out);
+                /* export_toggl_csv says why not */
+                if !sheet.export_toggl_csv(&out) {
+                    process::exit(1);
+                }
+                println!("Wrote {}.", out.display());
+                return;
+            }
+            if format == "tex" && !arg.is_present("events") {
+                let out = invocation_dir.join(arg.value_of("out").unwrap_or("timesheet.tex"));
+                /* write_to_tex says why not */
+                if !sheet.write_to_tex(Some(&out)) {
+                    process::exit(1);
+                }
+                println!("Wrote {}. Compile it with pdflatex.", out.display());
+                return;
+            }
+            let exported = match (arg.is_present("events"), format) {
+                (true, "csv") => Ok(sheet.events_to_csv(since, until)),
+                (true, _) => Err(String::from("Events can only be exported as csv.")),
+                (false, _) => {
+                    sheet.export(format, arg.is_present("computed"), arg.is_present("pretty"))
+                }
+            };
+            match (exported, arg.value_of("out")) {
+                (Ok(exported), None) => print!("{}", exported),
+                (Ok(exported), Some(out)) => {
+                    let path = invocation_dir.join(out);
+                    if let Err(e) = fs::write(&path, exported) {
+                        fail(format!("Could not write {}: {}", path.display(), e).into());
+                    }
+                }
+                (Err(e), _) => fail(e.into()),
+            }
+            return;
+        }
+        ("import", Some(arg)) => {
+            let path = invocation_dir.join(arg.value_of("file").unwrap());
+            let force = arg.is_present("force");
+            let imported = match arg.value_of("format").unwrap_or("csv") {
+                "csv" => {
+                    let mut content = String::new();
+                    if let Err(e) =
+                        File::open(&path).and_then(|mut file| file.read_to_string(&mut content))
+                    {
+                        fail(format!("Could not read {}: {}", path.display(), e).into());
+                    }
+                    message = "import sessions from csv";
+                    sheet.import_csv(&content, force).map_err(TrkError::from)
+                }
+                "timew" => {
+                    message = "import sessions from timewarrior";
+                    sheet.import_timewarrior(&path, force)
+                }
+                format => {
+                    fail(format!("What do you mean by {}? Should be csv or timew.", format).into())
+                }
+            };
+            match imported {
+                Ok(count) => println!("Imported {} sessions.", count),
+                Err(e) => fail(e),
+            }
+        }
+        ("session_json", Some(arg)) => {
+            let index = match arg.value_of("index") {
+                Some(index) => index.parse::<usize>().ok(),
+                None => sheet.session_count().checked_sub(1),
+            };
+            match index.and_then(|index| sheet.session_json(index)) {
+                Some(json) => println!("{}", json),
+                None => fail(String::from("No such session.").into()),
+            }
+            return;
+        }
+        ("events", Some(arg)) => {
+            let index = match arg.value_of("index") {
+                Some(index) => index.parse::<usize>().ok(),
+                None => sheet.session_count().checked_sub(1),
+            };
+            match index.and_then(|index| sheet.event_list(index)) {
+                Some(list) => print!("{}", list),
+                None => fail(String::from("No such session.").into()),
             }
             return;
         }
+        ("edit_note", Some(arg)) => {
+            let index = |name: &str| {
+                let value = arg.value_of(name).unwrap();
+                match value.parse::<usize>() {
+                    Ok(index) => index,
+                    Err(..) => {
+                        println!("What do you mean by {}? Should be an index like 0.", value);
+                        process::exit(1);
+                    }
+                }
+            };
+            let note_text = arg.value_of("note_text").unwrap().to_string();
+            if let Err(e) = sheet.edit_note(index("session"), index("event"), note_text) {
+                fail(e);
+            }
+            message = "edit note";
+        }
+        ("report", Some(arg)) => {
+            let mut sheet = repo_view(&sheet, arg);
+            if let Some(minutes) = arg.value_of("round_to") {
+                if let Err(e) = sheet.config_mut().set("round_to", minutes) {
+                    fail(e.into());
+                }
+            }
+            let no_open = env::var("TRK_NO_OPEN").map_or(false, |value| !value.is_empty());
+            sheet.no_open(no_open || arg.is_present("no_open"));
+            let reported = if let Some(period) = arg.value_of("compare") {
+                match period_arg(period) {
+                    Some(period) => sheet.report_compare(&period),
+                    None => fail(
+                        format!(
+                            "What do you mean by {}? Should be either 'week', 'month' or 'year'.",
+                            period
+                        )
+                        .into(),
+                    ),
+                }
+            } else if arg.is_present("by_issue") {
+                sheet.report_issues()
+            } else if arg.is_present("daily") {
+                sheet.report_daily()
+            } else {
+                match arg.value_of("sheet_or_session") {
+                    Some("session") => sheet.report_last_session(),
+                    Some("sheet") => {
+                        let until = arg.value_of("until").map(|until| {
+                            parse_until(until).unwrap_or_else(|e| {
+                                println!("--until: {}", e);
+                                process::exit(1);
+                            })
+                        });
+                        let inline_css = arg.is_present("inline_css");
+                        sheet.report_range(timestamp_arg(arg), until, inline_css)
+                    }
+                    Some(text) => fail(
+                        format!(
+                            "What do you mean by {}? Should be either 'sheet' or 'session'.",
+                            text
+                        )
+                        .into(),
+                    ),
+                    None => {
+                        fail(String::from("Which report? Say either 'sheet' or 'session'.").into())
+                    }
+                }
+            };
+            if let Err(e) = reported {
+                fail(e);
+            }
+            return;
+        }
+        ("rebuild_html", Some(..)) => {
+            /* write_reports says why not */
+            if !sheet.write_reports() {
+                process::exit(1);
+            }
+            println!("Rebuilt timesheet.html and session.html.");
+            return;
+        }
+        ("replay", Some(arg)) => {
+            let mut inputs = Vec::new();
+            for input in arg.values_of("inputs").unwrap() {
+                let mut parts = input.splitn(2, '@');
+                let input_type = parts.next().unwrap();
+                let timestamp = match parts.next().map(parse_timestamp) {
+                    Some(Ok(timestamp)) => timestamp,
+                    Some(Err(e)) => fail(format!("{}: {}", input, e).into()),
+                    None => {
+                        fail(format!("What do you mean by {}? Should be type@time.", input).into())
+                    }
+                };
+                inputs.push((input_type.to_string(), timestamp));
+            }
+            println!("{:<8}{:<20}result", "type", "time");
+            for (&(ref input_type, timestamp), result) in inputs.iter().zip(sheet.replay(&inputs)) {
+                let result = match result {
+                    Ok(()) => paint("accepted", Color::Green),
+                    Err(why) => format!("{} {}", paint("rejected:", Color::Yellow), why),
+                };
+                println!("{:<8}{:<20}{}", input_type, ts_to_date(timestamp), result);
+            }
+            return;
+        }
+        ("install_git_hook", Some(..)) => {
+            match sheet.install_git_hook() {
+                Ok(()) => println!("Commits are recorded by git's post-commit hook now."),
+                Err(e) => fail(e),
+            }
+            return;
+        }
+        ("uninstall_git_hook", Some(..)) => {
+            match sheet.uninstall_git_hook() {
+                Ok(()) => println!("Took trk out of git's post-commit hook."),
+                Err(e) => fail(e),
+            }
+            return;
+        }
+        ("rebuild_stats", Some(..)) => {
+            /* Writing the sheet recomputes them */
+            println!("Recomputed the cached stats.");
+            message = "recompute cached stats";
+        }
         ("set_show_commits", Some(arg)) => {
             match arg.value_of("on_off") {
                 Some("on") => sheet.show_commits(true),
                 Some("off") => sheet.show_commits(false),
-                Some(text) => {
-                    println!(
+                Some(text) => fail(
+                    format!(
                         "What do you mean by {}? Should be either 'on' or 'off'.",
                         text
                     )
-                }
+                    .into(),
+                ),
                 _ => unreachable!(),
             }
             message = "set show_commits";
         }
+        ("set_commit_branch_prefix", Some(arg)) => {
+            match arg.value_of("on_off") {
+                Some("on") => sheet.commit_branch_prefix(true),
+                Some("off") => sheet.commit_branch_prefix(false),
+                Some(text) => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'on' or 'off'.",
+                        text
+                    )
+                    .into(),
+                ),
+                _ => unreachable!(),
+            }
+            message = "set commit_branch_prefix";
+        }
+        ("set_auto_start_on_note", Some(arg)) => {
+            match arg.value_of("on_off") {
+                Some("on") => sheet.auto_start_on_note(true),
+                Some("off") => sheet.auto_start_on_note(false),
+                Some(text) => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'on' or 'off'.",
+                        text
+                    )
+                    .into(),
+                ),
+                _ => unreachable!(),
+            }
+            message = "set auto_start_on_note";
+        }
+        ("set_rate", Some(arg)) => {
+            let cents = match arg.value_of("amount").unwrap() {
+                "none" => None,
+                amount => match parse_cents(amount) {
+                    Some(cents) => Some(cents),
+                    None => fail(
+                        format!(
+                            "What do you mean by {}? Should be e.g. 85 or 85.50.",
+                            amount
+                        )
+                        .into(),
+                    ),
+                },
+            };
+            sheet.set_rate(cents, arg.value_of("currency").map(|c| c.to_string()));
+            message = "set rate";
+        }
+        ("set_commit_stats", Some(arg)) => {
+            match arg.value_of("on_off") {
+                Some("on") => sheet.commit_stats(true),
+                Some("off") => sheet.commit_stats(false),
+                Some(text) => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'on' or 'off'.",
+                        text
+                    )
+                    .into(),
+                ),
+                _ => unreachable!(),
+            }
+            message = "set commit_stats";
+        }
+        ("config", Some(arg)) => match arg.subcommand() {
+            ("get", Some(arg)) => {
+                match sheet.config().get(arg.value_of("key").unwrap()) {
+                    Ok(value) => println!("{}", value),
+                    Err(e) => fail(e.into()),
+                }
+                return;
+            }
+            ("set", Some(arg)) => {
+                let key = arg.value_of("key").unwrap();
+                if let Err(e) = sheet.config_mut().set(key, arg.value_of("value").unwrap()) {
+                    fail(e.into());
+                }
+                message = "change setting";
+            }
+            _ => {
+                for (key, value) in sheet.config().list() {
+                    println!("{} = {}", key, value);
+                }
+                return;
+            }
+        },
+        ("set_author_attribution", Some(arg)) => {
+            match arg.value_of("model") {
+                Some("commits") => sheet.author_attribution(Attribution::Commits),
+                Some("equal") => sheet.author_attribution(Attribution::Equal),
+                Some(text) => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'commits' or 'equal'.",
+                        text
+                    )
+                    .into(),
+                ),
+                _ => unreachable!(),
+            }
+            message = "set author_attribution";
+        }
+        ("set_html_output", Some(arg)) => {
+            match arg.value_of("on_off") {
+                Some("on") => sheet.html_output(true),
+                Some("off") => sheet.html_output(false),
+                Some(text) => fail(
+                    format!(
+                        "What do you mean by {}? Should be either 'on' or 'off'.",
+                        text
+                    )
+                    .into(),
+                ),
+                _ => unreachable!(),
+            }
+            message = "set html_output";
+        }
         ("set_repo_url", Some(arg)) => match arg.value_of("url") {
             Some(repo_url) => {
                 sheet.set_repo_url(repo_url.to_string());
@@ -306,7 +1540,9 @@ fn main() {
         },
         _ => unreachable!(),
     }
-    sheet.write_files();
-    git_commit_trk(message);
-    git_push();
+    if let Err(e) = sheet.write_files() {
+        fail(e);
+    }
+    git_commit_trk(&root, message);
+    git_push(&root);
 }
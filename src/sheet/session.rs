@@ -1,39 +1,200 @@
 use std::fmt::Write;
 
-/* For branch name dedup */
-use std::collections::HashSet;
+/* For branch name dedup, sorted so that timesheet.json is the same for the same sessions */
+use std::collections::{BTreeMap, BTreeSet};
 
-use util::{get_seconds, sec_to_hms_string, ts_to_date};
+use util::{
+    commit_url, csv_field, get_seconds, git_current_branch, html_escape, markdown_code,
+    markdown_escape, paint, round_ts, salted_hash, sec_to_hh_mm_ss, sec_to_hms_string,
+    sec_to_short_string, short_hash, tex_escape, ts_to_date, ts_to_date_and_time, Color,
+};
 
-/* For process termination */
-use std::process;
+use std::cmp;
 
-use sheet::traits::HasHTML;
+use sheet::traits::{HasHTML, HasMarkdown, HasTEX};
 
-#[derive(PartialEq, PartialOrd, Serialize, Deserialize, Debug)]
+#[derive(PartialEq, PartialOrd, Serialize, Deserialize, Debug, Clone)]
 pub enum EventType {
     Pause,
     Resume,
     Note,
     Commit { hash: String },
+    /* Checked out another branch, see switch_branch */
+    Branch { name: String },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Event {
+impl EventType {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EventType::Pause => "pause",
+            EventType::Resume => "resume",
+            EventType::Note => "note",
+            EventType::Commit { .. } => "commit",
+            EventType::Branch { .. } => "branch",
+        }
+    }
+}
+
+/** What an event does to a paused session. Altogether:
+ *
+ *  event  | running        | paused
+ *  -------+----------------+------------------------------------
+ *  pause  | starts a pause | rejected
+ *  resume | rejected       | ends the pause
+ *  note   | added          | attached to the pause, which goes on
+ *  commit | added          | ends the pause, then added
+ *  branch | added          | ends the pause, then added
+ *
+ * A finalized session takes no events at all. */
+enum WhilePaused {
+    Rejected,
+    Ends,
+    Attaches,
+    Resumes,
+}
+
+impl WhilePaused {
+    fn of(ev_ty: &EventType) -> WhilePaused {
+        match *ev_ty {
+            EventType::Pause => WhilePaused::Rejected,
+            EventType::Resume => WhilePaused::Ends,
+            EventType::Note => WhilePaused::Attaches,
+            /* Committing means working again */
+            EventType::Commit { .. } => WhilePaused::Resumes,
+            /* So does checking out a branch */
+            EventType::Branch { .. } => WhilePaused::Resumes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
     timestamp: u64,
     note: Option<String>,
     ev_ty: EventType,
+    /* Line counts of commits, only recorded if commit_stats is on */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    files_changed: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    added: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    removed: Option<u32>,
+    /* Branch a commit was made on, only recorded if commit_branch_prefix is on */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    /* Commit author, which can differ from the timesheet's user when pairing */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author_email: Option<String>,
+    /* Commits marked as significant, e.g. releases */
+    #[serde(default, skip_serializing_if = "is_false")]
+    milestone: bool,
+}
+
+/** What the html of sessions and their events takes from the timesheet */
+#[derive(Default, Clone, Copy)]
+pub struct HtmlOptions<'a> {
+    /* Commits by someone other than user (or anyone, if there is no user)
+     * name their author */
+    pub user: Option<&'a str>,
+    /* Links the issue, {} for its id */
+    pub issue_url: Option<&'a str>,
+    /* Links the commits, see commit_url */
+    pub repo_url: Option<&'a str>,
+    /* Times are rounded to this many minutes, durations are exact */
+    pub round_to: u64,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/* Notes keep their line breaks, html needs them spelled out.
+ * Anything else that looks like markup is shown as typed. */
+fn note_html(text: &str) -> String {
+    html_escape(text).replace('\n', "<br>\n")
+}
+
+/* Same for a Markdown list item, whose further lines are indented */
+fn note_md(text: &str) -> String {
+    markdown_escape(text).replace('\n', "  \n  ")
+}
+
+/* Same for LaTeX, inside a paragraph column */
+fn note_tex(text: &str) -> String {
+    tex_escape(text).replace('\n', "\\newline ")
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Event {
+    fn new(timestamp: u64, note: Option<String>, ev_ty: EventType) -> Event {
+        Event {
+            timestamp,
+            note,
+            ev_ty,
+            files_changed: None,
+            added: None,
+            removed: None,
+            branch: None,
+            author_name: None,
+            author_email: None,
+            milestone: false,
+        }
+    }
+
+    /** e.g. "note of 2017-04-07, 09:12: blablabla" */
+    pub fn describe(&self) -> String {
+        let mut text = format!("{} of {}", self.ev_ty.name(), ts_to_date(self.timestamp));
+        if let EventType::Commit { ref hash } = self.ev_ty {
+            write!(&mut text, " {}", short_hash(hash, 7)).unwrap();
+        }
+        if let Some(ref note) = self.note {
+            write!(&mut text, ": {}", note).unwrap();
+        }
+        text
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Session {
     pub start: u64,
     pub end: u64,
     running: bool,
-    branches: HashSet<String>,
+    branches: BTreeSet<String>,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    /* Repository worked in, only recorded in the global timesheet */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repo: Option<String>,
+    /* Ticket in an issue tracker the session was spent on, e.g. JIRA-123 */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issue: Option<String>,
     events: Vec<Event>,
 }
 
+/** Session as handed out to other tools, with the derived durations */
+#[derive(Serialize, Debug)]
+pub struct SessionSummary<'a> {
+    /* Stored, except that end is the current end for a running session */
+    start: u64,
+    end: u64,
+    running: bool,
+    branches: &'a BTreeSet<String>,
+    tags: &'a BTreeSet<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repo: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    issue: Option<&'a String>,
+    events: &'a Vec<Event>,
+    /* Computed */
+    work_time: u64,
+    pause_time: u64,
+    commit_count: usize,
+    milestone_count: usize,
+    lines_added: u32,
+    lines_removed: u32,
+}
+
 impl Session {
     pub fn new(timestamp: Option<u64>) -> Session {
         let timestamp = match timestamp {
@@ -44,11 +205,37 @@ impl Session {
             start: timestamp,
             end: timestamp + 1,
             running: true,
-            branches: HashSet::<String>::new(),
+            branches: BTreeSet::<String>::new(),
+            tags: BTreeSet::<String>::new(),
+            repo: None,
+            issue: None,
             events: Vec::<Event>::new(),
         }
     }
 
+    /** A finalized session from another tracker, with the note (if any) at its start */
+    pub fn imported(
+        start: u64,
+        end: u64,
+        branches: BTreeSet<String>,
+        note: Option<String>,
+    ) -> Session {
+        let mut events = Vec::<Event>::new();
+        if let Some(note) = note {
+            events.push(Event::new(start, Some(note), EventType::Note));
+        }
+        Session {
+            start,
+            end,
+            running: false,
+            branches,
+            tags: BTreeSet::<String>::new(),
+            repo: None,
+            issue: None,
+            events,
+        }
+    }
+
     pub fn is_running(&self) -> bool {
         self.running
     }
@@ -59,6 +246,26 @@ impl Session {
             .map_or(false, |ev| ev.ev_ty == EventType::Pause)
     }
 
+    /** Time of the last event, or the start if there is none */
+    pub fn last_activity(&self) -> u64 {
+        self.events.last().map_or(self.start, |ev| ev.timestamp)
+    }
+
+    pub fn last_event_type(&self) -> Option<&EventType> {
+        self.events.last().map(|ev| &ev.ev_ty)
+    }
+
+    /** Timestamp of a resume that lies in the future, i.e. the end of a planned pause */
+    pub fn planned_resume(&self) -> Option<u64> {
+        self.events.last().and_then(|ev| {
+            if ev.ev_ty == EventType::Resume && ev.timestamp > get_seconds() {
+                Some(ev.timestamp)
+            } else {
+                None
+            }
+        })
+    }
+
     // TODO: improve the session finalize/end logic
     pub fn update_end(&mut self) {
         self.end = match self.events.len() {
@@ -67,8 +274,74 @@ impl Session {
         }
     }
 
-    pub fn finalize(&mut self, timestamp: Option<u64>) {
-        let timestamp = timestamp.unwrap_or_else(get_seconds);
+    /** Replaces the note of a note, pause or commit event, an empty one
+     * removes that of a pause */
+    pub fn set_note(&mut self, event_index: usize, text: String) -> Result<(), String> {
+        let count = self.events.len();
+        let event = match self.events.get_mut(event_index) {
+            Some(event) => event,
+            None => {
+                return Err(format!(
+                    "No event {} in this session, there are {} (counting from 0).",
+                    event_index, count
+                ))
+            }
+        };
+        match event.ev_ty {
+            EventType::Resume => return Err(String::from("A resume has no note to edit.")),
+            EventType::Branch { .. } => {
+                return Err(String::from("A branch switch has no note to edit."))
+            }
+            EventType::Pause if text.is_empty() => event.note = None,
+            _ if text.is_empty() => return Err(String::from("Empty note, nothing changed.")),
+            _ => event.note = Some(text),
+        }
+        Ok(())
+    }
+
+    /** One line per event with its index, for choosing one to edit */
+    pub fn event_list(&self) -> String {
+        let mut list = String::new();
+        for (index, event) in self.events.iter().enumerate() {
+            let note = event.note.as_deref().unwrap_or("");
+            let mut lines = note.lines();
+            let first = lines.next().unwrap_or("");
+            let more = if lines.next().is_some() { " …" } else { "" };
+            writeln!(
+                &mut list,
+                "{:>3}  {}  {:<7} {}{}",
+                index,
+                ts_to_date(event.timestamp),
+                event.ev_ty.name(),
+                first,
+                more
+            )
+            .unwrap();
+        }
+        list
+    }
+
+    /** Takes back the last event of the running session, e.g. a pause
+     * (which leaves it running) or the resume after one (paused again) */
+    pub fn pop_event(&mut self) -> Result<Event, String> {
+        if !self.is_running() {
+            return Err(String::from("Already finalized, cannot undo events."));
+        }
+        let event = match self.events.pop() {
+            Some(event) => event,
+            None => return Err(String::from("No event to undo in this session.")),
+        };
+        if self.events.is_empty() {
+            self.end = self.start + 1;
+        } else {
+            self.update_end();
+        }
+        Ok(event)
+    }
+
+    /** Ends the session, Err if timestamp isn't after its last event */
+    pub fn finalize(&mut self, given: Option<u64>) -> Result<(), String> {
+        let timestamp = given.unwrap_or_else(get_seconds);
         let is_valid_ts = match self.events.len() {
             0 => timestamp > self.start,
             n => {
@@ -77,119 +350,173 @@ impl Session {
             }
         };
         if !is_valid_ts {
-            println!("That is not a valid timestamp!");
-            process::exit(0);
+            return Err(String::from("That is not a valid timestamp!"));
+        }
+        if given.is_none() && self.is_running() && !self.is_paused() {
+            self.switch_to_checked_out();
         }
 
         if self.is_running() {
             if self.is_paused() {
-                self.push_event(Some(timestamp), None, EventType::Resume);
+                /* Can't fail, the session is running and paused */
+                let _ = self.push_event(Some(timestamp), None, EventType::Resume);
             }
             self.running = false;
             self.end = timestamp + 1;
         }
+        Ok(())
+    }
+
+    /** Lets a finalized session take events again, e.g. commits made after it
+     * ended. finalize it again afterwards. */
+    pub fn reopen(&mut self) {
+        self.running = true;
     }
 
+    /** Replays the events through the running/paused state machine (see WhilePaused)
+     * and returns the index and description of the first one that couldn't have
+     * been added that way, e.g. after editing timesheet.json by hand */
+    pub fn first_bad_transition(&self) -> Option<(usize, String)> {
+        let mut paused = false;
+        let mut last_timestamp = self.start;
+        for (idx, event) in self.events.iter().enumerate() {
+            if event.timestamp < last_timestamp {
+                return Some((idx, String::from("comes before the event preceding it")));
+            }
+            if !self.running && event.timestamp > self.end {
+                return Some((idx, String::from("comes after the session ended")));
+            }
+            let illegal = match (paused, &event.ev_ty) {
+                (true, &EventType::Pause) => Some("pause while paused"),
+                (false, &EventType::Resume) => Some("resume while not paused"),
+                (true, &EventType::Note) => Some("separate note during a pause"),
+                (true, &EventType::Commit { .. }) => Some("commit during a pause"),
+                (true, &EventType::Branch { .. }) => Some("branch switch during a pause"),
+                _ => None,
+            };
+            if let Some(illegal) = illegal {
+                return Some((idx, String::from(illegal)));
+            }
+            paused = match event.ev_ty {
+                EventType::Pause => true,
+                EventType::Resume => false,
+                _ => paused,
+            };
+            last_timestamp = event.timestamp;
+        }
+        if paused && !self.running {
+            return Some((
+                self.events.len() - 1,
+                String::from("session ended during a pause"),
+            ));
+        }
+        None
+    }
+
+    /** Adds an event, or says why it can't be added. Nothing changes on error.
+     * An event happening now (timestamp None) also records switching to the
+     * branch checked out, right before it or right after a resume. */
     pub fn push_event(
         &mut self,
         timestamp: Option<u64>,
         note: Option<String>,
         type_of_event: EventType,
-    ) -> bool {
+    ) -> Result<(), String> {
+        let count = self.events.len();
+        self.push(timestamp, note, type_of_event)?;
+        /* Not for notes that went to a pause */
+        if timestamp.is_some() || self.events.len() == count {
+            return Ok(());
+        }
+        match self.events.last().unwrap().ev_ty {
+            EventType::Branch { .. } => {}
+            EventType::Resume => self.switch_to_checked_out(),
+            _ => {
+                let (event, end) = (self.events.pop().unwrap(), self.end);
+                if !self.is_paused() {
+                    self.switch_to_checked_out();
+                }
+                self.events.push(event);
+                self.end = end;
+            }
+        }
+        Ok(())
+    }
+
+    /** Records checking out the branch of the repository around the working
+     * directory, for events happening now. Those entered for the past may
+     * have been on another branch. */
+    pub fn switch_to_checked_out(&mut self) {
+        if let Some(name) = git_current_branch() {
+            /* Can't fail, the session is running and not paused */
+            let _ = self.switch_branch(None, name);
+        }
+    }
+
+    /* push_event without recording the branch */
+    fn push(
+        &mut self,
+        timestamp: Option<u64>,
+        note: Option<String>,
+        type_of_event: EventType,
+    ) -> Result<(), String> {
         /* Cannot push if session is already finalized. */
         if !self.is_running() {
-            println!("Already finalized, cannot push event.");
-            return false;
+            return Err(String::from("Already finalized, cannot push event."));
         }
 
-        let timestamp = match timestamp {
+        let (timestamp, end) = match timestamp {
             None => {
                 let now = get_seconds();
-                self.end = now;
-                now
+                (now, now)
             }
             Some(timestamp) => {
                 let is_valid_ts = match self.events.len() {
                     0 => timestamp > self.start,
                     n => timestamp > self.events[n - 1].timestamp,
                 };
-                if is_valid_ts {
-                    self.end = timestamp + 1;
-                    timestamp
-                } else {
-                    println!("That timestamp is before the last event.");
-                    return false;
+                if !is_valid_ts {
+                    return Err(String::from("That timestamp is before the last event."));
                 }
+                (timestamp, timestamp + 1)
             }
         };
-        /* TODO: improve logic */
-        match type_of_event {
-            // TODO: fix this, so both note and ago work...
-            EventType::Pause => {
-                if self.is_paused() {
-                    println!("Already paused.");
-                    false
-                } else {
-                    self.events.push(Event {
-                        timestamp,
-                        note,
-                        ev_ty: EventType::Pause,
-                    });
-                    true
-                }
-            }
-            EventType::Resume => {
-                if !self.is_paused() {
-                    println!("Currently not paused.");
-                    false
-                } else {
-                    self.events.push(Event {
-                        timestamp,
-                        note,
-                        ev_ty: EventType::Resume,
-                    });
-                    true
+        if self.is_paused() {
+            match WhilePaused::of(&type_of_event) {
+                WhilePaused::Rejected => return Err(String::from("Already paused.")),
+                WhilePaused::Ends => {}
+                WhilePaused::Resumes => {
+                    self.events
+                        .push(Event::new(timestamp, None, EventType::Resume));
                 }
-            }
-            EventType::Note => {
-                if self.is_paused() {
-                    /* Add note to previous pause (last of events vec) */
-                    /* If self.is_paused(), then self.len() is always at least 1 */
-                    let len = self.events.len();
-                    let pause = &mut self.events[len - 1];
-                    match pause.note {
-                        Some(ref mut already) => {
-                            already.push_str("<br>");
-                            already.push_str(&note.unwrap());
+                WhilePaused::Attaches => {
+                    /* If self.is_paused(), the last event is the pause */
+                    let pause = self.events.last_mut().unwrap();
+                    if let Some(note) = note {
+                        match pause.note {
+                            Some(ref mut already) => {
+                                already.push('\n');
+                                already.push_str(&note);
+                            }
+                            None => pause.note = Some(note),
                         }
-                        None => pause.note = note,
                     }
-                } else {
-                    self.events.push(Event {
-                        timestamp,
-                        note,
-                        ev_ty: EventType::Note,
-                    })
-                };
-                true
-            }
-            /* Commit adding possible only in present */
-            EventType::Commit { hash } => {
-                if self.is_paused() {
-                    self.push_event(None, None, EventType::Resume);
-                }
-                /* Commit message must be provided */
-                if note.is_none() {
-                    println!("No commit message found for commit {}.", hash);
+                    self.end = end;
+                    return Ok(());
                 }
-                self.events.push(Event {
-                    timestamp: get_seconds(),
-                    note,
-                    ev_ty: EventType::Commit { hash },
-                });
-                true
             }
+        } else if type_of_event == EventType::Resume {
+            return Err(String::from("Currently not paused."));
         }
+        if let EventType::Commit { ref hash } = type_of_event {
+            /* Commit message must be provided */
+            if note.is_none() {
+                println!("No commit message found for commit {}.", hash);
+            }
+        }
+        self.events.push(Event::new(timestamp, note, type_of_event));
+        self.end = end;
+        Ok(())
     }
 
     pub fn pause_time(&self) -> u64 {
@@ -198,49 +525,578 @@ impl Session {
         for event in &self.events {
             match event.ev_ty {
                 EventType::Pause => last_pause_ts = event.timestamp,
-                EventType::Resume => pause_time += event.timestamp - last_pause_ts,
+                /* A planned resume only counts as pause up to now, or the end of a finished session */
+                EventType::Resume => {
+                    pause_time +=
+                        cmp::min(event.timestamp, self.current_end()).saturating_sub(last_pause_ts)
+                }
                 _ => {}
             }
         }
         if self.is_paused() {
-            pause_time += get_seconds() - self.events.last().unwrap().timestamp;
+            pause_time += self
+                .current_end()
+                .saturating_sub(self.events.last().unwrap().timestamp);
         }
         pause_time
     }
 
+    /** End of the session as far as durations are concerned:
+     * now for the running session (its stored end only moves on events),
+     * the stored end for finalized ones. */
+    pub fn current_end(&self) -> u64 {
+        if self.is_running() {
+            cmp::max(get_seconds(), self.start)
+        } else {
+            self.end
+        }
+    }
+
+    /** Time span of the session including pauses.
+     * A running session spans up to now, even if it has no events yet.
+     * Zero if a hand-edited session ends before it starts. */
+    pub fn elapsed(&self) -> u64 {
+        self.current_end().saturating_sub(self.start)
+    }
+
     pub fn work_time(&self) -> u64 {
-        let pause_time = self.pause_time();
+        self.elapsed().saturating_sub(self.pause_time())
+    }
+
+    /** Alternating stretches of work (true) and pause (false) as (working, from, to),
+     * up to now for the running session */
+    fn stretches(&self) -> Vec<(bool, u64, u64)> {
+        let end = self.current_end();
+        let mut stretches = Vec::new();
+        let mut stretch_start = self.start;
+        let mut working = true;
+        for event in &self.events {
+            let switches = match event.ev_ty {
+                EventType::Pause => working,
+                EventType::Resume => !working,
+                _ => false,
+            };
+            if switches {
+                let timestamp = cmp::max(cmp::min(event.timestamp, end), stretch_start);
+                stretches.push((working, stretch_start, timestamp));
+                stretch_start = timestamp;
+                working = !working;
+            }
+        }
+        stretches.push((working, stretch_start, cmp::max(end, stretch_start)));
+        stretches.retain(|&(_, from, to)| to > from);
+        stretches
+    }
+
+    /** Alternating stretches of work (true) and pause (false) with their durations */
+    pub fn segments(&self) -> Vec<(bool, u64)> {
+        self.stretches()
+            .into_iter()
+            .map(|(working, from, to)| (working, to - from))
+            .collect()
+    }
+
+    /** Start and end of every pause, up to now for an open one */
+    pub fn pause_intervals(&self) -> Vec<(u64, u64)> {
+        self.stretches()
+            .into_iter()
+            .filter(|&(working, _, _)| !working)
+            .map(|(_, from, to)| (from, to))
+            .collect()
+    }
+
+    /** Start and end of every stretch of work */
+    pub fn work_intervals(&self) -> Vec<(u64, u64)> {
+        self.stretches()
+            .into_iter()
+            .filter(|&(working, _, _)| working)
+            .map(|(_, from, to)| (from, to))
+            .collect()
+    }
+
+    /** Segments as e.g. "45m work, 10m pause, 20m work (now)" */
+    pub fn segments_string(&self) -> String {
+        let segments = self.segments();
+        let mut parts: Vec<String> = segments
+            .iter()
+            .map(|&(working, duration)| {
+                let kind = if working { "work" } else { "pause" };
+                format!("{} {}", sec_to_short_string(duration), kind)
+            })
+            .collect();
         if self.is_running() {
-            get_seconds() - self.start - pause_time
+            if let Some(last) = parts.last_mut() {
+                last.push_str(" (now)");
+            }
+        }
+        parts.join(", ")
+    }
+
+    pub fn commit_count(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|ev| matches!(ev.ev_ty, EventType::Commit { .. }))
+            .count()
+    }
+
+    /** When each commit of this session was made */
+    pub fn commit_times(&self) -> Vec<u64> {
+        self.events
+            .iter()
+            .filter(|ev| matches!(ev.ev_ty, EventType::Commit { .. }))
+            .map(|ev| ev.timestamp)
+            .collect()
+    }
+
+    /** Number of (pauses, notes, commits). Notes added during a pause
+     * belong to the pause and don't count. */
+    pub fn event_type_counts(&self) -> (usize, usize, usize) {
+        self.events
+            .iter()
+            .fold((0, 0, 0), |(pauses, notes, commits), ev| match ev.ev_ty {
+                EventType::Pause => (pauses + 1, notes, commits),
+                EventType::Note => (pauses, notes + 1, commits),
+                EventType::Commit { .. } => (pauses, notes, commits + 1),
+                EventType::Resume | EventType::Branch { .. } => (pauses, notes, commits),
+            })
+    }
+
+    /* e.g. "2 pauses, 1 note, 3 commits" */
+    fn event_type_counts_string(&self) -> String {
+        let (pauses, notes, commits) = self.event_type_counts();
+        let plural =
+            |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+        format!(
+            "{}, {}, {}",
+            plural(pauses, "pause"),
+            plural(notes, "note"),
+            plural(commits, "commit")
+        )
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        SessionSummary {
+            start: self.start,
+            end: self.current_end(),
+            running: self.running,
+            branches: &self.branches,
+            tags: &self.tags,
+            repo: self.repo.as_ref(),
+            issue: self.issue.as_ref(),
+            events: &self.events,
+            work_time: self.work_time(),
+            pause_time: self.pause_time(),
+            commit_count: self.commit_count(),
+            milestone_count: self.milestones().len(),
+            lines_added: self.lines_changed().0,
+            lines_removed: self.lines_changed().1,
+        }
+    }
+
+    /** Moves the end of a finalized session back to end.
+     * Refuses (returns false) if that would cut off events. */
+    pub fn trim_end(&mut self, end: u64) -> bool {
+        let cuts_events = self.events.iter().any(|ev| ev.timestamp >= end);
+        if self.is_running() || cuts_events || end <= self.start {
+            false
         } else {
-            self.end - self.start - pause_time
+            self.end = end;
+            true
         }
     }
 
+    /** Attaches (files changed, lines added, lines removed) to the last commit */
+    pub fn set_commit_stat(&mut self, stat: (u32, u32, u32)) {
+        if let Some(ev) = self.events.last_mut() {
+            if let EventType::Commit { .. } = ev.ev_ty {
+                ev.files_changed = Some(stat.0);
+                ev.added = Some(stat.1);
+                ev.removed = Some(stat.2);
+            }
+        }
+    }
+
+    /** Moves the last event back to timestamp, among the events before it.
+     * Nothing changes on Err, e.g. if it would fall before the start or into
+     * a pause. */
+    pub fn backdate_last_event(&mut self, timestamp: u64) -> Result<(), String> {
+        let mut event = match self.events.pop() {
+            Some(event) => event,
+            None => return Err(String::from("No event to move.")),
+        };
+        let original = event.timestamp;
+        if timestamp < self.start || timestamp > original {
+            self.events.push(event);
+            return Err(String::from(
+                "Can only move the last event back within the session.",
+            ));
+        }
+        let index = self
+            .events
+            .iter()
+            .position(|ev| ev.timestamp > timestamp)
+            .unwrap_or(self.events.len());
+        event.timestamp = timestamp;
+        self.events.insert(index, event);
+        if let Some((_, problem)) = self.first_bad_transition() {
+            let mut event = self.events.remove(index);
+            event.timestamp = original;
+            self.events.push(event);
+            return Err(format!(
+                "Not moving the event back, it would be a {}.",
+                problem
+            ));
+        }
+        Ok(())
+    }
+
+    /** Remembers the branch the last commit was made on */
+    pub fn set_commit_branch(&mut self, branch: String) {
+        if let Some(ev) = self.events.last_mut() {
+            if let EventType::Commit { .. } = ev.ev_ty {
+                ev.branch = Some(branch);
+            }
+        }
+    }
+
+    /** Remembers who authored the last commit */
+    pub fn set_commit_author(&mut self, name: String, email: String) {
+        if let Some(ev) = self.events.last_mut() {
+            if let EventType::Commit { .. } = ev.ev_ty {
+                ev.author_name = Some(name);
+                ev.author_email = Some(email);
+            }
+        }
+    }
+
+    /** Authors of the commits in this session that have one recorded */
+    pub fn commit_authors(&self) -> Vec<&str> {
+        self.events
+            .iter()
+            .filter_map(|ev| ev.author_name.as_deref())
+            .collect()
+    }
+
+    /** Branches the commits in this session were made on, where recorded */
+    pub fn commit_branches(&self) -> Vec<&str> {
+        self.events
+            .iter()
+            .filter_map(|ev| ev.branch.as_deref())
+            .collect()
+    }
+
+    /** Marks the last commit whose hash starts with prefix as a milestone,
+     * returning its full hash */
+    pub fn mark_commit(&mut self, prefix: &str) -> Option<String> {
+        for ev in self.events.iter_mut().rev() {
+            if let EventType::Commit { ref hash } = ev.ev_ty {
+                if hash.starts_with(prefix) {
+                    ev.milestone = true;
+                    return Some(hash.clone());
+                }
+            }
+        }
+        None
+    }
+
+    /** (timestamp, hash, message) of the commits marked as milestones */
+    pub fn milestones(&self) -> Vec<(u64, &str, Option<&str>)> {
+        self.events
+            .iter()
+            .filter(|ev| ev.milestone)
+            .filter_map(|ev| match ev.ev_ty {
+                EventType::Commit { ref hash } => {
+                    Some((ev.timestamp, hash.as_str(), ev.note.as_deref()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /** Lines (added, removed) by the commits of this session that have stats */
+    pub fn lines_changed(&self) -> (u32, u32) {
+        self.events.iter().fold((0, 0), |(added, removed), ev| {
+            (
+                added + ev.added.unwrap_or(0),
+                removed + ev.removed.unwrap_or(0),
+            )
+        })
+    }
+
+    pub fn branches(&self) -> &BTreeSet<String> {
+        &self.branches
+    }
+
     pub fn add_branch(&mut self, name: String) {
         if self.is_running() {
             self.branches.insert(name);
         }
     }
 
+    /** Records checking out name, unless it is the branch already checked out */
+    pub fn switch_branch(&mut self, timestamp: Option<u64>, name: String) -> Result<(), String> {
+        if self.checked_out() != Some(name.as_str()) {
+            self.push_event(timestamp, None, EventType::Branch { name: name.clone() })?;
+        }
+        self.add_branch(name);
+        Ok(())
+    }
+
+    /* Branch of the last recorded switch */
+    fn checked_out(&self) -> Option<&str> {
+        self.events.iter().rev().find_map(|ev| match ev.ev_ty {
+            EventType::Branch { ref name } => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /** Whether switches between branches were recorded, see switch_branch */
+    pub fn has_branch_switches(&self) -> bool {
+        self.checked_out().is_some()
+    }
+
+    /** Work time per branch, each stretch counting for the branch of the
+     * switch before it. Work before the first switch counts for None, or for
+     * the branch of a session that saw only one and recorded no switches. */
+    pub fn work_per_branch(&self) -> BTreeMap<Option<&str>, u64> {
+        let mut switches = self
+            .events
+            .iter()
+            .filter_map(|ev| match ev.ev_ty {
+                EventType::Branch { ref name } => Some((ev.timestamp, name.as_str())),
+                _ => None,
+            })
+            .peekable();
+        let mut current = match (switches.peek(), self.branches.len()) {
+            (None, 1) => self.branches.iter().next().map(String::as_str),
+            _ => None,
+        };
+        let mut times = BTreeMap::new();
+        for (start, end) in self.work_intervals() {
+            let mut from = start;
+            while let Some(&(at, name)) = switches.peek() {
+                if at >= end {
+                    break;
+                }
+                if at > from {
+                    *times.entry(current).or_insert(0) += at - from;
+                    from = at;
+                }
+                current = Some(name);
+                switches.next();
+            }
+            *times.entry(current).or_insert(0) += end - from;
+        }
+        times
+    }
+
+    pub fn set_tags(&mut self, tags: &[String]) {
+        self.tags = tags.iter().cloned().collect();
+    }
+
+    /** Sets the note at the very start of the session, e.g. the default_note,
+     * replacing one set before. None removes it. */
+    pub fn set_start_note(&mut self, note: Option<String>) {
+        let start = self.start;
+        let has_start_note = self.events.first().map_or(false, |ev| {
+            ev.ev_ty == EventType::Note && ev.timestamp == start
+        });
+        if has_start_note {
+            self.events.remove(0);
+        }
+        if let Some(note) = note {
+            self.events
+                .insert(0, Event::new(start, Some(note), EventType::Note));
+        }
+    }
+
+    /** Moves (or copies, if keep_branches) the recorded branches into the tags.
+     * Returns the number of branches reclassified. */
+    pub fn branches_to_tags(&mut self, keep_branches: bool) -> usize {
+        let count = self.branches.len();
+        if keep_branches {
+            self.tags.extend(self.branches.iter().cloned());
+        } else {
+            self.tags.append(&mut self.branches);
+        }
+        count
+    }
+
+    pub fn repo(&self) -> Option<&str> {
+        self.repo.as_deref()
+    }
+
+    pub fn set_repo(&mut self, repo: Option<String>) {
+        self.repo = repo;
+    }
+
+    pub fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    pub fn set_issue(&mut self, issue: Option<String>) {
+        self.issue = issue;
+    }
+
+    /** Replaces branches, tags, repository, issue, commit hashes, the branches commits
+     * were made on and authors by salted hashes and drops all notes and emails */
+    pub fn anonymize(&mut self, salt: &str) {
+        self.branches = self
+            .branches
+            .iter()
+            .map(|branch| salted_hash(salt, branch))
+            .collect();
+        self.tags = self.tags.iter().map(|tag| salted_hash(salt, tag)).collect();
+        self.repo = self.repo.as_ref().map(|repo| salted_hash(salt, repo));
+        self.issue = self.issue.as_ref().map(|issue| salted_hash(salt, issue));
+        for ev in &mut self.events {
+            ev.note = None;
+            ev.author_name = ev.author_name.as_ref().map(|name| salted_hash(salt, name));
+            ev.author_email = None;
+            ev.branch = ev.branch.as_ref().map(|branch| salted_hash(salt, branch));
+            match ev.ev_ty {
+                EventType::Commit { ref mut hash } => *hash = salted_hash(salt, hash),
+                EventType::Branch { ref mut name } => *name = salted_hash(salt, name),
+                _ => {}
+            }
+        }
+    }
+
+    /* start,end,work_time,pause_time,commits,branches,notes,issue
+     * with start and end rounded to round_to minutes */
+    pub fn to_csv_row(&self, round_to: u64) -> String {
+        let mut branches: Vec<&str> = self.branches.iter().map(|b| b.as_str()).collect();
+        branches.sort();
+        let notes: Vec<&str> = self
+            .events
+            .iter()
+            .filter_map(|ev| match ev.ev_ty {
+                EventType::Commit { .. } => None,
+                _ => ev.note.as_deref(),
+            })
+            .collect();
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            round_ts(self.start, round_to),
+            round_ts(self.current_end(), round_to),
+            self.work_time(),
+            self.pause_time(),
+            self.commit_count(),
+            csv_field(&branches.join(";")),
+            csv_field(&notes.join("\n")),
+            csv_field(self.issue.as_deref().unwrap_or(""))
+        )
+    }
+
+    /* The Toggl columns User,Email,Client,Project,Description,Start date,Start time,
+     * End date,End time,Duration,Tags with the first note as description, the
+     * work time as duration and the branches as tags. The email is the one
+     * user made commits with in this session, if any. */
+    pub fn to_toggl_csv_row(&self, user: &str, project: &str) -> String {
+        let email = self
+            .events
+            .iter()
+            .filter(|ev| ev.author_name.as_deref() == Some(user))
+            .filter_map(|ev| ev.author_email.as_deref())
+            .next()
+            .unwrap_or("");
+        let description = self
+            .events
+            .iter()
+            .filter_map(|ev| match ev.ev_ty {
+                EventType::Commit { .. } => None,
+                _ => ev.note.as_deref(),
+            })
+            .next()
+            .unwrap_or("");
+        let (start_date, start_time) = ts_to_date_and_time(self.start);
+        let (end_date, end_time) = ts_to_date_and_time(self.current_end());
+        let tags: Vec<&str> = self.branches.iter().map(|b| b.as_str()).collect();
+        format!(
+            "{},{},,{},{},{},{},{},{},{},{}",
+            csv_field(user),
+            csv_field(email),
+            csv_field(project),
+            csv_field(description),
+            start_date,
+            start_time,
+            end_date,
+            end_time,
+            sec_to_hh_mm_ss(self.work_time()),
+            csv_field(&tags.join(", "))
+        )
+    }
+
+    /* session,timestamp,date,type,hash,note for each event between from and to,
+     * with the time rounded to round_to minutes */
+    pub fn events_csv_rows(&self, index: usize, from: u64, to: u64, round_to: u64) -> Vec<String> {
+        self.events
+            .iter()
+            .filter(|ev| from <= ev.timestamp && ev.timestamp < to)
+            .map(|ev| {
+                let hash = match ev.ev_ty {
+                    EventType::Commit { ref hash } => hash.as_str(),
+                    _ => "",
+                };
+                format!(
+                    "{},{},{},{},{},{}",
+                    index,
+                    round_ts(ev.timestamp, round_to),
+                    csv_field(&ts_to_date(round_ts(ev.timestamp, round_to))),
+                    ev.ev_ty.name(),
+                    hash,
+                    csv_field(ev.note.as_deref().unwrap_or(""))
+                )
+            })
+            .collect()
+    }
+
     pub fn status(&self) -> String {
-        let mut status = format!(
-            "Session running for {}.\n",
-            sec_to_hms_string(self.pause_time() + self.work_time())
-        );
+        let mut status = if self.is_running() {
+            /* Pauses, the current one included, are no work */
+            let running = format!(
+                "Session running since {}, worked for {}.",
+                ts_to_date(self.start),
+                sec_to_hms_string(self.work_time())
+            );
+            format!("{}\n", paint(&running, Color::Green))
+        } else {
+            format!("Session lasted {}.\n", sec_to_hms_string(self.elapsed()))
+        };
         if self.is_paused() {
+            let since = self.last_activity();
+            let paused = format!(
+                "Paused since {} ({}).",
+                ts_to_date(since),
+                sec_to_hms_string(get_seconds().saturating_sub(since))
+            );
+            status.push_str(&format!("    {}\n", paint(&paused, Color::Yellow)));
+        } else if let Some(resume) = self.planned_resume() {
             status.push_str(&format!(
-                "    Paused since {}.\n",
-                sec_to_hms_string(get_seconds() - self.events[self.events.len() - 1].timestamp)
+                "    On a planned pause, resuming at {} ({} left).\n",
+                ts_to_date(resume),
+                sec_to_hms_string(resume.saturating_sub(get_seconds()))
             ));
         } else {
             match self.events.len() {
-                0 => status.push_str(&String::from("    No events in this session yet!\n")),
-                n => status.push_str(&format!(
-                    "    Last event: {:?}, {} ago.\n",
-                    &self.events[n - 1].ev_ty,
-                    sec_to_hms_string(get_seconds() - self.events[n - 1].timestamp)
+                0 => status.push_str(&format!(
+                    "    No events in this session yet, started on {}.\n",
+                    ts_to_date(self.start)
                 )),
+                n => {
+                    let event = format!("{:?}", &self.events[n - 1].ev_ty);
+                    let event = match self.events[n - 1].ev_ty {
+                        EventType::Commit { .. } => paint(&event, Color::Cyan),
+                        _ => event,
+                    };
+                    status.push_str(&format!(
+                        "    Last event: {}, {} ago.\n",
+                        event,
+                        sec_to_hms_string(
+                            get_seconds().saturating_sub(self.events[n - 1].timestamp)
+                        )
+                    ))
+                }
             }
         }
         let branch_str = match self.branches.len() {
@@ -253,11 +1109,17 @@ impl Session {
                 }),
         };
         status.push_str(&branch_str);
+        if !self.tags.is_empty() {
+            let tags: Vec<&str> = self.tags.iter().map(|tag| tag.as_str()).collect();
+            status.push_str(&format!("    Tags: {}\n", tags.join(", ")));
+        }
         status.push_str(&format!(
             "    Total work time:  {}\n    \
-                                      Total pause time: {}\n",
+                                      Total pause time: {}\n    \
+                                      Events: {}\n",
             sec_to_hms_string(self.work_time()),
-            sec_to_hms_string(self.pause_time())
+            sec_to_hms_string(self.pause_time()),
+            self.event_type_counts_string()
         ));
         status
     }
@@ -265,6 +1127,13 @@ impl Session {
 
 impl HasHTML for Event {
     fn to_html(&self) -> String {
+        self.to_html_as(&HtmlOptions::default())
+    }
+}
+
+impl Event {
+    fn to_html_as(&self, options: &HtmlOptions) -> String {
+        let (user, round_to) = (options.user, options.round_to);
         match self.ev_ty {
             EventType::Pause => match self.note {
                 Some(ref info) => {
@@ -272,15 +1141,15 @@ impl HasHTML for Event {
                         r#"<div class="entry pause">{}: Started a pause
     <p class="mininote wordWrap">{}</p>
 </div>"#,
-                        ts_to_date(self.timestamp),
-                        info.clone()
+                        ts_to_date(round_ts(self.timestamp, round_to)),
+                        note_html(info)
                     )
                 }
                 None => {
                     format!(
                         r#"<div class="entry pause">{}: Started a pause
 </div>"#,
-                        ts_to_date(self.timestamp)
+                        ts_to_date(round_ts(self.timestamp, round_to))
                     )
                 }
             },
@@ -289,7 +1158,7 @@ impl HasHTML for Event {
                     r#"<div class="entry resume">{}: Resumed work
 <hr>
 </div>"#,
-                    ts_to_date(self.timestamp)
+                    ts_to_date(round_ts(self.timestamp, round_to))
                 )
             }
             /* An EventType::Note note is a Some because it's
@@ -303,8 +1172,8 @@ impl HasHTML for Event {
 }
 <hr>
 </div>"#,
-                        ts_to_date(self.timestamp),
-                        text
+                        ts_to_date(round_ts(self.timestamp, round_to)),
+                        note_html(text)
                     )
                 }
                 None => unreachable!(),
@@ -315,40 +1184,106 @@ impl HasHTML for Event {
              */
             EventType::Commit { ref hash } => match self.note {
                 Some(ref text) => {
+                    let stat = match (self.files_changed, self.added, self.removed) {
+                        (Some(files), Some(added), Some(removed)) => format!(
+                            "\n    <p class=\"mininote\">{} files, +{}/-{}</p>",
+                            files, added, removed
+                        ),
+                        _ => String::new(),
+                    };
+                    let branch = match self.branch {
+                        Some(ref branch) => format!("{}: ", html_escape(branch)),
+                        None => String::new(),
+                    };
+                    let author = match self.author_name {
+                        Some(ref name) if Some(name.as_str()) != user => {
+                            let email = self.author_email.as_deref().unwrap_or("");
+                            format!(
+                                "\n    <p class=\"mininote\">by {} &lt;{}&gt;</p>",
+                                html_escape(name),
+                                html_escape(email)
+                            )
+                        }
+                        _ => String::new(),
+                    };
+                    let id = match options.repo_url.and_then(|url| commit_url(url, hash)) {
+                        Some(url) => format!(
+                            r#"<a href="{}">{}</a>"#,
+                            html_escape(&url),
+                            html_escape(hash)
+                        ),
+                        None => html_escape(hash),
+                    };
+                    let (class, label) = if self.milestone {
+                        (" milestone", "Milestone")
+                    } else {
+                        ("", "Commit")
+                    };
                     format!(
-                        r#"<div class="entry commit git_info wordWrap">{}: Commit id: {}
-    <p class="mininote wordWrap">message: {}</p>
+                        r#"<div class="entry commit git_info wordWrap{}">{}: {} id: {}
+    <p class="mininote wordWrap">message: {}{}</p>{}{}
   <hr>
 </div>"#,
-                        ts_to_date(self.timestamp),
-                        hash,
-                        text
+                        class,
+                        ts_to_date(round_ts(self.timestamp, round_to)),
+                        label,
+                        id,
+                        branch,
+                        html_escape(text),
+                        author,
+                        stat
                     )
                 }
                 None => unreachable!(),
             },
+            EventType::Branch { ref name } => {
+                format!(
+                    r#"<div class="entry branch git_info">{}: Switched to branch {}
+<hr>
+</div>"#,
+                    ts_to_date(round_ts(self.timestamp, round_to)),
+                    html_escape(name)
+                )
+            }
         }
     }
 }
 
 impl HasHTML for Session {
     fn to_html(&self) -> String {
+        self.to_html_as(&HtmlOptions::default())
+    }
+}
+
+impl Session {
+    /** Html of the session as options say, see HtmlOptions */
+    pub fn to_html_as(&self, options: &HtmlOptions) -> String {
+        let round_to = options.round_to;
+        /* Raw seconds for scripts, so they don't have to parse the text */
         let mut html = format!(
-            r#"<section class="session">
+            r#"<section class="session" data-start="{}" data-end="{}" data-working="{}" data-paused="{}">
     <h1 class="sessionheader">Session on {}</h1>"#,
-            ts_to_date(self.start)
+            round_ts(self.start, round_to),
+            round_ts(self.current_end(), round_to),
+            self.work_time(),
+            self.pause_time(),
+            ts_to_date(round_ts(self.start, round_to))
         );
 
         for event in &self.events {
-            html.push_str(&event.to_html());
+            html.push_str(&event.to_html_as(options));
         }
 
-        write!(
-            &mut html,
-            r#"<h2 class="sessionfooter">Ended on {}</h2>"#,
-            ts_to_date(self.end)
-        )
-        .unwrap();
+        if self.is_running() {
+            html.push_str(r#"<h2 class="sessionfooter">Still running</h2>"#);
+        } else {
+            write!(
+                &mut html,
+                r#"<h2 class="sessionfooter">Ended on {}</h2>"#,
+                ts_to_date(round_ts(self.end, round_to))
+            )
+            .unwrap();
+        }
 
         let mut branch_str = String::new();
         match self.branches.len() {
@@ -356,21 +1291,46 @@ impl HasHTML for Session {
             n => {
                 write!(&mut branch_str, "Worked on {} branches: ", n).unwrap();
                 for branch in &self.branches {
-                    write!(&mut branch_str, "{} ", branch).unwrap();
+                    write!(&mut branch_str, "{} ", html_escape(branch)).unwrap();
                 }
             }
         };
 
+        let mut tag_str = String::new();
+        if let Some(ref repo) = self.repo {
+            write!(&mut tag_str, "Repository: {} ", html_escape(repo)).unwrap();
+        }
+        if let Some(ref issue) = self.issue {
+            match options.issue_url {
+                Some(url) => write!(
+                    &mut tag_str,
+                    r#"Issue: <a href="{}">{}</a> "#,
+                    html_escape(&url.replace("{}", issue)),
+                    html_escape(issue)
+                ),
+                None => write!(&mut tag_str, "Issue: {} ", html_escape(issue)),
+            }
+            .unwrap();
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<&str> = self.tags.iter().map(|tag| tag.as_str()).collect();
+            write!(&mut tag_str, "Tags: {}", html_escape(&tags.join(", "))).unwrap();
+        }
+
         write!(
             &mut html,
             r#"<section class="summary">
     <p class="git_info">{}</p>
+    <p>{}</p>
     <p>Worked for {}</p>
     <p>Paused for {}</p>
+    <p>{}</p>
 </div></section>"#,
             branch_str,
+            tag_str,
             sec_to_hms_string(self.work_time()),
-            sec_to_hms_string(self.pause_time())
+            sec_to_hms_string(self.pause_time()),
+            self.event_type_counts_string()
         )
         .unwrap();
 
@@ -378,3 +1338,141 @@ impl HasHTML for Session {
         html
     }
 }
+
+/* Columns of the event table: time, event, and details that wrap */
+const TEX_TABLE: &str = r"\begin{longtable}{ll>{\raggedright\arraybackslash}p{9cm}}
+Time & Event & Details \\ \hline
+\endhead
+";
+
+impl HasTEX for Event {
+    /* A row of the session's event table */
+    fn to_tex(&self) -> String {
+        let (label, details) = match self.ev_ty {
+            EventType::Pause => (
+                "Pause",
+                self.note.as_deref().map_or(String::new(), note_tex),
+            ),
+            EventType::Resume => ("Resume", String::new()),
+            EventType::Branch { ref name } => ("Branch", tex_escape(name)),
+            EventType::Note => ("Note", self.note.as_deref().map_or(String::new(), note_tex)),
+            EventType::Commit { ref hash } => {
+                let mut details = format!("\\texttt{{{}}}", tex_escape(short_hash(hash, 7)));
+                if let Some(ref branch) = self.branch {
+                    write!(&mut details, " on {}", tex_escape(branch)).unwrap();
+                }
+                if let Some(ref text) = self.note {
+                    write!(&mut details, ": {}", note_tex(text)).unwrap();
+                }
+                if let Some(ref name) = self.author_name {
+                    write!(&mut details, " (by {})", tex_escape(name)).unwrap();
+                }
+                let label = if self.milestone {
+                    "Milestone"
+                } else {
+                    "Commit"
+                };
+                (label, details)
+            }
+        };
+        format!(
+            "{} & {} & {} \\\\\n",
+            ts_to_date(self.timestamp),
+            label,
+            details
+        )
+    }
+}
+
+impl HasTEX for Session {
+    /** A subsection with the events as a table and the durations below */
+    fn to_tex(&self) -> String {
+        let mut tex = format!("\\subsection*{{Session on {}}}\n", ts_to_date(self.start));
+        if self.events.is_empty() {
+            tex.push_str("No events.\n\n");
+        } else {
+            tex.push_str(TEX_TABLE);
+            for event in &self.events {
+                tex.push_str(&event.to_tex());
+            }
+            tex.push_str("\\end{longtable}\n");
+        }
+        if self.is_running() {
+            tex.push_str("Still running. ");
+        } else {
+            write!(&mut tex, "Ended on {}. ", ts_to_date(self.end)).unwrap();
+        }
+        writeln!(
+            &mut tex,
+            "Worked for {}, paused for {}.",
+            sec_to_hms_string(self.work_time()),
+            sec_to_hms_string(self.pause_time())
+        )
+        .unwrap();
+        tex
+    }
+}
+
+impl HasMarkdown for Event {
+    /* An item of the session's event list */
+    fn to_markdown(&self) -> String {
+        let details = match self.ev_ty {
+            EventType::Pause => match self.note {
+                Some(ref note) => format!("Pause: {}", note_md(note)),
+                None => String::from("Pause"),
+            },
+            EventType::Resume => String::from("Resume"),
+            EventType::Branch { ref name } => format!("Branch {}", markdown_code(name)),
+            EventType::Note => format!(
+                "Note: {}",
+                self.note.as_deref().map_or(String::new(), note_md)
+            ),
+            EventType::Commit { ref hash } => {
+                let label = if self.milestone {
+                    "Milestone"
+                } else {
+                    "Commit"
+                };
+                let mut details = format!("{} {}", label, markdown_code(short_hash(hash, 7)));
+                if let Some(ref branch) = self.branch {
+                    write!(&mut details, " on {}", markdown_code(branch)).unwrap();
+                }
+                if let Some(ref text) = self.note {
+                    write!(&mut details, ": {}", note_md(text.trim_end())).unwrap();
+                }
+                if let Some(ref name) = self.author_name {
+                    write!(&mut details, " (by {})", markdown_escape(name)).unwrap();
+                }
+                details
+            }
+        };
+        format!("- {}: {}\n", ts_to_date(self.timestamp), details)
+    }
+}
+
+impl HasMarkdown for Session {
+    /** A heading, the events as a list and the durations below */
+    fn to_markdown(&self) -> String {
+        let mut md = format!("## Session on {}\n\n", ts_to_date(self.start));
+        if self.events.is_empty() {
+            md.push_str("No events.\n");
+        }
+        for event in &self.events {
+            md.push_str(&event.to_markdown());
+        }
+        let ended = if self.is_running() {
+            String::from("Still running")
+        } else {
+            format!("Ended on {}", ts_to_date(self.end))
+        };
+        write!(
+            &mut md,
+            "\n```\n{}\nWorked: {}\nPaused: {}\n```\n\n",
+            ended,
+            sec_to_hms_string(self.work_time()),
+            sec_to_hms_string(self.pause_time())
+        )
+        .unwrap();
+        md
+    }
+}
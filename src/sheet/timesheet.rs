@@ -1,39 +1,360 @@
-use std::fs::{self, OpenOptions};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io;
 use std::io::prelude::*;
-use std::path::Path;
-use std::{env, process};
+use std::path::{Path, PathBuf};
 /* Alias to avoid naming conflict for write_all!() */
+use std::fmt;
 use std::fmt::Write as std_write;
 
-use url::Url;
-use url_open::UrlOpen;
+use serde_json::{from_str, to_string, to_string_pretty};
 
-use serde_json::{from_str, to_string};
-
-use config::Config;
-use sheet::traits::HasHTML;
+use config::{Attribution, Config};
 use util::*;
 
 use sheet::session::EventType;
-use sheet::session::Session;
+use sheet::session::{Event, HtmlOptions, Session, SessionSummary};
+use sheet::traits::{HasMarkdown, HasTEX};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Timesheet {
     start: u64,
     end: u64,
     config: Config,
     sessions: Vec<Session>,
+    /* Directory containing .trk and the reports, found when loading */
+    #[serde(skip_serializing, skip_deserializing)]
+    base_dir: PathBuf,
+    /* Repository new sessions are tagged with, only set for the global timesheet */
+    #[serde(skip_serializing, skip_deserializing)]
+    session_repo: Option<String>,
+    /* Name of the sheet among those in .trk, None for the default one */
+    #[serde(skip_serializing, skip_deserializing)]
+    project: Option<String>,
+    /* Reports are written but not opened in a browser, e.g. on CI or over ssh */
+    #[serde(skip_serializing, skip_deserializing)]
+    no_open: bool,
+    /* Totals of the finished sessions as of the last write, see CachedStats */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cached_stats: Option<CachedStats>,
+    /* Commits still pending once the sheet has taken the others, only written
+     * to the queue together with the sheet, see write_files */
+    #[serde(skip_serializing, skip_deserializing)]
+    pending_left: Option<Vec<(u64, String)>>,
+}
+
+/** Totals of the finished sessions, stored with the sheet and refreshed on
+ * every write so that status doesn't have to walk all events. The running
+ * session changes by the second and is always added up on the spot. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedStats {
+    sessions: usize,
+    commits: usize,
+    work_time: u64,
+    pause_time: u64,
+    /* When the totals were computed */
+    updated: u64,
+}
+
+impl CachedStats {
+    fn same_totals(&self, other: &CachedStats) -> bool {
+        self.sessions == other.sessions
+            && self.commits == other.commits
+            && self.work_time == other.work_time
+            && self.pause_time == other.pause_time
+    }
+}
+
+/* Stylesheet of a built-in theme, see Config::theme */
+fn theme_css(name: &str) -> Option<&'static str> {
+    match name {
+        "dark" => Some(include_str!("../../style.css")),
+        "light" => Some(include_str!("../../themes/light.css")),
+        "print" => Some(include_str!("../../themes/print.css")),
+        _ => None,
+    }
+}
+
+/** Why no timesheet could be loaded */
+#[derive(Debug)]
+pub enum LoadError {
+    /* No .trk directory or no timesheet.json in it, i.e. not initialized */
+    Absent,
+    Unreadable(io::Error),
+    /* timesheet.json is there but isn't a timesheet */
+    Corrupt(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Absent => write!(f, "No timesheet file! You might have to init first."),
+            LoadError::Unreadable(ref e) => write!(f, "Could not read timesheet.json: {}", e),
+            LoadError::Corrupt(ref e) => write!(f, "timesheet.json is corrupt: {}", e),
+        }
+    }
+}
+
+/** Why a command didn't change the timesheet */
+#[derive(Debug)]
+pub enum TrkError {
+    /* Not after the last event or session */
+    InvalidTimestamp,
+    NotInitialized,
+    AlreadyInitialized,
+    Io(io::Error),
+    GitFailure(String),
+    AlreadyRunning,
+    /* What there was no session to do, e.g. "pause" */
+    NoSession(&'static str),
+    /* The session refused the event, says why */
+    Rejected(String),
+    /* Report file that could not be written */
+    Report(String, io::Error),
+}
+
+impl fmt::Display for TrkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrkError::InvalidTimestamp => write!(f, "That timestamp is invalid."),
+            TrkError::NotInitialized => {
+                write!(f, "No timesheet file! You might have to init first.")
+            }
+            TrkError::AlreadyInitialized => write!(f, "Timesheet is already initialized!"),
+            TrkError::Io(ref e) => write!(f, "Could not write timesheet.json: {}", e),
+            TrkError::GitFailure(ref e) => write!(f, "{}", e),
+            TrkError::AlreadyRunning => write!(f, "Last session is still running."),
+            TrkError::NoSession(action) => write!(f, "No session to {}.", action),
+            TrkError::Rejected(ref e) => write!(f, "{}", e),
+            TrkError::Report(ref file, ref e) => write!(f, "Could not write {}: {}", file, e),
+        }
+    }
+}
+
+impl From<String> for TrkError {
+    fn from(reason: String) -> TrkError {
+        TrkError::Rejected(reason)
+    }
+}
+
+impl From<io::Error> for TrkError {
+    fn from(e: io::Error) -> TrkError {
+        TrkError::Io(e)
+    }
+}
+
+/* Ends the line install_git_hook adds to the post-commit hook */
+const HOOK_MARKER: &str = "# added by trk install_git_hook";
+
+/* One interval of `timew export`, see Timesheet::import_timewarrior */
+#[derive(Deserialize, Debug)]
+struct TimewInterval {
+    start: String,
+    /* None while the interval is still open */
+    end: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    annotation: Option<String>,
+}
+
+/** Totals of all sessions started within one week, month or year */
+#[derive(Serialize, Debug)]
+pub struct PeriodSummary {
+    period: String,
+    sessions: usize,
+    work_time: u64,
+    commits: usize,
+    top_branch: Option<String>,
+}
+
+/** Work time of one branch, see Timesheet::working_time_by_branch */
+#[derive(Serialize, Debug)]
+pub struct BranchTime {
+    branch: String,
+    working_secs: u64,
+    sessions: usize,
+    /* The author_attribution setting the work time was shared by */
+    attribution: &'static str,
+}
+
+/* work_time shared in proportion to counts. Rounding leftovers go to the
+ * first key, so the shares add up to work_time. counts must not be empty. */
+fn shares<'a>(work_time: u64, counts: &BTreeMap<&'a str, u64>) -> Vec<(&'a str, u64)> {
+    let total: u64 = counts.values().sum();
+    let mut shares: Vec<(&str, u64)> = counts
+        .iter()
+        .map(|(&key, &count)| (key, work_time * count / total))
+        .collect();
+    let leftover = work_time - shares.iter().map(|&(_, share)| share).sum::<u64>();
+    shares[0].1 += leftover;
+    shares
+}
+
+/* start to end cut at each local midnight in between, so that every piece
+ * lies within one local day */
+fn split_at_midnights(start: u64, end: u64) -> Vec<(u64, u64)> {
+    let mut pieces = Vec::new();
+    let mut from = start;
+    while from < end {
+        /* Always after from, see next_local_midnight */
+        let to = cmp::min(end, next_local_midnight(from));
+        pieces.push((from, to));
+        from = to;
+    }
+    pieces
+}
+
+/* What seconds of work come to at rate cents per hour, half a cent and up
+ * being a cent */
+fn billed_cents(seconds: u64, rate: u64) -> u64 {
+    (seconds * rate + 1800) / 3600
+}
+
+/* See Timesheet::remaining_string. Rounds what is left up to whole minutes,
+ * so it only says reached once it is. */
+fn remaining_text(worked: u64, target: u64) -> String {
+    let fmt = DurationFmt {
+        smallest: TimeUnit::Minutes,
+        compact: true,
+        ..DurationFmt::default()
+    };
+    if worked < target {
+        let left = (target - worked + 59) / 60 * 60;
+        format!("{} remaining", format_duration(left, fmt))
+    } else {
+        format!("goal reached (+{})", format_duration(worked - target, fmt))
+    }
+}
+
+/** Status of the sheet and its last session for other programs to read */
+#[derive(Serialize, Debug)]
+pub struct StatusReport {
+    sessions: usize,
+    running: bool,
+    paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_event: Option<&'static str>,
+    /* Commits waiting for a session, see Timesheet::queue_commit */
+    pending_commits: usize,
+    session_work_time: u64,
+    session_pause_time: u64,
+    work_time: u64,
+    pause_time: u64,
+    /* Of the last session, sorted by name */
+    branches: Vec<String>,
+}
+
+/** The sheet for other tools: some stored fields plus the totals and
+ * per-session durations that aren't stored, so they needn't redo the
+ * pause-aware arithmetic. timesheet.json keeps its own schema. */
+#[derive(Serialize, Debug)]
+pub struct SheetExport<'a> {
+    /* Stored */
+    start: u64,
+    end: u64,
+    user_name: Option<&'a String>,
+    repository: Option<&'a String>,
+    /* Computed */
+    session_count: usize,
+    commit_count: usize,
+    work_time: u64,
+    pause_time: u64,
+    days_worked: usize,
+    sessions: Vec<SessionSummary<'a>>,
+}
+
+/** Work time of the current period so far and of the same stretch of the previous one */
+#[derive(Serialize, Debug)]
+pub struct PeriodComparison {
+    period: &'static str,
+    start: u64,
+    previous_start: u64,
+    current: u64,
+    previous: u64,
+}
+
+impl PeriodComparison {
+    /* Change in hours and, if there was work before, in percent */
+    fn delta(&self) -> (f64, Option<f64>) {
+        let delta = self.current as f64 - self.previous as f64;
+        let percent = if self.previous > 0 {
+            Some(delta * 100.0 / self.previous as f64)
+        } else {
+            None
+        };
+        (delta / 3600.0, percent)
+    }
+
+    fn delta_string(&self) -> String {
+        match self.delta() {
+            (hours, Some(percent)) => format!("{:+.1} hours ({:+.0}%)", hours, percent),
+            (hours, None) => format!("{:+.1} hours", hours),
+        }
+    }
+}
+
+/** Overall numbers of the sheet */
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    sessions: usize,
+    work_time: u64,
+    /* Days with any work among the configured working_days */
+    working_days: usize,
+    /* Days with any work at all, see working_day_count */
+    days_worked: usize,
+    commits: usize,
+    /* Averages over those days, None without such days */
+    work_per_working_day: Option<u64>,
+    commits_per_working_day: Option<f64>,
+    milestones: usize,
+    /* Commits per author, for those recorded with one */
+    commits_by_author: BTreeMap<String, usize>,
+    /* Work time per author, see time_by_author */
+    time_by_author: BTreeMap<String, u64>,
+}
+
+/** Today so far, for dashboards polling trk */
+#[derive(Serialize, Debug)]
+pub struct TodayReport {
+    date: String,
+    /* "Today" is the local day at this offset */
+    utc_offset: String,
+    work_time: u64,
+    pause_time: u64,
+    /* Time since midnight outside of any session */
+    idle_time: u64,
+    sessions: usize,
+    running: bool,
+    paused: bool,
+}
+
+/** Index and work time of the longest and shortest sessions */
+#[derive(Debug, PartialEq)]
+pub struct Extremes {
+    pub longest: Option<(usize, u64)>,
+    pub shortest: Option<(usize, u64)>,
 }
 
 impl Timesheet {
-    /** Initializes the .trk/timesheet.json file which holds
-     * the serialized timesheet
-     * Returns Some(newTimesheet) if operation succeeded */
-    pub fn init(author_name: Option<&str>) -> Option<Timesheet> {
+    /** Initializes the base_dir/.trk/timesheet.json file which holds
+     * the serialized timesheet. The .trk git repository is left to
+     * the caller, see git_init_trk. */
+    pub fn init(base_dir: &Path, author_name: Option<&str>) -> Result<Timesheet, TrkError> {
+        Timesheet::init_project(base_dir, author_name, None)
+    }
+
+    /** Like init, for project's own timesheet-<project>.json */
+    pub fn init_project(
+        base_dir: &Path,
+        author_name: Option<&str>,
+        project: Option<&str>,
+    ) -> Result<Timesheet, TrkError> {
         /* Check if file already exists (no init permitted) */
-        if Timesheet::is_init() {
-            println!("Timesheet is already initialized!");
-            return None;
+        if Timesheet::is_init(base_dir, project) {
+            return Err(TrkError::AlreadyInitialized);
         }
         /* File does not exist, initialize */
         let git_author_name = git_author();
@@ -42,250 +363,568 @@ impl Timesheet {
             None => match git_author_name {
                 Some(ref git_name) => git_name,
                 None => {
-                    println!(
-                        "Empty name not permitted. \
-                                  Please run with 'trk init <name>'"
-                    );
-                    process::exit(0);
+                    return Err(TrkError::Rejected(String::from(
+                        "Empty name not permitted. Please run with 'trk init <name>'",
+                    )));
                 }
             },
         };
         let mut config = Config::new();
         config.user_name = Some(author_name.to_string());
         let now = get_seconds();
-        let sheet = Timesheet {
+        let mut sheet = Timesheet {
             start: now,
             end: now + 1,
             config,
             sessions: Vec::<Session>::new(),
+            base_dir: base_dir.to_path_buf(),
+            session_repo: None,
+            project: project.map(|name| name.to_string()),
+            no_open: false,
+            cached_stats: None,
+            pending_left: None,
         };
-        if sheet.write_files() {
-            git_init_trk();
-            Some(sheet)
-        } else {
-            None
+        sheet.write_files()?;
+        Ok(sheet)
+    }
+
+    fn is_init(base_dir: &Path, project: Option<&str>) -> bool {
+        Timesheet::load_project(base_dir, project).is_ok()
+    }
+
+    /* e.g. timesheet.json, or timesheet-acme.json for project acme */
+    fn project_file(name: &str, project: Option<&str>, extension: &str) -> String {
+        match project {
+            Some(project) => format!("{}-{}.{}", name, project, extension),
+            None => format!("{}.{}", name, extension),
         }
     }
 
-    fn is_init() -> bool {
-        Path::new("./.trk/timesheet.json").exists() && Timesheet::load_from_file().is_some()
+    fn json_path(base_dir: &Path, project: Option<&str>) -> PathBuf {
+        base_dir
+            .join(".trk")
+            .join(Timesheet::project_file("timesheet", project, "json"))
+    }
+
+    /* File name of a report in base_dir, e.g. session.html, with the
+     * project in it so that the reports of projects don't overwrite each other */
+    fn report_file(&self, name: &str) -> String {
+        Timesheet::project_file(name, self.project.as_deref(), "html")
     }
 
-    pub fn new_session(&mut self, timestamp: Option<u64>) -> bool {
-        let possible = self.sessions.last_mut().map_or(true, |session| {
-            if session.is_running() {
-                println!("Last session is still running.");
+    /** Projects with a timesheet in base_dir/.trk, sorted,
+     * "default" for timesheet.json */
+    pub fn list_projects(base_dir: &Path) -> Vec<String> {
+        let entries = match fs::read_dir(base_dir.join(".trk")) {
+            Ok(entries) => entries,
+            Err(..) => return Vec::new(),
+        };
+        let mut projects: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter_map(|file| match file.as_str() {
+                "timesheet.json" => Some(String::from("default")),
+                _ => file
+                    .strip_prefix("timesheet-")
+                    .and_then(|rest| rest.strip_suffix(".json"))
+                    .map(|project| project.to_string()),
+            })
+            .collect();
+        projects.sort();
+        projects
+    }
+
+    fn trk_dir(&self) -> PathBuf {
+        self.base_dir.join(".trk")
+    }
+
+    /** Whether the last session is still running, false without sessions */
+    pub fn is_running(&self) -> bool {
+        self.sessions
+            .last()
+            .map_or(false, |session| session.is_running())
+    }
+
+    pub fn new_session(&mut self, timestamp: Option<u64>) -> Result<(), TrkError> {
+        if self.is_running() {
+            return Err(TrkError::AlreadyRunning);
+        }
+        match timestamp {
+            Some(timestamp) => {
+                let is_valid_ts = match self.sessions.last() {
+                    None => timestamp > self.start,
+                    Some(last_session) => timestamp > last_session.end,
+                };
+                if !is_valid_ts {
+                    return Err(TrkError::InvalidTimestamp);
+                }
+                self.sessions.push(Session::new(Some(timestamp)));
             }
-            !session.is_running()
-        });
-        if possible {
-            match timestamp {
-                Some(timestamp) => {
-                    let is_valid_ts = match self.sessions.last() {
-                        None => timestamp > self.start,
-                        Some(last_session) => timestamp > last_session.end,
-                    };
-                    if is_valid_ts {
-                        self.sessions.push(Session::new(Some(timestamp)));
-                    } else {
-                        println!("That timestamp is invalid.");
-                        process::exit(0);
-                    }
+            None => {
+                self.sessions.push(Session::new(None));
+            }
+        };
+        let repo = self.session_repo.clone();
+        self.sessions.last_mut().unwrap().set_repo(repo);
+        self.attach_recent_pending();
+        /* Now, after the pending commits, which keep the times they were made */
+        if timestamp.is_none() {
+            self.sessions.last_mut().unwrap().switch_to_checked_out();
+        }
+        /* After the pending commits, which may move the start back */
+        let (tags, note) = (
+            self.config.default_tags.clone(),
+            self.config.default_note.clone(),
+        );
+        self.set_session_defaults(Some(&tags), Some(note));
+        Ok(())
+    }
+
+    /** Replaces the tags and/or the start note of the last session, e.g. the
+     * defaults new_session gave it. None leaves them as they are. */
+    pub fn set_session_defaults(&mut self, tags: Option<&[String]>, note: Option<Option<String>>) {
+        if let Some(session) = self.sessions.last_mut() {
+            if let Some(tags) = tags {
+                session.set_tags(tags);
+            }
+            if let Some(note) = note {
+                session.set_start_note(note);
+            }
+        }
+    }
+
+    /* Each project has its own queue, e.g. pending_commits-acme for acme */
+    fn pending_path(&self) -> PathBuf {
+        let name = match self.project {
+            Some(ref project) => format!("pending_commits-{}", project),
+            None => String::from("pending_commits"),
+        };
+        self.trk_dir().join(name)
+    }
+
+    /** Commits that came without a running session and weren't recorded,
+     * as (timestamp, hash), oldest first */
+    pub fn pending_commits(&self) -> Vec<(u64, String)> {
+        let content = fs::read_to_string(self.pending_path()).unwrap_or_default();
+        content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next().map(|ts| ts.parse::<u64>()), fields.next()) {
+                    (Some(Ok(timestamp)), Some(hash)) => Some((timestamp, hash.to_string())),
+                    _ => None,
                 }
-                None => {
-                    self.sessions.push(Session::new(None));
+            })
+            .collect()
+    }
+
+    /* One "timestamp hash" line per commit, no file without commits */
+    fn write_pending(&self, pending: &[(u64, String)]) -> io::Result<()> {
+        if pending.is_empty() {
+            return match fs::remove_file(self.pending_path()) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+        let mut content = String::new();
+        for &(timestamp, ref hash) in pending {
+            writeln!(&mut content, "{} {}", timestamp, hash).unwrap();
+        }
+        fs::write(self.pending_path(), content)
+    }
+
+    /** Keeps a commit made without a running session in .trk/pending_commits,
+     * for the next session or 'trk flush_commits' */
+    pub fn queue_commit(&self, hash: String) -> io::Result<()> {
+        let mut pending = self.pending_commits();
+        pending.push((get_seconds(), hash));
+        self.write_pending(&pending)
+    }
+
+    /* Moves the pending commits made within pending_commit_window before the
+     * session just begun into it, starting it at the first of them */
+    fn attach_recent_pending(&mut self) {
+        let pending = self.pending_commits();
+        if pending.is_empty() {
+            return;
+        }
+        let window = self.config.pending_commit_window * 60;
+        let previous_end = match self.sessions.len() {
+            n if n > 1 => self.sessions[n - 2].end,
+            _ => self.start,
+        };
+        let start = self.sessions.last().unwrap().start;
+        let (recent, old): (Vec<(u64, String)>, Vec<(u64, String)>) = pending
+            .into_iter()
+            .partition(|&(timestamp, _)| timestamp >= previous_end && timestamp + window >= start);
+        if recent.is_empty() {
+            return;
+        }
+        let first = recent[0].0;
+        {
+            let session = self.sessions.last_mut().unwrap();
+            session.start = cmp::min(session.start, cmp::max(first - 1, previous_end));
+        }
+        let mut attached = 0;
+        for (timestamp, hash) in recent {
+            let timestamp = cmp::max(timestamp, self.sessions.last().unwrap().last_activity() + 1);
+            if self.record_commit(hash, Some(timestamp)).is_ok() {
+                attached += 1;
+            }
+        }
+        println!("Added {} pending commit(s) to the session.", attached);
+        self.pending_left = Some(old);
+    }
+
+    /** Adds the pending commits to the last session, or discards them.
+     * A session that has ended is extended to the last of them. Those the
+     * session refuses stay pending. The added ones leave the queue when the
+     * sheet is written. Returns how many were added or discarded. */
+    pub fn flush_commits(&mut self, discard: bool) -> Result<usize, String> {
+        let pending = self.pending_commits();
+        if discard {
+            self.pending_left = None;
+            self.write_pending(&[]).map_err(|e| {
+                format!("Could not update {}: {}", self.pending_path().display(), e)
+            })?;
+            return Ok(pending.len());
+        }
+        let mut kept = Vec::new();
+        if !pending.is_empty() {
+            let (reopened, end) = match self.sessions.last_mut() {
+                Some(session) => {
+                    let reopened = !session.is_running();
+                    session.reopen();
+                    (reopened, session.end)
                 }
+                None => return Err(String::from("No session to add the commits to.")),
             };
+            for (timestamp, hash) in pending.iter().cloned() {
+                if let Err(e) = self.record_commit(hash.clone(), Some(timestamp)) {
+                    println!("Commit {}: {}", hash, e);
+                    kept.push((timestamp, hash));
+                }
+            }
+            if reopened {
+                let session = self.sessions.last_mut().unwrap();
+                let last = session.last_activity();
+                /* Can't fail, the timestamp is after the last event */
+                let _ = session.finalize(Some(cmp::max(end - 1, last + 1)));
+                self.end = cmp::max(self.end, session.end + 1);
+                self.cached_stats = None;
+            }
         }
-        possible
+        let added = pending.len() - kept.len();
+        self.pending_left = Some(kept);
+        Ok(added)
+    }
+
+    /** Tags sessions begun from now on with repo, for the global timesheet */
+    pub fn set_session_repo(&mut self, repo: Option<String>) {
+        self.session_repo = repo;
     }
 
-    pub fn end_session(&mut self, timestamp: Option<u64>) {
+    /** A copy holding only the sessions tagged with repo */
+    pub fn for_repo(&self, repo: &str) -> Timesheet {
+        let mut sheet = self.clone();
+        sheet
+            .sessions
+            .retain(|session| session.repo() == Some(repo));
+        sheet.cached_stats = None;
+        sheet
+    }
+
+    pub fn end_session(&mut self, timestamp: Option<u64>) -> Result<(), TrkError> {
         match self.sessions.last_mut() {
             Some(session) => {
                 session.update_end();
-                session.finalize(timestamp);
+                session
+                    .finalize(timestamp)
+                    .map_err(|_| TrkError::InvalidTimestamp)?;
                 self.end = session.end + 1;
+                self.cached_stats = None;
+                Ok(())
             }
-            None => println!("No session to finalize."),
+            None => Err(TrkError::NoSession("finalize")),
         }
     }
 
-    pub fn pause(&mut self, timestamp: Option<u64>, note: Option<String>) {
+    /** Err says why nothing changed */
+    pub fn pause(&mut self, timestamp: Option<u64>, note: Option<String>) -> Result<(), TrkError> {
         match self.sessions.last_mut() {
-            Some(session) => {
-                session.push_event(timestamp, note, EventType::Pause);
-            }
-            None => println!("No session to pause."),
+            Some(session) => Ok(session.push_event(timestamp, note, EventType::Pause)?),
+            None => Err(TrkError::NoSession("pause")),
         }
     }
 
-    pub fn resume(&mut self, timestamp: Option<u64>) {
+    /** Pauses the running session if there was no activity for idle_limit seconds,
+     * backdated to when it stopped. Activity is keyboard and mouse input if the
+     * system can tell (idle feature), otherwise the last event of the session.
+     * Returns whether the session was paused. */
+    pub fn checkpoint(&mut self, idle_limit: u64) -> bool {
+        let now = get_seconds();
         match self.sessions.last_mut() {
-            Some(session) => {
-                session.push_event(timestamp, None, EventType::Resume);
+            Some(session) if session.is_running() && !session.is_paused() => {
+                let last_activity = session.last_activity();
+                let idle_since = match system_idle_seconds() {
+                    Some(idle) => now.saturating_sub(idle),
+                    None => last_activity,
+                };
+                if now.saturating_sub(idle_since) < idle_limit {
+                    return false;
+                }
+                let timestamp = cmp::max(idle_since, last_activity + 1);
+                session
+                    .push_event(
+                        Some(timestamp),
+                        Some(String::from("auto (idle)")),
+                        EventType::Pause,
+                    )
+                    .is_ok()
             }
-            None => println!("No session to pause."),
+            _ => false,
         }
     }
 
-    pub fn note(&mut self, timestamp: Option<u64>, note_text: String) {
+    /** Removes the last event of the running session and returns it.
+     * Events of sessions that have ended stay. */
+    pub fn undo_last_event(&mut self) -> Result<Event, TrkError> {
         match self.sessions.last_mut() {
             Some(session) => {
-                session.push_event(timestamp, Some(note_text), EventType::Note);
+                let event = session.pop_event()?;
+                self.cached_stats = None;
+                Ok(event)
             }
-            None => println!("No session to add note to."),
+            None => Err(TrkError::NoSession("undo an event of")),
         }
     }
 
-    pub fn add_commit(&mut self, hash: String) {
-        let new_needed = self
-            .sessions
-            .last()
-            .map_or(true, |session| !session.is_running());
-        if new_needed {
-            self.new_session(None);
+    /** Err says why nothing changed */
+    pub fn resume(&mut self, timestamp: Option<u64>) -> Result<(), TrkError> {
+        match self.sessions.last_mut() {
+            Some(session) => Ok(session.push_event(timestamp, None, EventType::Resume)?),
+            None => Err(TrkError::NoSession("resume")),
+        }
+    }
+
+    /** Err says why the note wasn't added */
+    pub fn note(&mut self, timestamp: Option<u64>, note_text: String) -> Result<(), TrkError> {
+        if self.config.auto_start_on_note && !self.is_running() {
+            /* The note has to come after the start of its session */
+            self.new_session(timestamp.map(|ts| ts - 1))?;
+        }
+        match self.sessions.last_mut() {
+            Some(session) => Ok(session.push_event(timestamp, Some(note_text), EventType::Note)?),
+            None => Err(TrkError::NoSession("add note to")),
+        }
+    }
+
+    /** Err says why the commit wasn't added. Without a running session, one
+     * is begun for it unless auto_session_on_commit is off, in which case
+     * the commit is queued (see queue_commit). */
+    pub fn add_commit(&mut self, hash: String) -> Result<(), String> {
+        if !self.is_running() {
+            if !self.config.auto_session_on_commit {
+                return match self.queue_commit(hash.clone()) {
+                    Ok(()) => Err(format!(
+                        "No running session, commit {} waits for the next one \
+                         ('trk flush_commits' adds it to the last session).",
+                        hash
+                    )),
+                    Err(e) => Err(format!("Could not queue commit {}: {}", hash, e)),
+                };
+            }
+            self.new_session(None).map_err(|e| e.to_string())?;
         }
+        self.record_commit(hash, None)
+    }
+
+    /* Adds the commit to the last session with what git knows about it */
+    fn record_commit(&mut self, hash: String, timestamp: Option<u64>) -> Result<(), String> {
         match self.sessions.last_mut() {
             Some(session) => {
                 let message = git_commit_message(&hash).unwrap_or_default();
-                session.push_event(None, Some(message), EventType::Commit { hash });
+                let stat = if self.config.commit_stats {
+                    git_commit_stat(&hash)
+                } else {
+                    None
+                };
+                /* The current branch is only that of new commits */
+                let branch = if self.config.commit_branch_prefix && timestamp.is_none() {
+                    /* A detached HEAD is the commit itself, no branch */
+                    git_current_branch().filter(|branch| !hash.starts_with(branch.as_str()))
+                } else {
+                    None
+                };
+                let author = git_commit_author(&hash);
+                /* Commits tracked late go where they were made, if they fit there */
+                let committed = match timestamp {
+                    None => git_commit_time(&hash),
+                    Some(..) => None,
+                };
+                session.push_event(timestamp, Some(message), EventType::Commit { hash })?;
+                if let Some(stat) = stat {
+                    session.set_commit_stat(stat);
+                }
+                if let Some(branch) = branch {
+                    session.set_commit_branch(branch);
+                }
+                if let Some((name, email)) = author {
+                    session.set_commit_author(name, email);
+                }
+                if let Some(committed) = committed {
+                    /* Otherwise it stays at now */
+                    let _ = session.backdate_last_event(committed);
+                }
+                Ok(())
             }
-            None => println!("No session to add commit to."),
+            None => Err(String::from("No session to add commit to.")),
         }
     }
 
-    pub fn add_branch(&mut self, name: String) {
-        if let Some(session) = self.sessions.last_mut() {
-            session.add_branch(name)
+    /** Links the last session to issue, or unlinks it if issue is None */
+    pub fn set_issue(&mut self, issue: Option<String>) -> Result<(), String> {
+        match self.sessions.last_mut() {
+            Some(session) => {
+                session.set_issue(issue);
+                Ok(())
+            }
+            None => Err(String::from("No session to link to an issue.")),
+        }
+    }
+
+    /** Marks the latest commit whose hash starts with hash as a milestone */
+    pub fn mark(&mut self, hash: &str) -> bool {
+        if hash.is_empty() {
+            println!("Which commit?");
+            return false;
+        }
+        for session in self.sessions.iter_mut().rev() {
+            if let Some(full_hash) = session.mark_commit(hash) {
+                println!("Marked {} as a milestone.", full_hash);
+                return true;
+            }
+        }
+        println!("No commit {} in the timesheet.", hash);
+        false
+    }
+
+    /** One line per milestone: date, short hash and message */
+    pub fn milestones_string(&self) -> String {
+        let mut list = String::new();
+        for session in &self.sessions {
+            for (timestamp, hash, message) in session.milestones() {
+                writeln!(
+                    &mut list,
+                    "{}  {}  {}",
+                    ts_to_date(timestamp),
+                    short_hash(hash, 8),
+                    message.unwrap_or("")
+                )
+                .unwrap();
+            }
+        }
+        if list.is_empty() {
+            list.push_str("No milestones yet. Mark a commit with 'trk mark <hash>'.\n");
+        }
+        list
+    }
+
+    /** Records the switch to branch name in the running session, if any */
+    pub fn add_branch(&mut self, name: String) -> Result<(), TrkError> {
+        match self.sessions.last_mut() {
+            Some(session) if session.is_running() => Ok(session.switch_branch(None, name)?),
+            _ => Ok(()),
         }
     }
 
-    fn write_to_html(&self, ago: Option<u64>) -> bool {
+    fn write_to_html(
+        &self,
+        file: &str,
+        from: Option<u64>,
+        to: Option<u64>,
+        inline_css: bool,
+    ) -> io::Result<()> {
         // TODO: avoid time-of-check-to-time-of-use race risk
-        let path = Path::new("./timesheet.html");
-        let file = OpenOptions::new()
+        let path = self.base_dir.join(file);
+        let mut file = OpenOptions::new()
             .write(true)
             .truncate(true)
             .create(true)
-            .open(&path);
-
-        match file {
-            Ok(mut file) => {
-                file.write_all(self.to_html(ago).as_bytes()).unwrap();
-                format_file("timesheet.html");
-                /* Save was successful */
-                true
-            }
-            Err(e) => {
-                println!("Could not report sheet! {}", e);
-                false
-            }
-        }
+            .open(&path)?;
+        let html = if inline_css {
+            self.to_html_standalone(from, to)
+        } else {
+            self.to_html(from, to)
+        };
+        file.write_all(indent_html(&html).as_bytes())?;
+        Ok(())
     }
 
-    fn write_last_session_html(&self) -> bool {
+    fn write_last_session_html(&self) -> io::Result<()> {
         let session = match self.sessions.last() {
             Some(session) => session,
-            None => return true,
+            None => return Ok(()),
         };
-        let path = Path::new("./session.html");
-        let file = OpenOptions::new()
+        let path = self.base_dir.join(self.report_file("session"));
+        let mut file = OpenOptions::new()
             .write(true)
             .truncate(true)
             .create(true)
-            .open(&path);
-
-        let mut file = match file {
-            Ok(file) => file,
-            Err(e) => {
-                eprintln!("Could not write report to session.html! {}", e);
-                return false;
-            }
-        };
-
-        let stylesheets = if self.config.show_commits {
-            r#"<link rel="stylesheet" type="text/css" href=".trk/style.css">
-"#
-        } else {
-            r#"<link rel="stylesheet" type="text/css" href=".trk/style.css">
-<link rel="stylesheet" type="text/css" href=".trk/no_git_info.css">
-"#
-        };
+            .open(&path)?;
 
         let html = format!(
             r#"<!DOCTYPE html>
 <html>
 <head>
   {}
-  <title>{} for {}</title>
+  <title>{}</title>
 </head>
 <body>
-{}
+{}{}
 </body>
 </html>"#,
-            stylesheets,
-            "Session",
-            "Rafael Bachmann",
-            session.to_html()
+            self.stylesheets(".trk/"),
+            self.page_title("Session", session.work_time()),
+            self.report_header("Session"),
+            session.to_html_as(&self.html_options())
         );
-        file.write_all(html.as_bytes()).unwrap();
-        format_file("session.html");
-        /* Save was successful */
-        true
+        file.write_all(indent_html(&html).as_bytes())?;
+        Ok(())
     }
 
-    fn write_to_json(&self) -> bool {
-        if !Path::new("./.trk").exists() {
-            match fs::create_dir("./.trk") {
-                Ok(_) => {}
-                _ => {
-                    println!("Could not create .trk directory.");
-                    process::exit(0);
-                }
-            }
+    fn write_to_json(&self) -> io::Result<()> {
+        if !self.trk_dir().exists() {
+            fs::create_dir(self.trk_dir())?;
         }
 
-        let path = Path::new("./.trk/timesheet.json");
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(&path);
-
-        match file {
-            Ok(mut file) => {
-                /* Convert the sheet to a JSON string. */
-                let serialized = to_string(&self).expect("Could not write serialized time sheet.");
-                file.write_all(serialized.as_bytes()).unwrap();
-                /* Save was successful */
-                true
-            }
-            Err(e) => {
-                eprintln!("Could not open timesheet.json file: {}", e);
-                false
-            }
-        }
+        /* Convert the sheet to a JSON string. */
+        let serialized =
+            to_string(&self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        /* Written next to it and renamed over it, so that a crash or a full
+         * disk never leaves timesheet.json half written */
+        let path = Timesheet::json_path(&self.base_dir, self.project.as_deref());
+        let tmp = path.with_extension("json.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(serialized.as_bytes())?;
+        /* On disk before the rename, or a crash may leave it renamed but empty */
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
     }
 
-    fn write_stylesheets(filename: &str, content: &'static str) -> bool {
+    fn write_stylesheets(file_path: &Path, content: &'static str) -> bool {
         // TODO: avoid time-of-check-to-time-of-use race risk
-        let mut file_path = env::current_dir().unwrap();
-        file_path.push(filename);
         if !file_path.exists() {
             let file = OpenOptions::new()
                 .write(true)
                 .truncate(true)
                 .create(true)
                 .open(&file_path);
-            match file {
-                Ok(mut file) => {
-                    file.write_all(content.as_bytes()).unwrap();
-                    /* Save was successful */
-                    true
-                }
+            match file.and_then(|mut file| file.write_all(content.as_bytes())) {
+                /* Save was successful */
+                Ok(()) => true,
                 Err(e) => {
-                    println!("Could not report sheet! {}", e);
+                    eprintln!("Could not write {}: {}", file_path.display(), e);
                     false
                 }
             }
@@ -294,129 +933,881 @@ impl Timesheet {
         }
     }
 
-    pub fn write_files(&self) -> bool {
-        /* TODO: avoid time-of-check-to-time-of-use race risk */
-        self.write_to_json() && self.write_to_html(None) && self.write_last_session_html()
+    /* Writes the default stylesheets into .trk unless they are already there */
+    fn write_default_stylesheets(&self) -> bool {
+        let style: &'static str = include_str!("../../style.css");
+        let no_git_info_style: &'static str = include_str!("../../no_git_info.css");
+        Timesheet::write_stylesheets(&self.trk_dir().join("style.css"), style)
+            && Timesheet::write_stylesheets(
+                &self.trk_dir().join("no_git_info.css"),
+                no_git_info_style,
+            )
     }
 
-    /** Return a Some(Timesheet) struct if a timesheet.json file
-     * is present and valid in the .trk directory, and None otherwise.
-     * */
-    pub fn load_from_file() -> Option<Timesheet> {
-        let mut path = env::current_dir().unwrap();
-        loop {
-            path.push(".trk");
-            if path.exists() {
-                env::set_current_dir(&path).unwrap();
-                break;
-            } else {
-                path.pop();
-                if !path.pop() {
-                    return None;
-                }
+    /** Err if timesheet.json could not be written. Reports that could not
+     * be written are only mentioned. */
+    pub fn write_files(&mut self) -> Result<(), TrkError> {
+        self.cached_stats = Some(self.finished_stats());
+        /* Other trk processes are kept out by the lock main holds, see TrkLock */
+        self.write_to_json()?;
+        /* Only now that the sheet has them, or they'd be lost if it couldn't be written */
+        if let Some(pending) = self.pending_left.take() {
+            if let Err(e) = self.write_pending(&pending) {
+                eprintln!("Could not update {}: {}", self.pending_path().display(), e);
             }
         }
-
-        path.push("timesheet.json");
-        let file = OpenOptions::new().read(true).open(&path);
-        let result = match file {
-            Ok(mut file) => {
-                let mut serialized = String::new();
-                match file.read_to_string(&mut serialized) {
-                    Ok(..) => {
-                        let style: &'static str = include_str!("../../style.css");
-                        let no_git_info_style: &'static str = include_str!("../../no_git_info.css");
-                        let trk_gitignore: &'static str = include_str!("trk_gitignore");
-                        Timesheet::write_stylesheets("style.css", style);
-                        Timesheet::write_stylesheets("no_git_info.css", no_git_info_style);
-                        Timesheet::write_stylesheets(".gitignore", trk_gitignore);
-                        from_str(&serialized).unwrap_or(None)
-                    }
-                    Err(..) => {
-                        println!("IO error while reading the timesheet file.");
-                        process::exit(0);
-                    }
-                }
-            }
-            Err(..) => None,
-        };
-        path.pop();
-        env::set_current_dir(path).unwrap();
-        result
-    }
-
-    pub fn clear() {
-        /* Try to get user name */
-        let sheet = Timesheet::load_from_file();
-        /* In case there is a sheet, there must also be a name */
-        let name: Option<String> = sheet.map(|s| s.config.user_name.unwrap());
-
-        let path = Path::new("./.trk/timesheet.json");
-        if path.exists() {
-            fs::remove_file(&path).unwrap_or_else(|e| {
-                println!("Could not remove sessions file: {}", e);
-            });
+        let trk_gitignore: &'static str = include_str!("trk_gitignore");
+        Timesheet::write_stylesheets(&self.trk_dir().join(".gitignore"), trk_gitignore);
+        if self.config.html_output {
+            self.write_reports();
         }
-        Timesheet::init(name.as_deref());
-    }
-
-    pub fn timesheet_status(&self) -> String {
-        let mut status = format!(
-            "Sheet running for {}\n",
-            sec_to_hms_string(get_seconds() - self.start)
-        );
-        match self.sessions.len() {
-            0 => writeln!(&mut status, "No sessions yet.").unwrap(),
-            n => write!(
-                &mut status,
-                "{} session(s) so far.\nLast session:\n{}",
-                n,
-                self.sessions[n - 1].status()
-            )
-            .unwrap(),
-        };
-        status
+        Ok(())
     }
 
-    pub fn last_session_status(&self) -> String {
-        let status = self.sessions.last().map(|session| session.status());
-        status.unwrap_or_else(|| String::from("No session yet."))
+    /** Regenerates timesheet.html and session.html from the sheet as it is,
+     * without touching timesheet.json */
+    pub fn write_reports(&self) -> bool {
+        self.write_default_stylesheets();
+        Timesheet::report_written(
+            &self.report_file("timesheet"),
+            self.write_to_html(&self.report_file("timesheet"), None, None, false),
+        ) && Timesheet::report_written(&self.report_file("session"), self.write_last_session_html())
     }
 
-    fn open_local_html(&self, filename: &str) {
-        let file_url = match env::current_dir() {
-            Ok(dir) => match dir.join(&filename).to_str() {
-                Some(path) => format!("file://{}", path),
-                None => {
-                    println!("Invalid filename: {}.", filename);
-                    process::exit(0)
-                }
-            },
+    /* Tells the user which report could not be written */
+    fn report_written(filename: &str, result: io::Result<()>) -> bool {
+        match result {
+            Ok(()) => true,
             Err(e) => {
-                println!("Couldn't obtain current directory: {}", e);
-                process::exit(0)
+                eprintln!("Could not write {}: {}", filename, e);
+                false
             }
-        };
-        match Url::parse(&file_url) {
-            Ok(url) => url.open(),
-            Err(e) => println!("Couldn't open file: {}", e),
         }
     }
 
-    pub fn report_last_session(&self) {
-        self.write_to_html(None);
-        self.open_local_html("session.html");
+    /* Call of trk commit for this timesheet in the post-commit hook */
+    fn hook_call(&self) -> String {
+        let project = match self.project {
+            Some(ref project) => format!("TRK_PROJECT={} ", project),
+            None => String::new(),
+        };
+        format!("{}trk commit $(git rev-parse HEAD)", project)
     }
 
-    pub fn report_sheet(&self, ago: Option<u64>) {
-        self.write_to_html(ago);
-        self.open_local_html("timesheet.html");
-        /* Leave complete sheet html */
-        self.write_to_html(None);
+    /* Line the post-commit hook calls trk with, recognized by its comment */
+    fn hook_line(&self) -> String {
+        format!("{} {}", self.hook_call(), HOOK_MARKER)
     }
 
-    pub fn show_commits(&mut self, on_off: bool) {
-        self.config.show_commits = on_off;
+    /* Whether line of a hook is the call of hook_line, with or without its
+     * comment, as in the post-commit that comes with trk */
+    fn is_hook_call(&self, line: &str) -> bool {
+        line.trim().trim_end_matches(HOOK_MARKER).trim_end() == self.hook_call()
+    }
+
+    /* post-commit hook of the repository the timesheet belongs to */
+    fn post_commit_hook(&self) -> Result<PathBuf, TrkError> {
+        if self.session_repo.is_some() {
+            return Err(TrkError::Rejected(String::from(
+                "The global timesheet spans all repositories. Add this line to \
+                 .git/hooks/post-commit of each instead: trk --global commit $(git rev-parse HEAD)",
+            )));
+        }
+        match git_hooks_dir(&self.base_dir) {
+            Some(hooks) => Ok(hooks.join("post-commit")),
+            None => Err(TrkError::GitFailure(format!(
+                "{} is not in a git repository.",
+                self.base_dir.display()
+            ))),
+        }
+    }
+
+    /** Makes git's post-commit hook call trk commit, so that commits are recorded
+     * without calling it by hand. An existing hook is kept and the call appended,
+     * unless it is no shell script or ends by exiting. Does nothing if the hook
+     * already calls trk commit for this timesheet, whether added by this or by hand. */
+    pub fn install_git_hook(&self) -> Result<(), TrkError> {
+        let path = self.post_commit_hook()?;
+        let line = self.hook_line();
+        let script = match fs::read_to_string(&path) {
+            Ok(ref script) if script.lines().any(|l| self.is_hook_call(l)) => return Ok(()),
+            Ok(script) => {
+                let shell = script.lines().next().map_or(false, |first| {
+                    first.starts_with("#!") && first.ends_with("sh")
+                });
+                let exits = script
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .last()
+                    .map_or(false, |last| last.starts_with("exit"));
+                if !shell || exits {
+                    return Err(TrkError::Rejected(format!(
+                        "Not changing {}, trk would not be called at its end. \
+                         Add this line where it belongs yourself:\n{}",
+                        path.display(),
+                        line
+                    )));
+                }
+                let newline = if script.ends_with('\n') { "" } else { "\n" };
+                format!("{}{}{}\n", script, newline, line)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => format!("#!/bin/sh\n{}\n", line),
+            Err(e) => return Err(TrkError::Report(path.display().to_string(), e)),
+        };
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, script))
+            .and_then(|_| make_executable(&path))
+            .map_err(|e| TrkError::Report(path.display().to_string(), e))
+    }
+
+    /** Takes the call of install_git_hook out of the post-commit hook again,
+     * and removes the hook if nothing else is left in it */
+    pub fn uninstall_git_hook(&self) -> Result<(), TrkError> {
+        let path = self.post_commit_hook()?;
+        let line = self.hook_line();
+        let script = match fs::read_to_string(&path) {
+            Ok(script) => script,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(TrkError::Report(path.display().to_string(), e)),
+        };
+        if !script.lines().any(|l| l == line) {
+            return Err(TrkError::Rejected(format!(
+                "{} does not call trk for this timesheet.",
+                path.display()
+            )));
+        }
+        let rest: Vec<&str> = script.lines().filter(|&l| l != line).collect();
+        let removed = if rest
+            .iter()
+            .all(|l| l.trim().is_empty() || l.starts_with("#!"))
+        {
+            fs::remove_file(&path)
+        } else {
+            fs::write(&path, rest.join("\n") + "\n")
+        };
+        removed.map_err(|e| TrkError::Report(path.display().to_string(), e))
+    }
+
+    /** Loads the default timesheet of the .trk directory in base_dir */
+    pub fn load(base_dir: &Path) -> Result<Timesheet, LoadError> {
+        Timesheet::load_project(base_dir, None)
+    }
+
+    /** Like load, for project's timesheet (None for the default one) */
+    pub fn load_project(base_dir: &Path, project: Option<&str>) -> Result<Timesheet, LoadError> {
+        let mut sheet = Timesheet::read_sheet(&Timesheet::json_path(base_dir, project))?;
+        sheet.base_dir = base_dir.to_path_buf();
+        sheet.project = project.map(|name| name.to_string());
+        Ok(sheet)
+    }
+
+    fn read_sheet(path: &Path) -> Result<Timesheet, LoadError> {
+        let mut serialized = String::new();
+        OpenOptions::new()
+            .read(true)
+            .open(path)
+            .and_then(|mut file| file.read_to_string(&mut serialized))
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => LoadError::Absent,
+                _ => LoadError::Unreadable(e),
+            })?;
+        match from_str::<Option<Timesheet>>(&serialized) {
+            Ok(Some(sheet)) => Ok(sheet),
+            Ok(None) => Err(LoadError::Corrupt(String::from("it is null"))),
+            Err(e) => Err(LoadError::Corrupt(e.to_string())),
+        }
+    }
+
+    pub fn backup_path(base_dir: &Path, project: Option<&str>) -> PathBuf {
+        Timesheet::json_path(base_dir, project).with_extension("json.bak")
+    }
+
+    /** Replaces timesheet.json by the backup, if the backup is a valid timesheet */
+    pub fn restore_backup(base_dir: &Path, project: Option<&str>) -> Result<Timesheet, LoadError> {
+        let backup = Timesheet::backup_path(base_dir, project);
+        Timesheet::read_sheet(&backup)?;
+        fs::copy(&backup, Timesheet::json_path(base_dir, project))
+            .map_err(LoadError::Unreadable)?;
+        Timesheet::load_project(base_dir, project)
+    }
+
+    /** Starts project's timesheet afresh, the other projects stay */
+    pub fn clear(base_dir: &Path, project: Option<&str>) -> Result<(), TrkError> {
+        /* Try to get user name */
+        let sheet = Timesheet::load_project(base_dir, project).ok();
+        /* In case there is a sheet, there must also be a name */
+        let name: Option<String> = sheet.map(|s| s.config.user_name.unwrap());
+
+        let path = Timesheet::json_path(base_dir, project);
+        if path.exists() {
+            fs::remove_file(&path).unwrap_or_else(|e| {
+                eprintln!("Could not remove {}: {}", path.display(), e);
+            });
+        }
+        Timesheet::init_project(base_dir, name.as_deref(), project).map(|_| ())
+    }
+
+    /** Copies .trk/timesheet.json to .trk/timesheet.json.bak
+     * so that a bulk change can be undone by copying it back */
+    pub fn backup(&self) -> bool {
+        let project = self.project.as_deref();
+        let path = Timesheet::json_path(&self.base_dir, project);
+        match fs::copy(&path, Timesheet::backup_path(&self.base_dir, project)) {
+            Ok(..) => true,
+            Err(e) => {
+                eprintln!("Could not back up timesheet.json: {}", e);
+                false
+            }
+        }
+    }
+
+    /** Reclassifies the git branches of all sessions as tags.
+     * Returns (sessions affected, branches reclassified). */
+    pub fn branches_to_tags(&mut self, keep_branches: bool) -> (usize, usize) {
+        self.sessions
+            .iter_mut()
+            .map(|session| session.branches_to_tags(keep_branches))
+            .filter(|&count| count > 0)
+            .fold((0, 0), |(sessions, branches), count| {
+                (sessions + 1, branches + count)
+            })
+    }
+
+    /** Indices of finalized sessions worked on for less than min_work_time seconds.
+     * The running session is never included. */
+    pub fn empty_sessions(&self, min_work_time: u64) -> Vec<usize> {
+        self.sessions
+            .iter()
+            .enumerate()
+            .filter(|&(_, session)| !session.is_running() && session.work_time() < min_work_time)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /** Removes the sessions found by empty_sessions, returns how many */
+    pub fn prune(&mut self, min_work_time: u64) -> usize {
+        self.cached_stats = None;
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|session| session.is_running() || session.work_time() >= min_work_time);
+        before - self.sessions.len()
+    }
+
+    /** Pairs of session indices whose [start, end] ranges overlap */
+    pub fn overlapping_sessions(&self) -> Vec<(usize, usize)> {
+        let mut overlaps = Vec::new();
+        for (i, first) in self.sessions.iter().enumerate() {
+            for (j, second) in self.sessions.iter().enumerate().skip(i + 1) {
+                if first.start < second.current_end() && second.start < first.current_end() {
+                    overlaps.push((i, j));
+                }
+            }
+        }
+        overlaps
+    }
+
+    /** Describes everything that looks wrong with the sheet, one line per problem */
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .overlapping_sessions()
+            .into_iter()
+            .map(|(i, j)| {
+                format!(
+                    "Sessions {} ({}) and {} ({}) overlap.",
+                    i,
+                    ts_to_date(self.sessions[i].start),
+                    j,
+                    ts_to_date(self.sessions[j].start)
+                )
+            })
+            .collect();
+        for (i, session) in self.sessions.iter().enumerate() {
+            if let Some((event, problem)) = session.first_bad_transition() {
+                problems.push(format!(
+                    "Session {} ({}), event {}: {}.",
+                    i,
+                    ts_to_date(session.start),
+                    event,
+                    problem
+                ));
+            }
+        }
+        if let Some(ref cached) = self.cached_stats {
+            let actual = self.finished_stats();
+            if !cached.same_totals(&actual) {
+                problems.push(format!(
+                    "Cached stats are out of date: {} finished sessions with {} worked \
+                     and {} commits, but there are {} with {} worked and {} commits. \
+                     Any change or 'trk rebuild_stats' recomputes them.",
+                    cached.sessions,
+                    sec_to_hms_string(cached.work_time),
+                    cached.commits,
+                    actual.sessions,
+                    sec_to_hms_string(actual.work_time),
+                    actual.commits
+                ));
+            }
+        }
+        problems
+    }
+
+    /** Trims overlapping sessions so each ends when the next one starts.
+     * Returns the problems that could not be repaired this way. */
+    pub fn repair(&mut self) -> Vec<String> {
+        self.cached_stats = None;
+        let mut unrepaired = Vec::new();
+        for (i, j) in self.overlapping_sessions() {
+            let (earlier, later) = if self.sessions[i].start <= self.sessions[j].start {
+                (i, j)
+            } else {
+                (j, i)
+            };
+            let cut = self.sessions[later].start;
+            /* Trimming one pair may have resolved this one already */
+            if self.sessions[earlier].current_end() <= cut {
+                continue;
+            }
+            if !self.sessions[earlier].trim_end(cut) {
+                unrepaired.push(format!(
+                    "Could not trim session {} ({}) to end before session {} ({}), \
+                     please merge them by hand.",
+                    earlier,
+                    ts_to_date(self.sessions[earlier].start),
+                    later,
+                    ts_to_date(cut)
+                ));
+            }
+        }
+        unrepaired
+    }
+
+    /** What the (type, timestamp) inputs would do, in order, without touching
+     * the timesheet: Ok if accepted, else why not. Types are begin, pause,
+     * resume, note, commit and end. Events go through Session::push_event on
+     * a copy of the last session, so the same rules apply. */
+    pub fn replay(&self, inputs: &[(String, u64)]) -> Vec<Result<(), String>> {
+        let mut session = self.sessions.last().cloned();
+        inputs
+            .iter()
+            .map(|&(ref input_type, timestamp)| {
+                let running = session.as_ref().map_or(false, |s| s.is_running());
+                let ev_ty = match input_type.as_str() {
+                    "begin" => {
+                        if running {
+                            return Err(String::from("Last session is still running."));
+                        }
+                        let last_end = session.as_ref().map_or(self.start, |s| s.end);
+                        if timestamp <= last_end {
+                            return Err(String::from(
+                                "That timestamp is before the end of the last session.",
+                            ));
+                        }
+                        session = Some(Session::new(Some(timestamp)));
+                        return Ok(());
+                    }
+                    "end" => {
+                        return match session {
+                            Some(ref mut s) if running => {
+                                if timestamp <= s.last_activity() {
+                                    return Err(String::from(
+                                        "That timestamp is before the last event.",
+                                    ));
+                                }
+                                s.finalize(Some(timestamp))
+                            }
+                            _ => Err(String::from("No session running.")),
+                        };
+                    }
+                    "pause" => EventType::Pause,
+                    "resume" => EventType::Resume,
+                    "note" => EventType::Note,
+                    "commit" => EventType::Commit {
+                        hash: String::from("replay"),
+                    },
+                    other => {
+                        return Err(format!(
+                            "What do you mean by {}? Should be either 'begin', 'pause', \
+                             'resume', 'note', 'commit' or 'end'.",
+                            other
+                        ))
+                    }
+                };
+                /* Notes and commit messages don't matter, just avoid the missing message hint */
+                let note = match ev_ty {
+                    EventType::Note | EventType::Commit { .. } => Some(String::new()),
+                    _ => None,
+                };
+                match session {
+                    Some(ref mut s) => s.push_event(Some(timestamp), note, ev_ty),
+                    None => Err(String::from("No session running.")),
+                }
+            })
+            .collect()
+    }
+
+    pub fn timesheet_status(&self) -> String {
+        let mut status = format!(
+            "Sheet running for {}\n",
+            sec_to_hms_string(get_seconds().saturating_sub(self.start))
+        );
+        match self.sessions.len() {
+            0 => writeln!(&mut status, "No sessions yet.").unwrap(),
+            n => write!(
+                &mut status,
+                "{} session(s) on {} day(s) so far.\nLast session:\n{}",
+                n,
+                self.working_day_count(),
+                self.sessions[n - 1].status()
+            )
+            .unwrap(),
+        };
+        match self.pending_commits().len() {
+            0 => {}
+            n => writeln!(
+                &mut status,
+                "{} commit(s) waiting for a session, see 'trk flush_commits'.",
+                n
+            )
+            .unwrap(),
+        }
+        status
+    }
+
+    pub fn last_session_status(&self) -> String {
+        let status = self.sessions.last().map(|session| session.status());
+        status.unwrap_or_else(|| String::from("No session yet."))
+    }
+
+    /** e.g. "trk 1h5m" or "trk paused 10m", empty without a running session.
+     * Only uses the stored sheet, so it's fast enough for a shell prompt. */
+    pub fn prompt_string(&self) -> String {
+        if !self.is_running() {
+            return String::new();
+        }
+        /* Running, so there is a last session */
+        let session = self.sessions.last().unwrap();
+        if session.is_paused() {
+            format!(
+                "trk paused {}",
+                sec_to_short_string(get_seconds().saturating_sub(session.last_activity()))
+            )
+        } else {
+            format!("trk {}", sec_to_short_string(session.work_time()))
+        }
+    }
+
+    pub fn status_report(&self) -> StatusReport {
+        let last = self.sessions.last();
+        let totals = self.finished_totals();
+        let running: Vec<&Session> = self.sessions.iter().filter(|s| s.is_running()).collect();
+        StatusReport {
+            sessions: self.sessions.len(),
+            running: self.is_running(),
+            paused: self.is_running() && last.map_or(false, |session| session.is_paused()),
+            session_start: last.map(|session| session.start),
+            last_event: last
+                .and_then(|session| session.last_event_type())
+                .map(|ev_ty| ev_ty.name()),
+            pending_commits: self.pending_commits().len(),
+            session_work_time: last.map_or(0, |session| session.work_time()),
+            session_pause_time: last.map_or(0, |session| session.pause_time()),
+            work_time: totals.work_time + running.iter().map(|s| s.work_time()).sum::<u64>(),
+            pause_time: totals.pause_time + running.iter().map(|s| s.pause_time()).sum::<u64>(),
+            branches: last.map_or(Vec::new(), |session| {
+                session.branches().iter().cloned().collect()
+            }),
+        }
+    }
+
+    /** Status report as json, or as yaml/toml if built with
+     * the yaml-status/toml-status features */
+    pub fn status_formatted(&self, format: &str) -> Result<String, String> {
+        let status = self.status_report();
+        match format {
+            "json" => to_string(&status).map_err(|e| e.to_string()),
+            #[cfg(feature = "yaml-status")]
+            "yaml" => ::serde_yaml::to_string(&status).map_err(|e| e.to_string()),
+            #[cfg(feature = "toml-status")]
+            "toml" => ::toml::to_string(&status).map_err(|e| e.to_string()),
+            other => Err(format!(
+                "Format {} is not available. Use text or json \
+                 (yaml and toml need trk built with --features yaml-status,toml-status).",
+                other
+            )),
+        }
+    }
+
+    pub fn last_session_segments(&self) -> String {
+        let segments = self
+            .sessions
+            .last()
+            .map(|session| session.segments_string());
+        segments.unwrap_or_else(|| String::from("No session yet."))
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /** Appends one finalized session per csv row of start,end,branches,note
+     * (times as accepted by --at, branches separated by ;). With a header line,
+     * the columns are found by name, so exported sheets can be imported again.
+     * Returns the number of sessions imported; nothing is imported on any error,
+     * or if sessions would overlap without force. */
+    pub fn import_csv(&mut self, content: &str, force: bool) -> Result<usize, String> {
+        let mut records = parse_csv(content)?.into_iter().peekable();
+        let mut columns = (0, 1, 2, 3);
+        let has_header = records
+            .peek()
+            .map_or(false, |&(_, ref fields)| fields[0].trim() == "start");
+        if has_header {
+            let (_, header) = records.next().unwrap();
+            let find = |names: &[&str]| header.iter().position(|h| names.contains(&h.trim()));
+            columns = match (
+                find(&["start"]),
+                find(&["end"]),
+                find(&["branches"]),
+                find(&["note", "notes"]),
+            ) {
+                (Some(start), Some(end), Some(branches), Some(note)) => {
+                    (start, end, branches, note)
+                }
+                _ => {
+                    return Err(String::from(
+                        "Header must name start, end, branches and note.",
+                    ))
+                }
+            };
+        }
+
+        let mut imported = Vec::new();
+        for (line, fields) in records {
+            let field = |idx: usize| fields.get(idx).map_or("", |f| f.as_str());
+            let start = parse_timestamp(field(columns.0))
+                .map_err(|e| format!("Line {}: start: {}", line, e))?;
+            let end = parse_timestamp(field(columns.1))
+                .map_err(|e| format!("Line {}: end: {}", line, e))?;
+            if end <= start {
+                return Err(format!("Line {}: session ends before it starts.", line));
+            }
+            let branches = field(columns.2)
+                .split(';')
+                .map(|branch| branch.trim())
+                .filter(|branch| !branch.is_empty())
+                .map(|branch| branch.to_string())
+                .collect();
+            let note = match field(columns.3) {
+                "" => None,
+                note => Some(note.to_string()),
+            };
+            imported.push(Session::imported(start, end, branches, note));
+        }
+        self.append_imported(imported, force)
+    }
+
+    /** Appends one finalized session per closed interval of `timew export`,
+     * with the tags as branches and the annotation as note. Open intervals
+     * are left out. Returns the number of sessions imported; nothing is
+     * imported on any error. */
+    pub fn import_timewarrior(&mut self, path: &Path, force: bool) -> Result<usize, TrkError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let intervals: Vec<TimewInterval> = from_str(&content)
+            .map_err(|e| format!("{} is not a Timewarrior export: {}", path.display(), e))?;
+        let mut imported = Vec::new();
+        for (i, interval) in intervals.into_iter().enumerate() {
+            let end = match interval.end {
+                Some(end) => end,
+                None => continue,
+            };
+            let read = |time: &str| {
+                parse_utc_basic(time).ok_or_else(|| {
+                    format!(
+                        "Interval {}: What do you mean by {}? Should be e.g. 20240115T090000Z.",
+                        i, time
+                    )
+                })
+            };
+            let start = read(&interval.start)?;
+            let end = read(&end)?;
+            if end <= start {
+                return Err(TrkError::Rejected(format!(
+                    "Interval {}: ends before it starts.",
+                    i
+                )));
+            }
+            let branches = interval.tags.into_iter().collect();
+            imported.push(Session::imported(start, end, branches, interval.annotation));
+        }
+        Ok(self.append_imported(imported, force)?)
+    }
+
+    /* Merges imported sessions in order of their start. Without force,
+     * refuses if the sheet would have problems afterwards, e.g. overlaps.
+     * Nothing can go after a running session either way. */
+    fn append_imported(&mut self, imported: Vec<Session>, force: bool) -> Result<usize, String> {
+        if let Some(running) = self.sessions.last().filter(|session| session.is_running()) {
+            if imported
+                .iter()
+                .any(|session| session.start >= running.start)
+            {
+                return Err(String::from(
+                    "Not importing sessions that begin after the running one.",
+                ));
+            }
+        }
+        let count = imported.len();
+        let mut merged = self.sessions.clone();
+        merged.extend(imported);
+        merged.sort_by_key(|session| session.start);
+        /* Stats cached for the sessions as they were don't count as a problem */
+        let candidate = Timesheet {
+            sessions: merged,
+            cached_stats: None,
+            ..self.clone()
+        };
+        let problems = candidate.problems();
+        if !force && !problems.is_empty() {
+            return Err(format!(
+                "Not importing, the sessions would overlap:\n{}\n\
+                 --force imports them anyway, 'trk doctor --repair' trims them afterwards.",
+                problems.join("\n")
+            ));
+        }
+        self.sessions = candidate.sessions;
+        /* The sheet spans the imported sessions too, ending after the last like end_session */
+        if let Some(first) = self.sessions.first() {
+            self.start = cmp::min(self.start, first.start.saturating_sub(1));
+        }
+        let last_end = self.sessions.iter().map(|session| session.end + 1).max();
+        self.end = cmp::max(self.end, last_end.unwrap_or(0));
+        self.cached_stats = None;
+        Ok(count)
+    }
+
+    /** Copy of the sheet without notes and with branches, tags and commit hashes
+     * replaced by salted hashes. Durations and timestamps stay intact. */
+    pub fn anonymized(&self, salt: Option<&str>) -> Timesheet {
+        let salt = match salt.or_else(|| self.config.anon_salt.as_deref()) {
+            Some(salt) => salt.to_string(),
+            None => random_salt(),
+        };
+        let mut sheet = self.clone();
+        /* The settings hold the salt, names, notes and rates */
+        sheet.config = Config::new();
+        for session in &mut sheet.sessions {
+            session.anonymize(&salt);
+        }
+        sheet
+    }
+
+    /** One line per session: start,end,work_time,pause_time,commits,branches,notes,issue
+     * (times in seconds, rounded to the round_to setting, branches separated by ;) */
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("start,end,work_time,pause_time,commits,branches,notes,issue\n");
+        for session in &self.sessions {
+            writeln!(&mut csv, "{}", session.to_csv_row(self.config.round_to)).unwrap();
+        }
+        csv
+    }
+
+    /** One line per session in the columns of Toggl's csv import, with times
+     * in the local timezone and the repository as project (see to_toggl_csv_row) */
+    pub fn to_toggl_csv(&self) -> String {
+        let mut csv = String::from(
+            "User,Email,Client,Project,Description,Start date,Start time,\
+             End date,End time,Duration,Tags\n",
+        );
+        let user = self.config.user_name.as_deref().unwrap_or("");
+        for session in &self.sessions {
+            let project = session
+                .repo()
+                .or_else(|| self.config.repository.as_deref())
+                .unwrap_or("");
+            writeln!(&mut csv, "{}", session.to_toggl_csv_row(user, project)).unwrap();
+        }
+        csv
+    }
+
+    /** One line per event between from and to: session,timestamp,date,type,hash,note
+     * (session is the index of its session, timestamp in seconds, hash only for commits) */
+    pub fn events_to_csv(&self, from: u64, to: u64) -> String {
+        let mut csv = String::from("session,timestamp,date,type,hash,note\n");
+        for (index, session) in self.sessions.iter().enumerate() {
+            for row in session.events_csv_rows(index, from, to, self.config.round_to) {
+                writeln!(&mut csv, "{}", row).unwrap();
+            }
+        }
+        csv
+    }
+
+    /** A copy holding only the sessions begun between from and to */
+    pub fn between(&self, from: u64, to: u64) -> Timesheet {
+        let mut sheet = self.clone();
+        sheet
+            .sessions
+            .retain(|session| from <= session.start && session.start < to);
+        sheet.cached_stats = None;
+        sheet
+    }
+
+    pub fn export_view(&self) -> SheetExport {
+        SheetExport {
+            start: self.start,
+            end: self.end,
+            user_name: self.config.user_name.as_ref(),
+            repository: self.config.repository.as_ref(),
+            session_count: self.sessions.len(),
+            commit_count: self.sessions.iter().map(Session::commit_count).sum(),
+            work_time: self.work_time(),
+            pause_time: self.pause_time(),
+            days_worked: self.working_day_count(),
+            sessions: self.sessions.iter().map(Session::summary).collect(),
+        }
+    }
+
+    /** The sheet as csv, as csv for Toggl, as stored (json), if computed as json with the
+     * computed durations and counts (see SheetExport), or as the html report.
+     * pretty indents the json and implies computed. */
+    pub fn export(&self, format: &str, computed: bool, pretty: bool) -> Result<String, String> {
+        match format {
+            "csv" => Ok(self.to_csv()),
+            "html" => Ok(self.to_html(None, None)),
+            "tex" => Ok(self.to_tex()),
+            "toggl" => Ok(self.to_toggl_csv()),
+            "md" => Ok(self.to_markdown()),
+            "json" if pretty => to_string_pretty(&self.export_view()).map_err(|e| e.to_string()),
+            "json" if computed => to_string(&self.export_view()).map_err(|e| e.to_string()),
+            "json" => to_string(self).map_err(|e| e.to_string()),
+            other => Err(format!(
+                "What do you mean by {}? Should be either 'csv', 'toggl', 'json', 'html', 'tex' \
+                 or 'md'.",
+                other
+            )),
+        }
+    }
+
+    /** Serializes the session at idx together with its derived values,
+     * or returns None if there is no such session */
+    pub fn session_json(&self, idx: usize) -> Option<String> {
+        self.sessions
+            .get(idx)
+            .and_then(|session| to_string(&session.summary()).ok())
+    }
+
+    /** Events of session idx with their indices, see edit_note */
+    pub fn event_list(&self, idx: usize) -> Option<String> {
+        self.sessions.get(idx).map(|session| session.event_list())
+    }
+
+    /** Replaces the note of event event_index of session session_index,
+     * which has to be a note, pause or commit */
+    pub fn edit_note(
+        &mut self,
+        session_index: usize,
+        event_index: usize,
+        new_text: String,
+    ) -> Result<(), TrkError> {
+        let count = self.sessions.len();
+        match self.sessions.get_mut(session_index) {
+            Some(session) => Ok(session.set_note(event_index, new_text)?),
+            None => Err(TrkError::Rejected(format!(
+                "No session {}, there are {} (counting from 0).",
+                session_index, count
+            ))),
+        }
+    }
+
+    /* Opens the written report in the browser, or only says where it is
+     * with no_open or if that fails */
+    fn show_report(&self, filename: &str, written: io::Result<()>) -> Result<(), TrkError> {
+        written.map_err(|e| TrkError::Report(filename.to_string(), e))?;
+        let path = self.base_dir.join(filename);
+        if self.no_open {
+            println!("{}", path.display());
+        } else if let Err(e) = open_file(&path) {
+            println!(
+                "Couldn't open the report in a browser ({}). It is at {}",
+                e,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /** Reports are only written and their paths printed, not opened */
+    pub fn no_open(&mut self, on_off: bool) {
+        self.no_open = on_off;
+    }
+
+    pub fn report_last_session(&self) -> Result<(), TrkError> {
+        /* Reports are written on demand even if html_output is off */
+        self.write_default_stylesheets();
+        if self.sessions.is_empty() {
+            println!("No session yet.");
+            return Ok(());
+        }
+        let file = self.report_file("session");
+        self.show_report(&file, self.write_last_session_html())
+    }
+
+    /** Opens the sheet report of the sessions begun from from through to,
+     * either end left open by None. With inline_css, the report carries its
+     * stylesheets and can be passed on as a single file. A range goes to
+     * timesheet-range.html, so that timesheet.html stays the complete sheet. */
+    pub fn report_range(
+        &self,
+        from: Option<u64>,
+        to: Option<u64>,
+        inline_css: bool,
+    ) -> Result<(), TrkError> {
+        self.write_default_stylesheets();
+        let file = if from.is_none() && to.is_none() {
+            self.report_file("timesheet")
+        } else {
+            self.report_file("timesheet-range")
+        };
+        /* Don't open a stale or missing report */
+        self.show_report(&file, self.write_to_html(&file, from, to, inline_css))
+    }
+
+    pub fn show_commits(&mut self, on_off: bool) {
+        self.config.show_commits = on_off;
+    }
+
+    pub fn commit_branch_prefix(&mut self, on_off: bool) {
+        self.config.commit_branch_prefix = on_off;
+    }
+
+    pub fn auto_start_on_note(&mut self, on_off: bool) {
+        self.config.auto_start_on_note = on_off;
+    }
+
+    pub fn commit_stats(&mut self, on_off: bool) {
+        self.config.commit_stats = on_off;
+    }
+
+    /** What an hour is billed in invoices, None for only hours. The currency
+     * stays as it is unless one is given. */
+    pub fn set_rate(&mut self, cents_per_hour: Option<u64>, currency: Option<String>) {
+        self.config.rate_cents_per_hour = cents_per_hour;
+        if currency.is_some() {
+            self.config.currency = currency;
+        }
+    }
+
+    pub fn html_output(&mut self, on_off: bool) {
+        self.config.html_output = on_off;
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
     }
 
     pub fn set_repo_url(&mut self, repo: String) {
@@ -436,47 +1827,2993 @@ impl Timesheet {
             .fold(0, |total, session| total + session.work_time())
     }
 
-    fn to_html(&self, ago: Option<u64>) -> String {
-        let timestamp = ago.unwrap_or(self.start);
-        let mut sessions_html = String::new();
-        for session in &self.sessions {
-            if session.start > timestamp {
-                sessions_html.push_str(&format!("{}<hr>", session.to_html()));
-            }
+    /* Totals of the finished sessions, walking all of their events */
+    fn finished_stats(&self) -> CachedStats {
+        let finished = self.sessions.iter().filter(|session| !session.is_running());
+        let mut stats = CachedStats {
+            sessions: 0,
+            commits: 0,
+            work_time: 0,
+            pause_time: 0,
+            updated: get_seconds(),
+        };
+        for session in finished {
+            stats.sessions += 1;
+            stats.commits += session.commit_count();
+            stats.work_time += session.work_time();
+            stats.pause_time += session.pause_time();
         }
+        stats
+    }
 
-        let stylesheets = if self.config.show_commits {
-            "<link rel=\"stylesheet\" type=\"text/css\" href=\".trk/style.css\">\n".to_string()
-        } else {
-            r#"<link rel="stylesheet" type="text/css" href=".trk/style.css">
-<link rel="stylesheet" type="text/css" href=".trk/no_git_info.css">
-"#
-            .to_string()
+    /* Totals of the finished sessions, from the cache if there is one */
+    fn finished_totals(&self) -> CachedStats {
+        self.cached_stats
+            .clone()
+            .unwrap_or_else(|| self.finished_stats())
+    }
+
+    /** Longest and shortest session by work time. The running session only
+     * counts if include_running is set. On ties the earlier session wins. */
+    pub fn extremes(&self, include_running: bool) -> Extremes {
+        let candidates: Vec<(usize, u64)> = self
+            .sessions
+            .iter()
+            .enumerate()
+            .filter(|&(_, session)| include_running || !session.is_running())
+            .map(|(idx, session)| (idx, session.work_time()))
+            .collect();
+        Extremes {
+            longest: candidates
+                .iter()
+                .cloned()
+                .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0))),
+            shortest: candidates
+                .iter()
+                .cloned()
+                .min_by_key(|&(_, work_time)| work_time),
+        }
+    }
+
+    /** e.g. "Longest: session 3 on 2017-04-02, 14:00, worked 5 hours." */
+    pub fn extremes_string(&self, include_running: bool) -> String {
+        let extremes = self.extremes(include_running);
+        let describe = |label: &str, extreme: Option<(usize, u64)>| match extreme {
+            Some((idx, work_time)) => format!(
+                "{}: session {} on {}, worked {}.\n",
+                label,
+                idx,
+                ts_to_date(self.sessions[idx].start),
+                sec_to_hms_string(work_time)
+            ),
+            None => String::new(),
         };
+        if extremes.longest.is_none() {
+            return String::from("No finished session yet.\n");
+        }
+        describe("Longest", extremes.longest) + &describe("Shortest", extremes.shortest)
+    }
 
-        let mut html = format!(
-            r#"<!DOCTYPE html>
-<html>
-    <head>
-        {}
-        <title>{} for {}</title>
-    </head>
-    <body>
-    {}"#,
-            stylesheets, "Timesheet", "Rafael Bachmann", sessions_html
-        );
+    fn repo_name(&self) -> Option<String> {
+        self.config
+            .repository
+            .as_ref()
+            .and_then(|url| repo_name(url))
+    }
 
-        write!(
-            &mut html,
-            r#"<section class="summary">
-    <p>Worked for {}</p>
-    <p>Paused for {}</p>
-</div></section>"#,
-            sec_to_hms_string(self.work_time()),
-            sec_to_hms_string(self.pause_time())
-        )
-        .unwrap();
-        write!(&mut html, "</body>\n</html>").unwrap();
-        html
+    /* e.g. "Timesheet for Alice — my-project", without the user or the
+     * project if they aren't set */
+    fn report_title(&self, kind: &str) -> String {
+        let mut title = kind.to_string();
+        if let Some(ref user) = self.config.user_name {
+            write!(&mut title, " for {}", user).unwrap();
+        }
+        if let Some(repo) = self.repo_name() {
+            write!(&mut title, " — {}", repo).unwrap();
+        }
+        title
+    }
+
+    /* report_title followed by work_time, if title_totals is on, so that
+     * browser tabs show it. Escaped for html. */
+    fn page_title(&self, kind: &str, work_time: u64) -> String {
+        let title = html_escape(&self.report_title(kind));
+        if !self.config.title_totals {
+            return title;
+        }
+        let fmt = DurationFmt {
+            smallest: TimeUnit::Minutes,
+            compact: true,
+            ..DurationFmt::default()
+        };
+        format!("{} — {}", title, format_duration(work_time, fmt))
+    }
+
+    /* Stylesheets of a report that finds them in style_dir, e.g. ".trk/": the
+     * theme inline, if one is set, with custom.css to override it, or else
+     * style.css. Commit details are hidden by no_git_info.css. */
+    fn stylesheets(&self, style_dir: &str) -> String {
+        let link = |name: &str| {
+            format!(
+                "<link rel=\"stylesheet\" type=\"text/css\" href=\"{}{}\">\n",
+                style_dir, name
+            )
+        };
+        let mut stylesheets = match self.config.theme.as_deref().and_then(theme_css) {
+            Some(css) => {
+                let mut inline = format!("<style>\n{}</style>\n", css);
+                if self.trk_dir().join("custom.css").exists() {
+                    inline.push_str(&link("custom.css"));
+                }
+                inline
+            }
+            None => link("style.css"),
+        };
+        if !self.config.show_commits {
+            stylesheets.push_str(&link("no_git_info.css"));
+        }
+        stylesheets
+    }
+
+    /* The same stylesheets as one <style> block, taken from .trk/ where
+     * they were written and built in where not */
+    fn inline_stylesheets(&self) -> String {
+        let read = |name: &str| fs::read_to_string(self.trk_dir().join(name)).ok();
+        let mut css = match self.config.theme.as_deref().and_then(theme_css) {
+            Some(css) => css.to_string(),
+            None => {
+                read("style.css").unwrap_or_else(|| include_str!("../../style.css").to_string())
+            }
+        };
+        if let Some(custom) = read("custom.css") {
+            css.push_str(&custom);
+        }
+        if !self.config.show_commits {
+            css.push_str(
+                &read("no_git_info.css")
+                    .unwrap_or_else(|| include_str!("../../no_git_info.css").to_string()),
+            );
+        }
+        format!("<style>\n{}</style>\n", css)
+    }
+
+    /* What the html of the sessions takes from the config */
+    fn html_options(&self) -> HtmlOptions {
+        HtmlOptions {
+            user: self.config.user_name.as_deref(),
+            issue_url: self.config.issue_url.as_deref(),
+            repo_url: self.config.repository.as_deref(),
+            round_to: self.config.round_to,
+        }
+    }
+
+    /* Visible page header, saying whose report of which project it is */
+    fn report_header(&self, kind: &str) -> String {
+        format!(
+            "<h1 class=\"sheetheader\">{}</h1>\n",
+            html_escape(&self.report_title(kind))
+        )
+    }
+
+    /** Sums up sessions, work time and commits per period, in chronological order.
+     * A session counts towards the period it started in, and its whole work time
+     * towards each of its branches when picking the top branch. */
+    pub fn summary(&self, period: &Period) -> Vec<PeriodSummary> {
+        let mut periods: BTreeMap<String, (PeriodSummary, HashMap<&str, u64>)> = BTreeMap::new();
+        for session in &self.sessions {
+            let label = period.label(session.start);
+            let entry = periods.entry(label.clone()).or_insert_with(|| {
+                let summary = PeriodSummary {
+                    period: label,
+                    sessions: 0,
+                    work_time: 0,
+                    commits: 0,
+                    top_branch: None,
+                };
+                (summary, HashMap::new())
+            });
+            let work_time = session.work_time();
+            entry.0.sessions += 1;
+            entry.0.work_time += work_time;
+            entry.0.commits += session.commit_count();
+            for branch in session.branches() {
+                *entry.1.entry(branch.as_str()).or_insert(0) += work_time;
+            }
+        }
+        periods
+            .into_iter()
+            .map(|(_, (mut summary, branches))| {
+                summary.top_branch = branches
+                    .into_iter()
+                    .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+                    .map(|(branch, _)| branch.to_string());
+                summary
+            })
+            .collect()
+    }
+
+    /* Work time per label, with work across local midnight split between the days.
+     * Days labelled None are left out. */
+    fn work_per_day<F: Fn(u64) -> Option<String>>(&self, label: F) -> Vec<(String, u64)> {
+        let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+        for session in &self.sessions {
+            for (start, end) in session.work_intervals() {
+                for (from, to) in split_at_midnights(start, end) {
+                    if let Some(label) = label(from) {
+                        *totals.entry(label).or_insert(0) += to - from;
+                    }
+                }
+            }
+        }
+        totals.into_iter().collect()
+    }
+
+    /** Work time between from and to, leaving out pauses */
+    pub fn working_time_between(&self, from: u64, to: u64) -> u64 {
+        self.sessions
+            .iter()
+            .flat_map(|session| session.work_intervals())
+            .map(|(start, end)| cmp::min(end, to).saturating_sub(cmp::max(start, from)))
+            .sum()
+    }
+
+    /** Pause time between from and to */
+    pub fn pause_time_between(&self, from: u64, to: u64) -> u64 {
+        self.sessions
+            .iter()
+            .flat_map(|session| session.pause_intervals())
+            .map(|(start, end)| cmp::min(end, to).saturating_sub(cmp::max(start, from)))
+            .sum()
+    }
+
+    /** Work time since local midnight, including the running session up to now */
+    pub fn working_time_today(&self) -> u64 {
+        let now = get_seconds();
+        self.working_time_between(local_day_start(now), now)
+    }
+
+    /** How far today's work, the running session included, is from target
+     * seconds: "2h 15m remaining" or "goal reached (+30m)" */
+    pub fn remaining_string(&self, target: u64) -> String {
+        remaining_text(self.working_time_today(), target)
+    }
+
+    pub fn today(&self) -> TodayReport {
+        let now = get_seconds();
+        let midnight = local_day_start(now);
+        let work_time = self.working_time_between(midnight, now);
+        let pause_time = self.pause_time_between(midnight, now);
+        let last = self.sessions.last();
+        TodayReport {
+            date: ts_to_day(now),
+            utc_offset: utc_offset(now),
+            work_time,
+            pause_time,
+            idle_time: (now - midnight).saturating_sub(work_time + pause_time),
+            sessions: self
+                .sessions
+                .iter()
+                .filter(|session| session.current_end() > midnight && session.start < now)
+                .count(),
+            running: self.is_running(),
+            paused: self.is_running() && last.map_or(false, |session| session.is_paused()),
+        }
+    }
+
+    pub fn today_string(&self) -> String {
+        let today = self.today();
+        format!(
+            "Today ({}): worked {}, paused {} in {} session(s){}.\n",
+            today.date,
+            sec_to_hms_string(today.work_time),
+            sec_to_hms_string(today.pause_time),
+            today.sessions,
+            if today.paused {
+                ", paused right now"
+            } else if today.running {
+                ", still running"
+            } else {
+                ""
+            }
+        )
+    }
+
+    /** This period up to now against the equally long start of the previous one,
+     * or None if the sheet doesn't reach back that far */
+    pub fn compare(&self, period: &Period) -> Option<PeriodComparison> {
+        let now = get_seconds();
+        let (start, previous_start) = period.starts(now);
+        if self.start > previous_start {
+            return None;
+        }
+        let elapsed = now - start;
+        Some(PeriodComparison {
+            period: period.name(),
+            start,
+            previous_start,
+            current: self.working_time_between(start, now),
+            previous: self
+                .working_time_between(previous_start, cmp::min(previous_start + elapsed, start)),
+        })
+    }
+
+    pub fn compare_string(&self, period: &Period) -> String {
+        match self.compare(period) {
+            Some(comparison) => format!(
+                "This {} so far: {:.1} hours\nSame stretch of last {}: {:.1} hours\nChange: {}\n",
+                comparison.period,
+                comparison.current as f64 / 3600.0,
+                comparison.period,
+                comparison.previous as f64 / 3600.0,
+                comparison.delta_string()
+            ),
+            None => format!(
+                "Not enough history to compare: the timesheet starts on {}.\n",
+                ts_to_date(self.start)
+            ),
+        }
+    }
+
+    fn write_compare_html(&self, comparison: &PeriodComparison) -> io::Result<()> {
+        let path = self.base_dir.join(self.report_file("compare"));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&path)?;
+        let title = html_escape(&self.report_title("Comparison"));
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+  {}
+  <title>{}</title>
+</head>
+<body>
+{}<section class="summary">
+    <p>This {} since {}: {}</p>
+    <p>Same stretch of last {}, since {}: {}</p>
+    <p>Change: {}</p>
+</section>
+</body>
+</html>"#,
+            self.stylesheets(".trk/"),
+            title,
+            self.report_header("Comparison"),
+            comparison.period,
+            ts_to_date(comparison.start),
+            sec_to_hms_string(comparison.current),
+            comparison.period,
+            ts_to_date(comparison.previous_start),
+            sec_to_hms_string(comparison.previous),
+            comparison.delta_string()
+        );
+        file.write_all(indent_html(&html).as_bytes())?;
+        Ok(())
+    }
+
+    /** Prints the comparison and opens it as compare.html */
+    pub fn report_compare(&self, period: &Period) -> Result<(), TrkError> {
+        print!("{}", self.compare_string(period));
+        match self.compare(period) {
+            Some(comparison) => {
+                self.write_default_stylesheets();
+                let file = self.report_file("compare");
+                self.show_report(&file, self.write_compare_html(&comparison))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /** Work time per local calendar day, in chronological order */
+    pub fn daily_totals(&self) -> Vec<(String, u64)> {
+        self.work_per_day(|ts| Some(ts_to_day(ts)))
+    }
+
+    /** (day, work, pause) per local calendar day in chronological order, with
+     * stretches across midnight split between the days they fall on */
+    pub fn daily_summary(&self) -> Vec<(String, u64, u64)> {
+        let mut days: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for session in &self.sessions {
+            let work = session.work_intervals().into_iter().map(|i| (true, i));
+            let pauses = session.pause_intervals().into_iter().map(|i| (false, i));
+            for (working, (start, end)) in work.chain(pauses) {
+                for (from, to) in split_at_midnights(start, end) {
+                    let day = days.entry(ts_to_day(from)).or_insert((0, 0));
+                    if working {
+                        day.0 += to - from;
+                    } else {
+                        day.1 += to - from;
+                    }
+                }
+            }
+        }
+        days.into_iter()
+            .map(|(day, (work, pause))| (day, work, pause))
+            .collect()
+    }
+
+    /** Number of local calendar days with any work, whichever the weekday.
+     * A session across midnight counts for both days. */
+    pub fn working_day_count(&self) -> usize {
+        self.daily_totals()
+            .iter()
+            .filter(|&&(_, seconds)| seconds > 0)
+            .count()
+    }
+
+    /** Like daily_totals, but only the configured working_days */
+    pub fn working_day_totals(&self) -> Vec<(String, u64)> {
+        self.work_per_day(|ts| {
+            if self.config.is_working_day(ts_weekday(ts)) {
+                Some(ts_to_day(ts))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn stats(&self) -> Stats {
+        let totals = self.working_day_totals();
+        let working_days = totals.iter().filter(|&&(_, seconds)| seconds > 0).count();
+        let working_day_time: u64 = totals.iter().map(|&(_, seconds)| seconds).sum();
+        let working_day_commits = self
+            .sessions
+            .iter()
+            .flat_map(|session| session.commit_times())
+            .filter(|&ts| self.config.is_working_day(ts_weekday(ts)))
+            .count();
+        let commits: usize = self.sessions.iter().map(Session::commit_count).sum();
+        Stats {
+            sessions: self.sessions.len(),
+            work_time: self.work_time(),
+            working_days,
+            days_worked: self.working_day_count(),
+            commits,
+            work_per_working_day: if working_days > 0 {
+                Some(working_day_time / working_days as u64)
+            } else {
+                None
+            },
+            commits_per_working_day: if working_days > 0 {
+                Some(working_day_commits as f64 / working_days as f64)
+            } else {
+                None
+            },
+            milestones: self.sessions.iter().map(|s| s.milestones().len()).sum(),
+            commits_by_author: self.commits_by_author(),
+            time_by_author: self.time_by_author(),
+        }
+    }
+
+    /** Work time per issue, most worked on first. Sessions not linked to
+     * an issue count as "unassigned". */
+    pub fn time_by_issue(&self) -> Vec<(String, u64)> {
+        let mut times = BTreeMap::new();
+        for session in &self.sessions {
+            let issue = session.issue().unwrap_or("unassigned").to_string();
+            *times.entry(issue).or_insert(0) += session.work_time();
+        }
+        let mut times: Vec<(String, u64)> = times.into_iter().collect();
+        times.sort_by(|a, b| b.1.cmp(&a.1));
+        times
+    }
+
+    pub fn time_by_issue_string(&self) -> String {
+        let mut text = String::new();
+        for (issue, seconds) in self.time_by_issue() {
+            writeln!(&mut text, "{:<20} {}", issue, sec_to_hms_string(seconds)).unwrap();
+        }
+        text
+    }
+
+    fn write_issues_html(&self) -> io::Result<()> {
+        let path = self.base_dir.join(self.report_file("issues"));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&path)?;
+        let mut rows = String::new();
+        for (issue, seconds) in self.time_by_issue() {
+            let name = match (issue.as_str(), self.config.issue_url.as_deref()) {
+                ("unassigned", _) | (_, None) => html_escape(&issue),
+                (_, Some(url)) => format!(
+                    r#"<a href="{}">{}</a>"#,
+                    html_escape(&url.replace("{}", &issue)),
+                    html_escape(&issue)
+                ),
+            };
+            writeln!(
+                &mut rows,
+                "    <tr><td>{}</td><td>{}</td></tr>",
+                name,
+                sec_to_hms_string(seconds)
+            )
+            .unwrap();
+        }
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+  {}
+  <title>{}</title>
+</head>
+<body>
+{}<section class="summary">
+  <table>
+    <tr><th>Issue</th><th>Worked</th></tr>
+{}  </table>
+</section>
+</body>
+</html>"#,
+            self.stylesheets(".trk/"),
+            html_escape(&self.report_title("Issues")),
+            self.report_header("Issues"),
+            rows
+        );
+        file.write_all(indent_html(&html).as_bytes())?;
+        Ok(())
+    }
+
+    /** Prints the work time per issue and opens it as issues.html */
+    pub fn report_issues(&self) -> Result<(), TrkError> {
+        print!("{}", self.time_by_issue_string());
+        self.write_default_stylesheets();
+        let file = self.report_file("issues");
+        self.show_report(&file, self.write_issues_html())
+    }
+
+    fn write_daily_summary_html(&self) -> io::Result<()> {
+        let path = self.base_dir.join(self.report_file("daily"));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&path)?;
+        let hours = |seconds: u64| seconds as f64 / 3600.0;
+        let mut rows = String::new();
+        for (day, work, pause) in self.daily_summary() {
+            writeln!(
+                &mut rows,
+                "    <tr><td>{}</td><td>{:.2}</td><td>{:.2}</td></tr>",
+                day,
+                hours(work),
+                hours(pause)
+            )
+            .unwrap();
+        }
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+  {}
+  <title>{}</title>
+</head>
+<body>
+{}<section class="summary">
+  <table>
+    <tr><th>Day</th><th>Hours worked</th><th>Hours paused</th></tr>
+{}  </table>
+</section>
+</body>
+</html>"#,
+            self.stylesheets(".trk/"),
+            html_escape(&self.report_title("Daily summary")),
+            self.report_header("Daily summary"),
+            rows
+        );
+        file.write_all(indent_html(&html).as_bytes())?;
+        Ok(())
+    }
+
+    /** Opens the work and pause hours per day as daily.html */
+    pub fn report_daily(&self) -> Result<(), TrkError> {
+        self.write_default_stylesheets();
+        let file = self.report_file("daily");
+        self.show_report(&file, self.write_daily_summary_html())
+    }
+
+    pub fn commits_by_author(&self) -> BTreeMap<String, usize> {
+        let mut authors = BTreeMap::new();
+        for session in &self.sessions {
+            for author in session.commit_authors() {
+                *authors.entry(author.to_string()).or_insert(0) += 1;
+            }
+        }
+        authors
+    }
+
+    /** Work time per person. Each session's work time is shared among the authors
+     * of its commits according to the author_attribution setting, and goes to the
+     * user if no commit authors were recorded. Rounding leftovers go to the
+     * alphabetically first author, so the shares add up to the session. */
+    pub fn time_by_author(&self) -> BTreeMap<String, u64> {
+        let user = self
+            .config
+            .user_name
+            .clone()
+            .unwrap_or_else(|| String::from("unknown"));
+        let mut times = BTreeMap::new();
+        for session in &self.sessions {
+            let work_time = session.work_time();
+            let mut commits: BTreeMap<&str, u64> = BTreeMap::new();
+            for author in session.commit_authors() {
+                *commits.entry(author).or_insert(0) += 1;
+            }
+            if commits.is_empty() {
+                *times.entry(user.clone()).or_insert(0) += work_time;
+                continue;
+            }
+            if self.config.author_attribution == Attribution::Equal {
+                for count in commits.values_mut() {
+                    *count = 1;
+                }
+            }
+            for (author, share) in shares(work_time, &commits) {
+                *times.entry(author.to_string()).or_insert(0) += share;
+            }
+        }
+        times
+    }
+
+    /** Work time per branch, most first. A session that recorded switching
+     * branches counts each stretch of work for the branch checked out then,
+     * see Session::work_per_branch. Otherwise its work time is shared among
+     * the branches of its commits according to the author_attribution setting,
+     * like time_by_author does for authors, or equally among the session's
+     * branches if its commits have none recorded. Work on no known branch
+     * counts as "unrecorded", so the times add up to the work time. */
+    pub fn working_time_by_branch(&self) -> Vec<BranchTime> {
+        let attribution = match self.config.author_attribution {
+            Attribution::Commits => "commits",
+            Attribution::Equal => "equal",
+        };
+        let mut times: BTreeMap<String, (u64, usize)> = BTreeMap::new();
+        for session in &self.sessions {
+            for (branch, share) in self.branch_shares(session) {
+                let entry = times.entry(branch.to_string()).or_insert((0, 0));
+                entry.0 += share;
+                entry.1 += 1;
+            }
+        }
+        let mut times: Vec<BranchTime> = times
+            .into_iter()
+            .map(|(branch, (working_secs, sessions))| BranchTime {
+                branch,
+                working_secs,
+                sessions,
+                attribution,
+            })
+            .collect();
+        times.sort_by(|a, b| b.working_secs.cmp(&a.working_secs));
+        times
+    }
+
+    /* Work time of session per branch, see working_time_by_branch */
+    fn branch_shares<'a>(&self, session: &'a Session) -> Vec<(&'a str, u64)> {
+        if session.has_branch_switches() {
+            return session
+                .work_per_branch()
+                .into_iter()
+                .filter(|&(_, time)| time > 0)
+                .map(|(branch, time)| (branch.unwrap_or("unrecorded"), time))
+                .collect();
+        }
+        let mut commits: BTreeMap<&str, u64> = BTreeMap::new();
+        for branch in session.commit_branches() {
+            *commits.entry(branch).or_insert(0) += 1;
+        }
+        if self.config.author_attribution == Attribution::Equal {
+            commits = commits.keys().map(|&branch| (branch, 1)).collect();
+        }
+        if commits.is_empty() {
+            commits = session
+                .branches()
+                .iter()
+                .map(|branch| (branch.as_str(), 1))
+                .collect();
+        }
+        if commits.is_empty() {
+            return vec![("unrecorded", session.work_time())];
+        }
+        shares(session.work_time(), &commits)
+    }
+
+    pub fn working_time_by_branch_string(&self) -> String {
+        let mut text = String::new();
+        for branch in self.working_time_by_branch() {
+            writeln!(
+                &mut text,
+                "{:<20} {} ({} session(s))",
+                branch.branch,
+                sec_to_hms_string(branch.working_secs),
+                branch.sessions
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    pub fn author_attribution(&mut self, attribution: Attribution) {
+        self.config.author_attribution = attribution;
+    }
+
+    pub fn stats_string(&self) -> String {
+        let stats = self.stats();
+        let mut text = format!(
+            "Sessions: {}\nWorked: {}\nDays worked: {}\nWorking days: {}\nCommits: {}\n\
+             Work per working day: {}\nCommits per working day: {}\nMilestones: {}\n",
+            stats.sessions,
+            sec_to_hms_string(stats.work_time),
+            stats.days_worked,
+            stats.working_days,
+            stats.commits,
+            stats
+                .work_per_working_day
+                .map_or(String::from("-"), sec_to_hms_string),
+            stats
+                .commits_per_working_day
+                .map_or(String::from("-"), |average| format!("{:.1}", average)),
+            stats.milestones
+        );
+        for (author, time) in &stats.time_by_author {
+            writeln!(
+                &mut text,
+                "    {}: {}, {} commits",
+                author,
+                sec_to_hms_string(*time),
+                stats.commits_by_author.get(author).unwrap_or(&0)
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    /** A LaTeX invoice for the work between from and to, one line per day worked
+     * with its work time rounded as round says, with amounts in the sheet's
+     * currency if there is an hourly rate (in cents). Each day's amount is
+     * rounded to the cent and the total is their sum, as in the html invoice. */
+    pub fn invoice_tex(
+        &self,
+        client: &str,
+        rate: Option<u64>,
+        from: u64,
+        to: u64,
+        round: Round,
+    ) -> String {
+        let currency = self.config.currency.as_deref();
+        let money = |cents: Option<u64>| {
+            cents.map_or(String::new(), |cents| {
+                tex_escape(&format_cents(cents, currency))
+            })
+        };
+        let mut rows = String::new();
+        let (mut total, mut total_amount) = (0, 0);
+        let mut day = from;
+        while day < to {
+            let next_day = cmp::min(next_local_midnight(day), to);
+            let work_time = round.apply(self.working_time_between(day, next_day));
+            if work_time > 0 {
+                let amount = rate.map(|rate| billed_cents(work_time, rate));
+                writeln!(
+                    &mut rows,
+                    "{} & {:.2} & {} \\\\",
+                    ts_to_day(day),
+                    work_time as f64 / 3600.0,
+                    money(amount)
+                )
+                .unwrap();
+                total += work_time;
+                total_amount += amount.unwrap_or(0);
+            }
+            day = next_day;
+        }
+        let hours = total as f64 / 3600.0;
+        let (rate_line, total_amount) = match rate {
+            Some(rate) => (
+                format!("Rate: {} per hour \\\\\n", money(Some(rate))),
+                money(Some(total_amount)),
+            ),
+            None => (String::new(), String::new()),
+        };
+        format!(
+            r#"\documentclass[a4paper]{{article}}
+\usepackage[utf8]{{inputenc}}
+\begin{{document}}
+\section*{{Invoice}}
+From: {} \\
+To: {} \\
+Period: {} to {} \\
+{}
+\begin{{tabular}}{{lrr}}
+Day & Hours & Amount \\ \hline
+{}\hline
+Total & {:.2} & {} \\
+\end{{tabular}}
+\end{{document}}
+"#,
+            tex_escape(self.config.user_name.as_deref().unwrap_or("")),
+            tex_escape(client),
+            ts_to_day(from),
+            ts_to_day(to.saturating_sub(1)),
+            rate_line,
+            rows,
+            hours,
+            total_amount
+        )
+    }
+
+    /** Writes the invoice as out with .tex extension and compiles it with pdflatex,
+     * leaving just the .tex if that's not possible */
+    pub fn write_invoice(
+        &self,
+        out: &Path,
+        client: &str,
+        rate: Option<u64>,
+        from: u64,
+        to: u64,
+        round: Round,
+    ) -> bool {
+        let tex = out.with_extension("tex");
+        let written = fs::write(&tex, self.invoice_tex(client, rate, from, to, round));
+        if !Timesheet::report_written(&tex.display().to_string(), written) {
+            return false;
+        }
+        match run_pdflatex(&tex) {
+            Ok(true) => println!("Wrote {}.", out.with_extension("pdf").display()),
+            Ok(false) => println!(
+                "pdflatex failed, see {}. The invoice is in {}.",
+                out.with_extension("log").display(),
+                tex.display()
+            ),
+            Err(e) => println!(
+                "Couldn't run pdflatex ({}), so there is only {}. Compile it with any LaTeX.",
+                e,
+                tex.display()
+            ),
+        }
+        true
+    }
+
+    /** An html invoice for the work between from and to: a line per day worked
+     * with its work time rounded half up to round_minutes (0 for exact) and,
+     * if there is a rate (in cents per hour), what it comes to in the sheet's
+     * currency, then the totals */
+    pub fn generate_invoice_html(
+        &self,
+        rate: Option<u64>,
+        from: u64,
+        to: u64,
+        round_minutes: u64,
+    ) -> String {
+        let currency = self.config.currency.as_deref();
+        let amount = |seconds: u64| rate.map(|rate| billed_cents(seconds, rate));
+        let money = |cents: Option<u64>| {
+            cents.map_or(String::new(), |cents| {
+                html_escape(&format_cents(cents, currency))
+            })
+        };
+        let mut rows = String::new();
+        let (mut total, mut total_amount) = (0, 0);
+        let mut day = from;
+        while day < to {
+            let next_day = cmp::min(next_local_midnight(day), to);
+            let work_time = round_ts(self.working_time_between(day, next_day), round_minutes);
+            if work_time > 0 {
+                writeln!(
+                    &mut rows,
+                    "    <tr><td>{}</td><td>{:.2}</td><td>{}</td></tr>",
+                    ts_to_day(day),
+                    work_time as f64 / 3600.0,
+                    money(amount(work_time))
+                )
+                .unwrap();
+                total += work_time;
+                total_amount += amount(work_time).unwrap_or(0);
+            }
+            day = next_day;
+        }
+        let rate_line = match rate {
+            Some(rate) => format!("\n  <p>Rate: {} per hour</p>", money(Some(rate))),
+            None => String::new(),
+        };
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+  {}
+  <title>{}</title>
+</head>
+<body>
+{}<section class="summary">
+  <p>Period: {} to {}</p>{}
+  <table>
+    <tr><th>Day</th><th>Hours</th><th>Amount</th></tr>
+{}    <tr><th>Total</th><th>{:.2}</th><th>{}</th></tr>
+  </table>
+</section>
+</body>
+</html>"#,
+            self.stylesheets(".trk/"),
+            html_escape(&self.report_title("Invoice")),
+            self.report_header("Invoice"),
+            ts_to_day(from),
+            ts_to_day(to.saturating_sub(1)),
+            rate_line,
+            rows,
+            total as f64 / 3600.0,
+            money(rate.map(|_| total_amount))
+        )
+    }
+
+    /** Writes the sheet as a LaTeX document to path, by default timesheet.tex */
+    pub fn write_to_tex(&self, path: Option<&Path>) -> bool {
+        let path = path.map_or_else(|| self.base_dir.join("timesheet.tex"), Path::to_path_buf);
+        Timesheet::report_written(&path.display().to_string(), fs::write(&path, self.to_tex()))
+    }
+
+    /** Writes the sheet as csv for Toggl's import to path */
+    pub fn export_toggl_csv(&self, path: &Path) -> bool {
+        Timesheet::report_written(
+            &path.display().to_string(),
+            fs::write(path, self.to_toggl_csv()),
+        )
+    }
+
+    /** Writes the sheet as Markdown to path, by default timesheet.md */
+    pub fn write_to_markdown(&self, path: Option<&Path>) -> bool {
+        let path = path.map_or_else(|| self.base_dir.join("timesheet.md"), Path::to_path_buf);
+        Timesheet::report_written(
+            &path.display().to_string(),
+            fs::write(&path, self.to_markdown()),
+        )
+    }
+
+    /* Writes content through a temporary file next to path, indented first if
+     * it is html, so that path never holds half a page */
+    fn write_atomically(path: &Path, content: &str, html: bool) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        if html {
+            fs::write(&tmp, indent_html(content))?;
+        } else {
+            fs::write(&tmp, content)?;
+        }
+        fs::rename(&tmp, path)
+    }
+
+    /** Writes one page per month into dir, e.g. 2017-04.html, and index.html
+     * linking them with their totals, together with the stylesheets.
+     * Returns the number of monthly pages. */
+    pub fn write_monthly_html(&self, dir: &Path) -> io::Result<usize> {
+        fs::create_dir_all(dir)?;
+        let style: &'static str = include_str!("../../style.css");
+        let no_git_info_style: &'static str = include_str!("../../no_git_info.css");
+        Timesheet::write_atomically(&dir.join("style.css"), style, false)?;
+        Timesheet::write_atomically(&dir.join("no_git_info.css"), no_git_info_style, false)?;
+        if let Ok(custom) = fs::read_to_string(self.trk_dir().join("custom.css")) {
+            Timesheet::write_atomically(&dir.join("custom.css"), &custom, false)?;
+        }
+
+        let mut months: BTreeMap<String, Vec<Session>> = BTreeMap::new();
+        for session in &self.sessions {
+            months
+                .entry(Period::Month.label(session.start))
+                .or_insert_with(Vec::new)
+                .push(session.clone());
+        }
+        let count = months.len();
+        for (month, sessions) in months {
+            let sheet = Timesheet {
+                start: self.start,
+                end: self.end,
+                config: self.config.clone(),
+                sessions,
+                base_dir: self.base_dir.clone(),
+                session_repo: None,
+                project: None,
+                no_open: false,
+                cached_stats: None,
+                pending_left: None,
+            };
+            let path = dir.join(format!("{}.html", month));
+            Timesheet::write_atomically(&path, &sheet.to_html_styled(""), true)?;
+        }
+
+        let mut rows = String::new();
+        for summary in self.summary(&Period::Month) {
+            writeln!(
+                &mut rows,
+                "    <tr><td><a href=\"{0}.html\">{0}</a></td><td>{1}</td><td>{2}</td><td>{3}</td></tr>",
+                summary.period,
+                summary.sessions,
+                sec_to_hms_string(summary.work_time),
+                summary.commits
+            )
+            .unwrap();
+        }
+        let index = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+  {}
+  <title>{}</title>
+</head>
+<body>
+{}<section class="summary">
+  <table>
+    <tr><th>Month</th><th>Sessions</th><th>Worked</th><th>Commits</th></tr>
+{}  </table>
+  <p>Worked for {}</p>
+</section>
+</body>
+</html>"#,
+            self.stylesheets(""),
+            self.page_title("Timesheet", self.work_time()),
+            self.report_header("Timesheet"),
+            rows,
+            sec_to_hms_string(self.work_time())
+        );
+        Timesheet::write_atomically(&dir.join("index.html"), &index, true)?;
+        Ok(count)
+    }
+
+    /** Work time per ISO week, in chronological order */
+    pub fn weekly_totals(&self) -> Vec<(String, u64)> {
+        self.work_per_day(|ts| Some(Period::Week.label(ts)))
+    }
+
+    pub fn summary_table(&self, period: &Period) -> String {
+        let mut table = format!(
+            "{:<10} {:>8} {:>8} {:>8}  {}\n",
+            "Period", "Sessions", "Hours", "Commits", "Top branch"
+        );
+        for summary in self.summary(period) {
+            writeln!(
+                &mut table,
+                "{:<10} {:>8} {:>8.1} {:>8}  {}",
+                summary.period,
+                summary.sessions,
+                summary.work_time as f64 / 3600.0,
+                summary.commits,
+                summary.top_branch.unwrap_or_else(|| String::from("-"))
+            )
+            .unwrap();
+        }
+        table
+    }
+
+    /* Only sessions begun from from through to, totals included */
+    fn in_range(&self, from: Option<u64>, to: Option<u64>) -> Timesheet {
+        let to = to.map_or(u64::MAX, |to| to.saturating_add(1));
+        self.between(from.unwrap_or(0), to)
+    }
+
+    fn to_html(&self, from: Option<u64>, to: Option<u64>) -> String {
+        self.in_range(from, to).to_html_styled(".trk/")
+    }
+
+    /** Like the sheet report, but with the stylesheets inlined so the page
+     * looks the same without .trk/, e.g. when sent by mail */
+    pub fn to_html_standalone(&self, from: Option<u64>, to: Option<u64>) -> String {
+        let sheet = self.in_range(from, to);
+        sheet.sheet_html(&sheet.inline_stylesheets())
+    }
+
+    /* The sheet report with the stylesheets in style_dir, e.g. ".trk/" */
+    fn to_html_styled(&self, style_dir: &str) -> String {
+        self.sheet_html(&self.stylesheets(style_dir))
+    }
+
+    fn sheet_html(&self, stylesheets: &str) -> String {
+        let mut sessions_html = String::new();
+        for session in &self.sessions {
+            sessions_html.push_str(&format!("{}<hr>", session.to_html_as(&self.html_options())));
+        }
+
+        let mut html = format!(
+            r#"<!DOCTYPE html>
+<html>
+    <head>
+        {}
+        <title>{}</title>
+    </head>
+    <body>
+    {}{}"#,
+            stylesheets,
+            self.page_title("Timesheet", self.work_time()),
+            self.report_header("Timesheet"),
+            sessions_html
+        );
+
+        /* Only worth a breakdown if more than one person worked on this */
+        let mut breakdown_html = String::new();
+        let times = self.time_by_author();
+        if times.len() > 1 {
+            for (author, time) in &times {
+                write!(
+                    &mut breakdown_html,
+                    "\n    <p class=\"git_info\">{}: {}</p>",
+                    html_escape(author),
+                    sec_to_hms_string(*time)
+                )
+                .unwrap();
+            }
+        }
+
+        /* Nothing to break down if no branch was ever recorded */
+        let branches = self.working_time_by_branch();
+        if branches.iter().any(|time| time.branch != "unrecorded") {
+            for time in &branches {
+                write!(
+                    &mut breakdown_html,
+                    "\n    <p class=\"git_info\">On {}: {}</p>",
+                    html_escape(&time.branch),
+                    sec_to_hms_string(time.working_secs)
+                )
+                .unwrap();
+            }
+        }
+
+        write!(
+            &mut html,
+            r#"<section class="summary">
+    <p>Worked for {}</p>
+    <p>Paused for {}</p>{}
+</div></section>"#,
+            sec_to_hms_string(self.work_time()),
+            sec_to_hms_string(self.pause_time()),
+            breakdown_html
+        )
+        .unwrap();
+        write!(&mut html, "</body>\n</html>").unwrap();
+        html
+    }
+}
+
+impl HasTEX for Timesheet {
+    /** A standalone document with the totals and a section per session */
+    fn to_tex(&self) -> String {
+        let mut sessions = String::new();
+        for session in &self.sessions {
+            sessions.push_str(&session.to_tex());
+        }
+        if self.sessions.is_empty() {
+            sessions.push_str("No sessions yet.\n");
+        }
+        format!(
+            r#"\documentclass[a4paper]{{article}}
+\usepackage[utf8]{{inputenc}}
+\usepackage{{array}}
+\usepackage{{longtable}}
+\begin{{document}}
+\section*{{{}}}
+Worked for {}, paused for {} in {} session(s).
+{}\end{{document}}
+"#,
+            tex_escape(&self.report_title("Timesheet")).replace('—', "---"),
+            sec_to_hms_string(self.work_time()),
+            sec_to_hms_string(self.pause_time()),
+            self.sessions.len(),
+            sessions
+        )
+    }
+}
+
+impl HasMarkdown for Timesheet {
+    /** A section per session and the totals at the end, e.g. for an issue */
+    fn to_markdown(&self) -> String {
+        let mut md = format!("# {}\n\n", markdown_escape(&self.report_title("Timesheet")));
+        for session in &self.sessions {
+            md.push_str(&session.to_markdown());
+        }
+        if self.sessions.is_empty() {
+            md.push_str("No sessions yet.\n\n");
+        }
+        write!(
+            &mut md,
+            "## Total\n\n```\nSessions: {}\nWorked: {}\nPaused: {}\n```\n",
+            self.sessions.len(),
+            sec_to_hms_string(self.work_time()),
+            sec_to_hms_string(self.pause_time())
+        )
+        .unwrap();
+        md
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use test_util::TempTrkDir;
+
+    #[test]
+    fn planned_pause_counts_up_to_the_end_of_its_session() {
+        /* Entered ahead of time, the pause is over before the session ends */
+        let now = get_seconds();
+        let mut session = Session::new(Some(now + 10));
+        session
+            .push_event(Some(now + 30), None, EventType::Pause)
+            .unwrap();
+        session
+            .push_event(Some(now + 40), None, EventType::Resume)
+            .unwrap();
+        session.finalize(Some(now + 100)).unwrap();
+        assert_eq!(session.pause_time(), 10);
+    }
+
+    #[test]
+    fn init_session_events_report() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        assert!(!sheet.is_running());
+        sheet.new_session(Some(now + 10)).unwrap();
+        assert!(sheet.is_running());
+        sheet
+            .note(Some(now + 20), String::from("first note"))
+            .unwrap();
+        sheet
+            .pause(Some(now + 30), Some(String::from("coffee")))
+            .unwrap();
+        sheet.resume(Some(now + 40)).unwrap();
+        sheet.end_session(Some(now + 100)).unwrap();
+        assert!(!sheet.is_running());
+        sheet.write_files().unwrap();
+
+        assert!(dir.path().join(".trk/timesheet.json").exists());
+        assert!(dir.path().join("timesheet.html").exists());
+        assert!(dir.path().join("session.html").exists());
+
+        let loaded = Timesheet::load(dir.path()).unwrap();
+        assert_eq!(loaded.session_count(), 1);
+        assert_eq!(loaded.pause_time(), 10);
+        assert_eq!(loaded.work_time() + loaded.pause_time(), 91);
+    }
+
+    #[test]
+    fn init_twice_and_skip_html() {
+        let dir = TempTrkDir::new();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        assert!(Timesheet::init(dir.path(), Some("Tester")).is_err());
+
+        fs::remove_file(dir.path().join("timesheet.html")).unwrap();
+        sheet.html_output(false);
+        sheet.write_files().unwrap();
+        assert!(!dir.path().join("timesheet.html").exists());
+        assert!(dir.path().join(".trk/timesheet.json").exists());
+    }
+
+    #[test]
+    fn interrupted_write_keeps_the_old_timesheet() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.new_session(Some(now + 10)).unwrap();
+        sheet.end_session(Some(now + 100)).unwrap();
+        sheet.write_files().unwrap();
+        let json = dir.path().join(".trk/timesheet.json");
+        let before = fs::read_to_string(&json).unwrap();
+
+        /* A write that died halfway left its temporary file behind */
+        let tmp = dir.path().join(".trk/timesheet.json.tmp");
+        fs::write(&tmp, &before[..before.len() / 2]).unwrap();
+        assert_eq!(Timesheet::load(dir.path()).unwrap().sessions.len(), 1);
+
+        /* A write that can't be made leaves timesheet.json as it was */
+        fs::remove_file(&tmp).unwrap();
+        fs::create_dir(&tmp).unwrap();
+        sheet.new_session(Some(now + 200)).unwrap();
+        assert!(sheet.write_files().is_err());
+        assert_eq!(fs::read_to_string(&json).unwrap(), before);
+
+        fs::remove_dir(&tmp).unwrap();
+        sheet.write_files().unwrap();
+        assert!(!tmp.exists());
+        assert_eq!(Timesheet::load(dir.path()).unwrap().sessions.len(), 2);
+    }
+
+    #[test]
+    fn projects_have_their_own_sheets_and_reports() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut default = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        default.new_session(Some(now + 10)).unwrap();
+        default.write_files().unwrap();
+        let mut acme = Timesheet::init_project(dir.path(), Some("Tester"), Some("acme")).unwrap();
+        assert!(Timesheet::init_project(dir.path(), None, Some("acme")).is_err());
+        assert_eq!(acme.session_count(), 0);
+        acme.new_session(Some(now + 20)).unwrap();
+        acme.end_session(Some(now + 30)).unwrap();
+        acme.write_files().unwrap();
+
+        assert!(dir.path().join(".trk/timesheet-acme.json").exists());
+        assert!(dir.path().join("timesheet-acme.html").exists());
+        assert!(dir.path().join("session-acme.html").exists());
+        assert_eq!(
+            Timesheet::list_projects(dir.path()),
+            vec!["acme", "default"]
+        );
+        let loaded = Timesheet::load_project(dir.path(), Some("acme")).unwrap();
+        assert!(!loaded.is_running());
+
+        Timesheet::clear(dir.path(), Some("acme")).unwrap();
+        let count = |project| {
+            Timesheet::load_project(dir.path(), project)
+                .unwrap()
+                .session_count()
+        };
+        assert_eq!(count(Some("acme")), 0);
+        assert_eq!(count(None), 1);
+    }
+
+    fn sheet_with(sessions: Vec<Session>) -> Timesheet {
+        let mut sheet = Timesheet {
+            start: sessions[0].start - 1,
+            end: sessions[0].start,
+            config: Config::new(),
+            sessions,
+            base_dir: PathBuf::new(),
+            session_repo: None,
+            project: None,
+            no_open: false,
+            cached_stats: None,
+            pending_left: None,
+        };
+        sheet.end = sheet.sessions.last().unwrap().end;
+        sheet
+    }
+
+    /* Europe has fall-back on 2017-10-29 and spring-forward on 2017-03-26, so
+     * run with e.g. TZ=Europe/Berlin to see 25 and 23 hour days. */
+    #[test]
+    fn daily_totals_follow_local_midnight() {
+        /* Days only last 23 or 25 hours in a zone with DST, so the actual
+         * checks run in a test process of their own with the zone pinned */
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(&["--exact", "sheet::timesheet::tests::daily_totals_in_berlin"])
+            .args(&["--ignored", "--quiet"])
+            .env("TZ", "Europe/Berlin")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    #[ignore]
+    fn daily_totals_in_berlin() {
+        /* Run by daily_totals_follow_local_midnight with TZ=Europe/Berlin */
+        assert_eq!(utc_offset(1509228000), "+02:00");
+        /* 2017-10-29 lasts 25 hours, from midnight CEST to midnight CET */
+        let (fall_back, next_day) = (1509228000, 1509318000);
+        assert_eq!(next_local_midnight(fall_back), next_day);
+        let sheet = sheet_with(vec![
+            Session::imported(fall_back, next_day, BTreeSet::new(), None),
+            Session::imported(next_day - 3600, next_day + 7200, BTreeSet::new(), None),
+        ]);
+        assert_eq!(
+            sheet.daily_totals(),
+            vec![
+                (String::from("2017-10-29"), 25 * 3600 + 3600),
+                (String::from("2017-10-30"), 7200),
+            ]
+        );
+        assert_eq!(sheet.working_day_count(), 2);
+        assert_eq!(
+            sheet.weekly_totals(),
+            vec![
+                (String::from("2017-W43"), 25 * 3600 + 3600),
+                (String::from("2017-W44"), 7200),
+            ]
+        );
+
+        /* 2017-03-26 lasts 23 hours */
+        let (spring, after) = (1490482800, 1490565600);
+        assert_eq!(next_local_midnight(spring), after);
+        let sheet = sheet_with(vec![Session::imported(
+            spring,
+            after,
+            BTreeSet::new(),
+            None,
+        )]);
+        assert_eq!(
+            sheet.daily_totals(),
+            vec![(String::from("2017-03-26"), 23 * 3600)]
+        );
+    }
+
+    #[test]
+    fn weekends_only_count_as_working_days_if_configured() {
+        use chrono::{Local, TimeZone};
+        let at = |d, h| Local.ymd(2017, 4, d).and_hms(h, 0, 0).timestamp() as u64;
+        /* Saturday and Monday */
+        let mut sheet = sheet_with(vec![
+            Session::imported(at(8, 10), at(8, 12), BTreeSet::new(), None),
+            Session::imported(at(10, 10), at(10, 14), BTreeSet::new(), None),
+        ]);
+        let stats = sheet.stats();
+        assert_eq!(stats.working_days, 2);
+        assert_eq!(stats.work_per_working_day, Some(3 * 3600));
+
+        sheet
+            .config_mut()
+            .set("working_days", "mon,tue,wed,thu,fri")
+            .unwrap();
+        let stats = sheet.stats();
+        assert_eq!(stats.work_time, 6 * 3600);
+        assert_eq!(stats.working_days, 1);
+        assert_eq!(stats.days_worked, 2);
+        assert_eq!(stats.work_per_working_day, Some(4 * 3600));
+        assert_eq!(sheet.daily_totals().len(), 2);
+        assert_eq!(
+            sheet.working_day_totals(),
+            vec![(String::from("2017-04-10"), 4 * 3600)]
+        );
+    }
+
+    #[test]
+    fn monthly_html_pages_and_index() {
+        use chrono::{Local, TimeZone};
+        let dir = TempTrkDir::new();
+        let at = |m, d| Local.ymd(2017, m, d).and_hms(10, 0, 0).timestamp() as u64;
+        let sheet = sheet_with(vec![
+            Session::imported(at(3, 30), at(3, 30) + 3600, BTreeSet::new(), None),
+            Session::imported(at(4, 2), at(4, 2) + 3600, BTreeSet::new(), None),
+            Session::imported(at(4, 3), at(4, 3) + 7200, BTreeSet::new(), None),
+        ]);
+        let out = dir.path().join("html");
+        assert_eq!(sheet.write_monthly_html(&out).unwrap(), 2);
+        let mut files: Vec<String> = fs::read_dir(&out)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "2017-03.html",
+                "2017-04.html",
+                "index.html",
+                "no_git_info.css",
+                "style.css"
+            ]
+        );
+        let index = fs::read_to_string(out.join("index.html")).unwrap();
+        assert!(index.contains("2017-04.html"));
+        assert!(index.contains("3 hours"));
+        let april = fs::read_to_string(out.join("2017-04.html")).unwrap();
+        assert_eq!(april.matches("class=\"session\"").count(), 2);
+    }
+
+    #[test]
+    fn events_csv_lists_every_event() {
+        let mut first = Session::new(Some(1000));
+        first
+            .push_event(Some(1100), Some(String::from("a, b")), EventType::Note)
+            .unwrap();
+        first
+            .push_event(Some(1200), Some(String::from("coffee")), EventType::Pause)
+            .unwrap();
+        first
+            .push_event(Some(1300), None, EventType::Resume)
+            .unwrap();
+        first.finalize(Some(1400)).unwrap();
+        let mut second = Session::new(Some(2000));
+        second
+            .push_event(
+                Some(2100),
+                Some(String::from("fix")),
+                EventType::Commit {
+                    hash: String::from("abc123"),
+                },
+            )
+            .unwrap();
+        let sheet = sheet_with(vec![first, second]);
+
+        let csv = sheet.events_to_csv(0, u64::MAX);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "session,timestamp,date,type,hash,note");
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("0,1100,\""));
+        assert!(lines[1].ends_with(",note,,\"a, b\""));
+        assert!(lines[3].ends_with(",resume,,"));
+        assert!(lines[4].ends_with(",commit,abc123,fix"));
+
+        let csv = sheet.events_to_csv(1150, 2000);
+        assert_eq!(csv.lines().count(), 3);
+    }
+
+    #[test]
+    fn pause_never_resumed_counts_until_the_end() {
+        let now = get_seconds();
+        let mut running = Session::new(Some(now - 100));
+        running
+            .push_event(Some(now - 40), None, EventType::Pause)
+            .unwrap();
+        let pause_time = running.pause_time();
+        assert!((40..45).contains(&pause_time));
+        /* Give or take the second that may pass in between */
+        let work_time = running.work_time();
+        assert!((59..=61).contains(&work_time));
+
+        /* Ended while paused, as in a hand-edited timesheet.json */
+        let ended: Session = from_str(
+            r#"{"start": 100, "end": 400, "running": false, "branches": [],
+                "events": [{"timestamp": 250, "note": null, "ev_ty": "Pause"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(ended.pause_time(), 150);
+        assert_eq!(ended.work_time(), 150);
+        let backwards: Session = from_str(
+            r#"{"start": 400, "end": 100, "running": false, "branches": [],
+                "events": [{"timestamp": 250, "note": null, "ev_ty": "Pause"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(backwards.elapsed(), 0);
+        assert_eq!(backwards.work_time(), 0);
+    }
+
+    #[test]
+    fn json_is_the_same_for_the_same_sessions() {
+        let session = |branches: &[&str]| {
+            let mut session = Session::new(Some(100));
+            for branch in branches {
+                session.add_branch(branch.to_string());
+            }
+            session
+        };
+        let names = ["main", "feature", "fix", "release", "docs", "wip"];
+        let mut reversed = names;
+        reversed.reverse();
+        let one = sheet_with(vec![session(&names)]);
+        let other = sheet_with(vec![session(&reversed)]);
+        assert_eq!(to_string(&one).unwrap(), to_string(&other).unwrap());
+        assert!(to_string(&one)
+            .unwrap()
+            .contains(r#""branches":["docs","feature","fix","main","release","wip"]"#));
+    }
+
+    #[test]
+    fn remaining_time_to_the_daily_target() {
+        assert_eq!(remaining_text(0, 8 * 3600), "8h remaining");
+        assert_eq!(
+            remaining_text(5 * 3600 + 45 * 60, 8 * 3600),
+            "2h 15m remaining"
+        );
+        assert_eq!(remaining_text(8 * 3600 - 30, 8 * 3600), "1m remaining");
+        assert_eq!(remaining_text(8 * 3600, 8 * 3600), "goal reached (+0m)");
+        assert_eq!(
+            remaining_text(8 * 3600 + 1830, 8 * 3600),
+            "goal reached (+30m)"
+        );
+    }
+
+    #[test]
+    fn new_sessions_get_the_defaults() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.config_mut().set("default_tags", "acme").unwrap();
+        sheet.config_mut().set("default_note", "for ACME").unwrap();
+        sheet.new_session(Some(now + 10)).unwrap();
+        assert!(sheet.last_session_status().contains("Tags: acme"));
+        sheet.note(Some(now + 20), String::from("later")).unwrap();
+        /* Entered ahead with --at, it happened no time ago yet */
+        assert!(sheet
+            .last_session_status()
+            .contains(&format!("Last event: Note, {} ago.", sec_to_hms_string(0))));
+        sheet.set_session_defaults(Some(&vec![String::from("other")][..]), Some(None));
+        let status = sheet.last_session_status();
+        assert!(status.contains("Tags: other") && !status.contains("acme"));
+        assert_eq!(sheet.sessions[0].first_bad_transition(), None);
+        assert!(!sheet.to_csv().contains("for ACME"));
+        assert!(sheet.to_csv().contains("later"));
+    }
+
+    #[test]
+    fn tex_escapes_and_handles_empty_and_running_sessions() {
+        let empty = Session::imported(100, 200, BTreeSet::new(), None);
+        let mut running = Session::new(Some(300));
+        let message = "Fix 100% of the {bugs} & more_ #1\nsecond line ".repeat(10);
+        running
+            .push_event(
+                Some(400),
+                Some(message),
+                EventType::Commit {
+                    hash: String::from("0123456789abcdef"),
+                },
+            )
+            .unwrap();
+        let dir = TempTrkDir::new();
+        let mut sheet = sheet_with(vec![empty, running]);
+        sheet.base_dir = dir.path().to_path_buf();
+        assert!(sheet.write_to_tex(None));
+        let tex = fs::read_to_string(dir.path().join("timesheet.tex")).unwrap();
+        assert!(tex.starts_with("\\documentclass"));
+        assert!(tex.trim_end().ends_with("\\end{document}"));
+        assert!(tex.contains("No events."));
+        assert!(tex.contains("Still running."));
+        assert!(tex.contains("\\texttt{0123456}"));
+        assert!(tex.contains("Fix 100\\% of the \\{bugs\\} \\& more\\_ \\#1\\newline second line"));
+        assert_eq!(tex.matches("\\begin{longtable}").count(), 1);
+    }
+
+    #[test]
+    fn json_export_computes_only_if_asked() {
+        let mut session = Session::new(Some(100));
+        session
+            .push_event(Some(200), None, EventType::Pause)
+            .unwrap();
+        session
+            .push_event(Some(260), None, EventType::Resume)
+            .unwrap();
+        session.finalize(Some(400)).unwrap();
+        session.set_tags(&[String::from("billable")]);
+        let sheet = sheet_with(vec![session]);
+        let stored = sheet.export("json", false, false).unwrap();
+        assert!(!stored.contains("work_time"));
+        assert!(stored.contains("\"sessions\":[{\"start\":100"));
+        let computed = sheet.export("json", true, false).unwrap();
+        assert!(computed.contains("\"pause_time\":60"));
+        assert!(computed.contains("\"days_worked\":1"));
+        assert!(computed.contains("\"tags\":[\"billable\"]"));
+        assert!(!computed.contains('\n'));
+        assert!(sheet
+            .session_json(0)
+            .unwrap()
+            .contains("\"tags\":[\"billable\"]"));
+        assert!(sheet
+            .export("json", false, true)
+            .unwrap()
+            .contains("\n  \"work_time\""));
+    }
+
+    #[test]
+    fn working_time_by_branch_follows_attribution() {
+        let mut committed = Session::new(Some(100));
+        for (timestamp, branch) in vec![(200, "main"), (300, "feature"), (400, "feature")] {
+            let hash = timestamp.to_string();
+            assert!(committed
+                .push_event(
+                    Some(timestamp),
+                    Some(hash.clone()),
+                    EventType::Commit { hash }
+                )
+                .is_ok());
+            committed.set_commit_branch(String::from(branch));
+        }
+        committed.finalize(Some(700)).unwrap();
+        let mut checked_out = Session::new(Some(1000));
+        checked_out.add_branch(String::from("main"));
+        checked_out.finalize(Some(1100)).unwrap();
+        let (first, second) = (committed.work_time(), checked_out.work_time());
+        let mut sheet = sheet_with(vec![committed, checked_out]);
+        let times = |sheet: &Timesheet| -> Vec<(String, u64, usize)> {
+            sheet
+                .working_time_by_branch()
+                .into_iter()
+                .map(|b| (b.branch, b.working_secs, b.sessions))
+                .collect()
+        };
+        assert_eq!(
+            times(&sheet),
+            vec![
+                (String::from("feature"), first - first / 3, 1),
+                (String::from("main"), first / 3 + second, 2),
+            ]
+        );
+        sheet.author_attribution(Attribution::Equal);
+        assert_eq!(
+            times(&sheet),
+            vec![
+                (String::from("main"), first / 2 + second, 2),
+                (String::from("feature"), first - first / 2, 1),
+            ]
+        );
+        assert!(sheet
+            .between(2000, 3000)
+            .working_time_by_branch()
+            .is_empty());
+    }
+
+    #[test]
+    fn replay_tells_what_would_be_rejected() {
+        let sheet = sheet_with(vec![Session::imported(100, 200, BTreeSet::new(), None)]);
+        let inputs: Vec<(String, u64)> = vec![
+            ("pause", 300),
+            ("begin", 150),
+            ("begin", 300),
+            ("resume", 310),
+            ("pause", 320),
+            ("pause", 330),
+            ("note", 310),
+            ("commit", 340),
+            ("end", 400),
+            ("nap", 500),
+        ]
+        .into_iter()
+        .map(|(input_type, timestamp)| (String::from(input_type), timestamp))
+        .collect();
+        let accepted: Vec<bool> = sheet.replay(&inputs).iter().map(|r| r.is_ok()).collect();
+        assert_eq!(
+            accepted,
+            vec![false, false, true, false, true, false, false, true, true, false]
+        );
+        assert_eq!(
+            sheet.replay(&inputs)[6],
+            Err(String::from("That timestamp is before the last event."))
+        );
+        /* Nothing was added for real */
+        assert_eq!(sheet.sessions.len(), 1);
+    }
+
+    #[test]
+    fn work_time_in_title() {
+        let mut sheet = sheet_with(vec![Session::imported(
+            1000,
+            1000 + 2 * 3600 + 600,
+            BTreeSet::new(),
+            None,
+        )]);
+        assert!(sheet
+            .to_html(None, None)
+            .contains("<title>Timesheet — 2h 10m</title>"));
+        sheet.config.user_name = Some(String::from("Alice"));
+        assert!(sheet
+            .to_html(None, None)
+            .contains("<title>Timesheet for Alice — 2h 10m</title>"));
+        sheet.config.title_totals = false;
+        let html = sheet.to_html(None, None);
+        assert!(html.contains("<title>Timesheet for Alice</title>"));
+        assert!(html.contains("<h1 class=\"sheetheader\">Timesheet for Alice</h1>"));
+        assert!(!html.contains("Rafael Bachmann"));
+    }
+
+    #[test]
+    fn theme_is_inlined() {
+        let mut sheet = sheet_with(vec![Session::imported(1000, 2000, BTreeSet::new(), None)]);
+        let html = sheet.to_html(None, None);
+        assert!(html.contains("href=\".trk/style.css\""));
+        sheet.config.theme = Some(String::from("print"));
+        let html = sheet.to_html(None, None);
+        assert!(html.contains("page-break-inside: avoid;"));
+        assert!(!html.contains("style.css"));
+        sheet.config.show_commits = false;
+        assert!(sheet
+            .to_html(None, None)
+            .contains("href=\".trk/no_git_info.css\""));
+    }
+
+    #[test]
+    fn working_time_between_clips_sessions() {
+        let sheet = sheet_with(vec![
+            Session::imported(1000, 2000, BTreeSet::new(), None),
+            Session::imported(3000, 4000, BTreeSet::new(), None),
+        ]);
+        assert_eq!(sheet.working_time_between(0, 5000), 2000);
+        assert_eq!(sheet.working_time_between(1500, 3500), 1000);
+        assert_eq!(sheet.working_time_between(2000, 3000), 0);
+    }
+
+    #[test]
+    fn multi_line_notes_keep_line_breaks() {
+        let mut sheet = sheet_with(vec![Session::new(Some(1000))]);
+        sheet
+            .note(Some(1100), String::from("first line\nsecond line"))
+            .unwrap();
+        sheet
+            .pause(Some(1200), Some(String::from("coffee")))
+            .unwrap();
+        sheet.note(Some(1300), String::from("and\ncake")).unwrap();
+        let html = sheet.to_html(None, None);
+        assert!(html.contains("first line<br>\nsecond line"));
+        assert!(html.contains("coffee<br>\nand<br>\ncake"));
+    }
+
+    #[test]
+    fn commit_hashes_link_to_the_repository() {
+        let mut session = Session::new(Some(1000));
+        session
+            .push_event(
+                Some(1100),
+                Some(String::from("fix")),
+                EventType::Commit {
+                    hash: String::from("abc123"),
+                },
+            )
+            .unwrap();
+        let mut sheet = sheet_with(vec![session]);
+        assert!(sheet.to_html(None, None).contains("id: abc123\n"));
+        sheet.set_repo_url(String::from("git@github.com:medium-endian/trk.git"));
+        assert!(sheet.to_html(None, None).contains(
+            r#"id: <a href="https://github.com/medium-endian/trk/commit/abc123">abc123</a>"#
+        ));
+    }
+
+    #[test]
+    fn notes_and_tags_are_html_escaped() {
+        let mut sheet = sheet_with(vec![Session::new(Some(1000))]);
+        sheet
+            .note(Some(1100), String::from("a < b && c > d"))
+            .unwrap();
+        sheet
+            .pause(Some(1200), Some(String::from("<script>alert(1)</script>")))
+            .unwrap();
+        sheet.set_session_defaults(Some(&vec![String::from("\"quoted\"")][..]), None);
+        let html = sheet.to_html(None, None);
+        assert!(html.contains("Note: a &lt; b &amp;&amp; c &gt; d"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(html.contains("Tags: &quot;quoted&quot;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn corrupt_sheet_is_not_absent_and_restores() {
+        let dir = TempTrkDir::new();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.new_session(None).unwrap();
+        sheet.write_files().unwrap();
+        assert!(sheet.backup());
+        fs::write(Timesheet::json_path(dir.path(), None), "{\"start\": 1,").unwrap();
+
+        match Timesheet::load(dir.path()) {
+            Err(LoadError::Corrupt(..)) => {}
+            other => panic!("expected a corrupt sheet, got {:?}", other.map(|_| ())),
+        }
+        assert!(!Timesheet::is_init(dir.path(), None));
+        let restored = Timesheet::restore_backup(dir.path(), None).unwrap();
+        assert_eq!(restored.session_count(), 1);
+        assert!(Timesheet::load(dir.path()).is_ok());
+
+        fs::remove_file(Timesheet::json_path(dir.path(), None)).unwrap();
+        assert!(matches!(
+            Timesheet::load(dir.path()),
+            Err(LoadError::Absent)
+        ));
+    }
+
+    #[test]
+    fn time_by_author_splits_by_commits() {
+        let now = get_seconds();
+        let mut shared = Session::new(Some(now - 1000));
+        for author in &["Bob", "Alice", "Bob"] {
+            shared
+                .push_event(
+                    None,
+                    Some(String::from("msg")),
+                    EventType::Commit {
+                        hash: String::from("abc"),
+                    },
+                )
+                .unwrap();
+            shared.set_commit_author(author.to_string(), String::from("x@example.com"));
+        }
+        shared.finalize(Some(now + 1000)).unwrap();
+        let alone = Session::imported(now + 2000, now + 2600, BTreeSet::new(), None);
+        let mut sheet = sheet_with(vec![shared, alone]);
+        sheet.config.user_name = Some(String::from("Tester"));
+
+        let times = sheet.time_by_author();
+        assert_eq!(times["Tester"], 600);
+        assert_eq!(times["Alice"] + times["Bob"], 2001);
+        assert_eq!(times["Bob"], 2001 * 2 / 3);
+
+        sheet.author_attribution(Attribution::Equal);
+        let times = sheet.time_by_author();
+        assert_eq!(times["Alice"], 1001);
+        assert_eq!(times["Bob"], 1000);
+        assert_eq!(times["Tester"], 600);
+    }
+
+    #[test]
+    fn time_by_issue_sums_shared_issues() {
+        let now = get_seconds();
+        let session = |start: u64, length: u64, issue: Option<&str>| {
+            let mut session = Session::imported(start, start + length, BTreeSet::new(), None);
+            session.set_issue(issue.map(|issue| issue.to_string()));
+            session
+        };
+        let sheet = sheet_with(vec![
+            session(now, 600, Some("TRK-1")),
+            session(now + 1000, 300, None),
+            session(now + 2000, 900, Some("TRK-2")),
+            session(now + 3000, 600, Some("TRK-1")),
+        ]);
+        assert_eq!(
+            sheet.time_by_issue(),
+            vec![
+                (String::from("TRK-1"), 1200),
+                (String::from("TRK-2"), 900),
+                (String::from("unassigned"), 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_op_events_are_reported_and_not_written() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        assert!(sheet.pause(Some(now + 5), None).is_err());
+        assert!(sheet.resume(Some(now + 5)).is_err());
+        sheet.new_session(Some(now + 10)).unwrap();
+        assert_eq!(
+            sheet.resume(Some(now + 20)).unwrap_err().to_string(),
+            "Currently not paused."
+        );
+        assert!(sheet.pause(Some(now + 30), None).is_ok());
+        assert_eq!(
+            sheet.pause(Some(now + 40), None).unwrap_err().to_string(),
+            "Already paused."
+        );
+        assert!(sheet.resume(Some(now + 50)).is_ok());
+        assert!(sheet
+            .note(Some(now + 45), String::from("before the last event"))
+            .is_err());
+        sheet.end_session(Some(now + 100)).unwrap();
+        assert_eq!(sheet.pause_time(), 20);
+    }
+
+    #[test]
+    fn refused_commands_return_errors() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        match Timesheet::init(dir.path(), Some("Tester")) {
+            Err(TrkError::AlreadyInitialized) => {}
+            other => panic!("expected AlreadyInitialized, got {:?}", other.map(|_| ())),
+        }
+        match sheet.end_session(Some(now + 5)) {
+            Err(TrkError::NoSession(..)) => {}
+            other => panic!("expected NoSession, got {:?}", other),
+        }
+        sheet.new_session(Some(now + 10)).unwrap();
+        match sheet.new_session(Some(now + 20)) {
+            Err(TrkError::AlreadyRunning) => {}
+            other => panic!("expected AlreadyRunning, got {:?}", other),
+        }
+        match sheet.end_session(Some(now + 5)) {
+            Err(TrkError::InvalidTimestamp) => {}
+            other => panic!("expected InvalidTimestamp, got {:?}", other),
+        }
+        assert!(sheet.is_running());
+        sheet.end_session(Some(now + 100)).unwrap();
+        match sheet.new_session(Some(now + 50)) {
+            Err(TrkError::InvalidTimestamp) => {}
+            other => panic!("expected InvalidTimestamp, got {:?}", other),
+        }
+        assert_eq!(sheet.sessions.len(), 1);
+    }
+
+    #[test]
+    fn undo_takes_back_events_of_the_running_session_only() {
+        let undo = |sheet: &mut Timesheet| sheet.undo_last_event().unwrap().describe();
+        let mut sheet = sheet_with(vec![Session::imported(100, 200, BTreeSet::new(), None)]);
+        assert!(sheet.undo_last_event().is_err());
+        sheet.new_session(Some(1000)).unwrap();
+        assert!(sheet.undo_last_event().is_err());
+
+        sheet.note(Some(1100), String::from("oops")).unwrap();
+        sheet.pause(Some(1200), None).unwrap();
+        sheet.resume(Some(1300)).unwrap();
+        assert!(undo(&mut sheet).starts_with("resume of "));
+        assert!(sheet.sessions[1].is_paused());
+        assert_eq!(sheet.sessions[1].end, 1201);
+        assert!(undo(&mut sheet).starts_with("pause of "));
+        assert!(!sheet.sessions[1].is_paused());
+        assert!(undo(&mut sheet).ends_with(": oops"));
+        assert_eq!(sheet.sessions[1].end, 1001);
+        /* Nothing left, and the session before stays as it was */
+        assert!(sheet.undo_last_event().is_err());
+        assert_eq!(sheet.sessions.len(), 2);
+
+        sheet.note(Some(1400), String::from("kept")).unwrap();
+        sheet.end_session(Some(1500)).unwrap();
+        assert!(sheet.undo_last_event().is_err());
+        assert_eq!(sheet.sessions[1].last_event_type(), Some(&EventType::Note));
+    }
+
+    #[test]
+    fn undo_describes_commits_with_any_hash() {
+        let mut sheet = sheet_with(vec![Session::new(Some(1000))]);
+        let hash = String::from("\u{e4}bcdefghij");
+        sheet.sessions[0]
+            .push_event(Some(1100), None, EventType::Commit { hash })
+            .unwrap();
+        let text = sheet.undo_last_event().unwrap().describe();
+        assert!(text.ends_with(" \u{e4}bcdefg"), "{}", text);
+    }
+
+    #[test]
+    fn edit_note_replaces_notes_only() {
+        let mut sheet = sheet_with(vec![Session::new(Some(1000))]);
+        sheet.note(Some(1100), String::from("tpyo")).unwrap();
+        sheet
+            .pause(Some(1200), Some(String::from("lunch")))
+            .unwrap();
+        sheet.resume(Some(1300)).unwrap();
+
+        sheet.edit_note(0, 0, String::from("typo")).unwrap();
+        sheet
+            .edit_note(0, 1, String::from("lunch\nand a walk"))
+            .unwrap();
+        let list = sheet.event_list(0).unwrap();
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("  0  ") && lines[0].ends_with("note    typo"));
+        assert!(lines[1].ends_with("pause   lunch …"));
+        assert!(lines[2].ends_with("resume  "));
+
+        assert!(sheet.edit_note(0, 2, String::from("no")).is_err());
+        assert!(sheet.edit_note(0, 3, String::from("no")).is_err());
+        assert!(sheet.edit_note(1, 0, String::from("no")).is_err());
+        assert!(sheet.edit_note(0, 0, String::new()).is_err());
+        sheet.edit_note(0, 1, String::new()).unwrap();
+        let list = sheet.event_list(0).unwrap();
+        assert!(list.lines().nth(1).unwrap().ends_with("pause   "));
+        assert!(sheet.event_list(1).is_none());
+    }
+
+    #[test]
+    fn commit_without_session_only_begins_one_if_configured() {
+        let dir = TempTrkDir::new();
+        let mut sheet = sheet_with(vec![Session::imported(1000, 2000, BTreeSet::new(), None)]);
+        sheet.base_dir = dir.path().to_path_buf();
+        sheet.config.auto_session_on_commit = false;
+        assert!(sheet.add_commit(String::from("abc123")).is_err());
+        assert_eq!(sheet.session_count(), 1);
+
+        sheet.config.auto_session_on_commit = true;
+        assert!(sheet.add_commit(String::from("abc123")).is_ok());
+        assert_eq!(sheet.session_count(), 2);
+        assert_eq!(sheet.sessions[1].commit_count(), 1);
+    }
+
+    #[test]
+    fn pending_commits_join_the_next_session_or_the_last() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.config.auto_session_on_commit = false;
+        assert!(sheet.add_commit(String::from("abc")).is_err());
+        assert_eq!(sheet.session_count(), 0);
+        assert_eq!(sheet.pending_commits().len(), 1);
+        assert_eq!(sheet.status_report().pending_commits, 1);
+
+        sheet.new_session(Some(now + 60)).unwrap();
+        /* Still queued until the sheet holding them is written */
+        assert_eq!(sheet.pending_commits().len(), 1);
+        sheet.write_files().unwrap();
+        assert!(sheet.pending_commits().is_empty());
+        assert!(!dir.path().join(".trk/pending_commits").exists());
+        assert_eq!(sheet.sessions[0].commit_count(), 1);
+        assert!(sheet.sessions[0].start < now + 60);
+        sheet.end_session(Some(now + 100)).unwrap();
+
+        sheet
+            .write_pending(&[(now + 200, String::from("def"))])
+            .unwrap();
+        assert_eq!(sheet.flush_commits(false), Ok(1));
+        sheet.write_files().unwrap();
+        assert!(sheet.pending_commits().is_empty());
+        assert_eq!(sheet.sessions[0].commit_count(), 2);
+        assert!(!sheet.sessions[0].is_running());
+        assert_eq!(sheet.sessions[0].end, now + 202);
+        assert!(sheet.problems().is_empty());
+
+        /* Before the session's last event, so it is refused and kept */
+        sheet
+            .write_pending(&[
+                (now + 50, String::from("old")),
+                (now + 300, String::from("ghi")),
+            ])
+            .unwrap();
+        assert_eq!(sheet.flush_commits(false), Ok(1));
+        sheet.write_files().unwrap();
+        assert_eq!(sheet.sessions[0].commit_count(), 3);
+        assert_eq!(
+            sheet.pending_commits(),
+            vec![(now + 50, String::from("old"))]
+        );
+
+        assert_eq!(sheet.flush_commits(true), Ok(1));
+        assert!(sheet.pending_commits().is_empty());
+        assert_eq!(sheet.sessions[0].commit_count(), 3);
+
+        /* Another project's queue is its own */
+        let mut other = Timesheet::init_project(dir.path(), Some("Tester"), Some("acme")).unwrap();
+        other.config.auto_session_on_commit = false;
+        assert!(other.add_commit(String::from("jkl")).is_err());
+        assert_eq!(other.pending_commits().len(), 1);
+        assert!(dir.path().join(".trk/pending_commits-acme").exists());
+        assert!(sheet.pending_commits().is_empty());
+        assert_eq!(sheet.flush_commits(false), Ok(0));
+        assert_eq!(other.pending_commits().len(), 1);
+    }
+
+    #[test]
+    fn event_type_counts_of_mixed_session() {
+        let now = get_seconds();
+        let mut session = Session::new(Some(now - 100));
+        session
+            .push_event(Some(now - 90), Some(String::from("a")), EventType::Note)
+            .unwrap();
+        session
+            .push_event(Some(now - 80), None, EventType::Pause)
+            .unwrap();
+        session
+            .push_event(Some(now - 70), Some(String::from("b")), EventType::Note)
+            .unwrap();
+        session
+            .push_event(Some(now - 60), None, EventType::Resume)
+            .unwrap();
+        session
+            .push_event(
+                None,
+                Some(String::from("c")),
+                EventType::Commit {
+                    hash: String::from("1"),
+                },
+            )
+            .unwrap();
+        session
+            .push_event(Some(now + 10), None, EventType::Pause)
+            .unwrap();
+        assert_eq!(session.event_type_counts(), (2, 1, 1));
+        let status = session.status();
+        assert!(status.contains("Events: 2 pauses, 1 note, 1 commit"));
+        /* A pause planned ahead hasn't lasted any time yet */
+        assert!(status.contains(&format!(
+            "Paused since {} ({}).",
+            ts_to_date(now + 10),
+            sec_to_hms_string(0)
+        )));
+    }
+
+    #[test]
+    fn events_while_paused() {
+        let now = get_seconds();
+        let paused = || {
+            let mut session = Session::new(Some(now - 100));
+            session
+                .push_event(Some(now - 90), None, EventType::Pause)
+                .unwrap();
+            session
+        };
+
+        let mut session = paused();
+        assert!(session
+            .push_event(Some(now - 80), None, EventType::Pause)
+            .is_err());
+        assert!(session.is_paused());
+
+        let mut session = paused();
+        session
+            .push_event(Some(now - 80), None, EventType::Resume)
+            .unwrap();
+        assert!(!session.is_paused());
+        assert_eq!(session.event_type_counts(), (1, 0, 0));
+
+        let mut session = paused();
+        session
+            .push_event(Some(now - 80), Some(String::from("tea")), EventType::Note)
+            .unwrap();
+        assert!(session.is_paused());
+        assert_eq!(session.event_type_counts(), (1, 0, 0));
+
+        let mut session = paused();
+        session
+            .push_event(
+                Some(now - 80),
+                Some(String::from("fix")),
+                EventType::Commit {
+                    hash: String::from("1"),
+                },
+            )
+            .unwrap();
+        assert!(!session.is_paused());
+        assert_eq!(
+            session.last_event_type().map(|ev_ty| ev_ty.name()),
+            Some("commit")
+        );
+        session.finalize(Some(now - 50)).unwrap();
+        assert_eq!(session.pause_time(), 10);
+    }
+
+    #[test]
+    fn doctor_finds_bad_transitions() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.new_session(Some(now + 10)).unwrap();
+        sheet.pause(Some(now + 20), None).unwrap();
+        sheet.resume(Some(now + 30)).unwrap();
+        sheet.end_session(Some(now + 40)).unwrap();
+        sheet.write_files().unwrap();
+        assert!(Timesheet::load(dir.path()).unwrap().problems().is_empty());
+
+        /* Drop the pause as if edited by hand, leaving a lone resume */
+        let path = Timesheet::json_path(dir.path(), None);
+        let json = fs::read_to_string(&path).unwrap();
+        let pause = format!(
+            "\"timestamp\":{},\"note\":null,\"ev_ty\":\"Pause\"",
+            now + 20
+        );
+        let resume = format!(
+            "\"timestamp\":{},\"note\":null,\"ev_ty\":\"Resume\"",
+            now + 20
+        );
+        assert!(json.contains(&pause));
+        fs::write(&path, json.replace(&pause, &resume)).unwrap();
+
+        /* The pause time changed with it, so the cached stats are out of date too */
+        let problems = Timesheet::load(dir.path()).unwrap().problems();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].ends_with("event 0: resume while not paused."));
+        assert!(problems[1].starts_with("Cached stats are out of date"));
+    }
+
+    #[test]
+    fn cached_stats_are_written_and_checked() {
+        let dir = TempTrkDir::new();
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.new_session(Some(now + 10)).unwrap();
+        sheet.pause(Some(now + 20), None).unwrap();
+        sheet.resume(Some(now + 30)).unwrap();
+        sheet.end_session(Some(now + 100)).unwrap();
+        sheet.new_session(Some(now + 200)).unwrap();
+        sheet.write_files().unwrap();
+
+        let loaded = Timesheet::load(dir.path()).unwrap();
+        let cached = loaded.cached_stats.clone().unwrap();
+        assert!(cached.same_totals(&loaded.finished_stats()));
+        assert_eq!((cached.sessions, cached.pause_time), (1, 10));
+        let status = loaded.status_report();
+        assert_eq!(status.work_time, loaded.work_time());
+        assert_eq!(status.pause_time, loaded.pause_time());
+        assert!(loaded.problems().is_empty());
+
+        let path = Timesheet::json_path(dir.path(), None);
+        let json = fs::read_to_string(&path).unwrap();
+        let pause_time = "\"pause_time\":10,";
+        assert!(json.contains(pause_time));
+        fs::write(&path, json.replace(pause_time, "\"pause_time\":99,")).unwrap();
+        let mut stale = Timesheet::load(dir.path()).unwrap();
+        assert_eq!(stale.problems().len(), 1);
+        assert!(stale.problems()[0].starts_with("Cached stats are out of date"));
+        stale.write_files().unwrap();
+        assert!(Timesheet::load(dir.path()).unwrap().problems().is_empty());
+    }
+
+    #[test]
+    fn report_range_leaves_either_end_open() {
+        let hour = 3600;
+        let session = |start: u64, hours: u64, note: &str| {
+            let note = Some(String::from(note));
+            Session::imported(start, start + hours * hour, BTreeSet::new(), note)
+        };
+        let sheet = sheet_with(vec![
+            session(10 * hour, 1, "note-of-alpha"),
+            session(20 * hour, 2, "note-of-beta"),
+            session(30 * hour, 3, "note-of-gamma"),
+        ]);
+        /* Names that appear nowhere else in the report, unlike e.g. "second" */
+        let shows = |html: &str, notes: [bool; 3]| {
+            let found =
+                ["note-of-alpha", "note-of-beta", "note-of-gamma"].map(|note| html.contains(note));
+            assert_eq!(found, notes, "{}", html);
+        };
+
+        let until = sheet.to_html(None, Some(20 * hour));
+        shows(&until, [true, true, false]);
+        assert!(until.contains("Worked for 3 hours</p>"));
+
+        let since = sheet.to_html(Some(20 * hour), None);
+        shows(&since, [false, true, true]);
+        assert!(since.contains("Worked for 5 hours</p>"));
+
+        let all = sheet.to_html(None, None);
+        shows(&all, [true, true, true]);
+        assert!(all.contains("Worked for 6 hours</p>"));
+        shows(&sheet.to_html(Some(21 * hour), Some(29 * hour)), [false; 3]);
+    }
+
+    #[test]
+    fn intervals_are_split_at_local_midnights() {
+        use chrono::{Local, TimeZone};
+        let midnight = Local.ymd(2017, 4, 8).and_hms(0, 0, 0).timestamp() as u64;
+        assert_eq!(
+            split_at_midnights(midnight - 60, midnight + 60),
+            vec![(midnight - 60, midnight), (midnight, midnight + 60)]
+        );
+        assert_eq!(
+            split_at_midnights(midnight, midnight + 60),
+            vec![(midnight, midnight + 60)]
+        );
+        assert!(split_at_midnights(midnight, midnight).is_empty());
+    }
+
+    #[test]
+    fn daily_summary_splits_stretches_at_midnight() {
+        use chrono::{Local, TimeZone};
+        let midnight = Local.ymd(2017, 4, 8).and_hms(0, 0, 0).timestamp() as u64;
+        let mut sheet = sheet_with(vec![Session::new(Some(midnight - 3600))]);
+        sheet.pause(Some(midnight - 600), None).unwrap();
+        sheet.resume(Some(midnight + 900)).unwrap();
+        sheet.end_session(Some(midnight + 3600)).unwrap();
+        /* The session ends a second after the given time, see Session::finalize,
+         * and the days add up to its work time */
+        assert_eq!(
+            sheet.daily_summary(),
+            vec![
+                (String::from("2017-04-07"), 3000, 600),
+                (String::from("2017-04-08"), 2701, 900),
+            ]
+        );
+        assert_eq!(sheet.work_time(), 3000 + 2701);
+    }
+
+    #[test]
+    fn working_time_by_branch_follows_the_switches() {
+        let mut switched = Session::new(Some(1000));
+        let branch = String::from;
+        switched.switch_branch(Some(1100), branch("main")).unwrap();
+        /* Already on main, nothing recorded */
+        switched.switch_branch(Some(1150), branch("main")).unwrap();
+        switched
+            .push_event(Some(1200), None, EventType::Pause)
+            .unwrap();
+        /* Ends the pause */
+        switched
+            .switch_branch(Some(1300), branch("feature"))
+            .unwrap();
+        switched.switch_branch(Some(1400), branch("main")).unwrap();
+        switched.finalize(Some(1500)).unwrap();
+        assert_eq!(switched.event_list().lines().count(), 5);
+        let legacy: BTreeSet<String> = vec![String::from("legacy")].into_iter().collect();
+        let sheet = sheet_with(vec![
+            switched,
+            Session::imported(2000, 2600, legacy, None),
+            Session::imported(3000, 3300, BTreeSet::new(), None),
+        ]);
+        let mut times: Vec<(String, u64)> = sheet
+            .working_time_by_branch()
+            .into_iter()
+            .map(|time| (time.branch, time.working_secs))
+            .collect();
+        times.sort();
+        /* main until the end, a second after 1500 (see Session::finalize) */
+        let expected = [
+            ("feature", 100),
+            ("legacy", 600),
+            ("main", 201),
+            ("unrecorded", 400),
+        ];
+        let expected: Vec<(String, u64)> = expected
+            .iter()
+            .map(|&(branch, time)| (String::from(branch), time))
+            .collect();
+        assert_eq!(times, expected);
+        let total: u64 = times.iter().map(|&(_, time)| time).sum();
+        assert_eq!(total, sheet.work_time());
+        let html = sheet.to_html(None, None);
+        assert!(html.contains("Switched to branch feature"));
+        assert!(html.contains("On legacy: 10 minutes</p>"));
+        assert!(
+            !sheet_with(vec![Session::imported(1, 2, BTreeSet::new(), None)])
+                .to_html(None, None)
+                .contains("unrecorded")
+        );
+    }
+
+    #[test]
+    fn standalone_html_inlines_the_stylesheets() {
+        let dir = TempTrkDir::new();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        fs::write(dir.path().join(".trk/custom.css"), "h1 { color: teal; }\n").unwrap();
+        let html = sheet.to_html_standalone(None, None);
+        assert!(!html.contains("<link"));
+        assert!(html.contains(include_str!("../../style.css")));
+        assert!(html.contains("h1 { color: teal; }"));
+        assert!(!html.contains("div.git_info"));
+
+        sheet.show_commits(false);
+        assert!(sheet
+            .to_html_standalone(None, None)
+            .contains(include_str!("../../no_git_info.css")));
+        assert!(sheet
+            .to_html(None, None)
+            .contains("href=\".trk/no_git_info.css\""));
+    }
+
+    #[test]
+    fn reports_without_a_browser() {
+        let dir = TempTrkDir::new();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.new_session(None).unwrap();
+        sheet.note(None, String::from("reported")).unwrap();
+        sheet.no_open(true);
+        sheet.report_last_session().unwrap();
+        sheet.report_range(None, None, false).unwrap();
+        let read = |file: &str| fs::read_to_string(dir.path().join(file)).unwrap();
+        assert!(read("session.html").contains("reported"));
+        assert!(read("timesheet.html").contains("reported"));
+
+        /* A range that leaves the session out doesn't replace the whole sheet */
+        sheet.report_range(None, Some(1000), false).unwrap();
+        assert!(!read("timesheet-range.html").contains("reported"));
+        assert!(read("timesheet.html").contains("reported"));
+
+        sheet.base_dir = dir.path().join("gone");
+        match sheet.report_daily() {
+            Err(TrkError::Report(file, _)) => assert_eq!(file, "daily.html"),
+            _ => panic!("daily.html can't have been written"),
+        }
+    }
+
+    #[test]
+    fn status_json_lists_the_branches() {
+        let mut sheet = sheet_with(vec![Session::new(Some(1000))]);
+        sheet.pause(Some(1100), None).unwrap();
+        sheet.sessions[0].add_branch(String::from("main"));
+        sheet.sessions[0].add_branch(String::from("feature"));
+        let json = sheet.status_formatted("json").unwrap();
+        assert!(json.contains("\"running\":true,\"paused\":true,\"session_start\":1000,"));
+        assert!(json.contains("\"last_event\":\"pause\""));
+        assert!(json.ends_with("\"branches\":[\"feature\",\"main\"]}"));
+    }
+
+    #[test]
+    fn status_counts_no_pause_as_work() {
+        let hour = 3600;
+        let now = get_seconds();
+        let mut session = Session::new(Some(now - 4 * hour));
+        for &(pause, resume) in &[
+            (4 * hour - 600, 4 * hour - 2400),
+            (2 * hour, 2 * hour - 1800),
+        ] {
+            session
+                .push_event(Some(now - pause), None, EventType::Pause)
+                .unwrap();
+            session
+                .push_event(Some(now - resume), None, EventType::Resume)
+                .unwrap();
+        }
+        session
+            .push_event(Some(now - hour), None, EventType::Pause)
+            .unwrap();
+        /* 4 hours less two completed half hour pauses and the last hour */
+        assert_eq!(session.work_time(), 2 * hour);
+        let status = session.status();
+        assert!(status.contains(&format!(
+            "Session running since {}, worked for 2 hours.",
+            ts_to_date(now - 4 * hour)
+        )));
+        assert!(status.contains(&format!("Paused since {} (1 hour", ts_to_date(now - hour))));
+    }
+
+    #[test]
+    fn backdated_commits_go_where_they_were_made() {
+        /* Outside of git, so that no branch is recorded with the commits */
+        let _dir = TempTrkDir::new();
+        let now = get_seconds();
+        let commit = |session: &mut Session, hash: &str| {
+            let ev_ty = EventType::Commit {
+                hash: String::from(hash),
+            };
+            session
+                .push_event(None, Some(String::from("msg")), ev_ty)
+                .unwrap();
+        };
+        let mut session = Session::new(Some(now - 1000));
+        session
+            .push_event(Some(now - 900), None, EventType::Pause)
+            .unwrap();
+        session
+            .push_event(Some(now - 800), None, EventType::Resume)
+            .unwrap();
+        session
+            .push_event(Some(now - 500), Some(String::from("n")), EventType::Note)
+            .unwrap();
+        commit(&mut session, "a");
+        session.backdate_last_event(now - 600).unwrap();
+        let kinds = |session: &Session| -> Vec<String> {
+            session
+                .event_list()
+                .lines()
+                .map(|line| line[24..].trim_end().to_string())
+                .collect()
+        };
+        assert_eq!(
+            kinds(&session),
+            vec!["pause", "resume", "commit  msg", "note    n"]
+        );
+        assert!(session.first_bad_transition().is_none());
+
+        commit(&mut session, "b");
+        /* During the pause, before the start and later than now are refused */
+        assert!(session.backdate_last_event(now - 850).is_err());
+        assert!(session.backdate_last_event(now - 2000).is_err());
+        assert!(session.backdate_last_event(now + 60).is_err());
+        assert_eq!(kinds(&session).last().unwrap(), "commit  msg");
+        assert!(session.last_activity() >= now);
+    }
+
+    #[test]
+    fn markdown_has_a_section_per_session_and_totals() {
+        let mut sheet = sheet_with(vec![Session::new(Some(1000))]);
+        sheet
+            .note(Some(1100), String::from("*bold* [link](x) #1\nmore"))
+            .unwrap();
+        let commit = EventType::Commit {
+            hash: String::from("0123456789abcdef"),
+        };
+        let session = &mut sheet.sessions[0];
+        session
+            .push_event(Some(1200), Some(String::from("Fix_it\n")), commit)
+            .unwrap();
+        session.set_commit_branch(String::from("fix`it"));
+        let branch = EventType::Branch {
+            name: String::from("`odd"),
+        };
+        session.push_event(Some(1250), None, branch).unwrap();
+        let commit = EventType::Commit {
+            hash: String::from("\u{e4}bcdefghij"),
+        };
+        session.push_event(Some(1260), None, commit).unwrap();
+        session
+            .push_event(Some(1300), None, EventType::Pause)
+            .unwrap();
+        sheet.end_session(Some(1500)).unwrap();
+        let md = sheet.to_markdown();
+        assert!(md.starts_with("# Timesheet\n\n## Session on "));
+        assert!(md.contains(": Note: \\*bold\\* \\[link\\]\\(x\\) \\#1  \n  more\n"));
+        assert!(md.contains(": Commit `0123456` on ``fix`it``: Fix\\_it\n"));
+        assert!(md.contains(": Branch `` `odd ``\n"));
+        assert!(md.contains(": Commit `\u{e4}bcdefg`\n"));
+        assert!(md.contains(": Pause\n"));
+        assert!(md.contains("\nWorked: 5 minutes\nPaused: 3 minutes\n```\n"));
+        assert!(md.ends_with(
+            "## Total\n\n```\nSessions: 1\nWorked: 5 minutes\nPaused: 3 minutes\n```\n"
+        ));
+    }
+
+    #[test]
+    fn invoice_bills_rounded_days() {
+        use chrono::{Local, TimeZone};
+        let at = |d, h, m| Local.ymd(2017, 4, d).and_hms(h, m, 0).timestamp() as u64;
+        let mut sheet = sheet_with(vec![
+            /* 1h08m is 1h15m to the quarter, 1h52m30s rounds up to 2h */
+            Session::imported(at(3, 9, 0), at(3, 10, 8), BTreeSet::new(), None),
+            Session::imported(at(4, 9, 0), at(4, 10, 52) + 30, BTreeSet::new(), None),
+            Session::imported(at(9, 9, 0), at(9, 10, 0), BTreeSet::new(), None),
+        ]);
+        let (from, to) = (at(1, 0, 0), at(8, 0, 0));
+        let html = sheet.generate_invoice_html(None, from, to, 15);
+        assert!(html.contains("<p>Period: 2017-04-01 to 2017-04-07</p>"));
+        assert!(html.contains("<tr><td>2017-04-03</td><td>1.25</td><td></td></tr>"));
+        assert!(!html.contains("2017-04-09"));
+
+        sheet.set_rate(None, Some(String::from("EUR")));
+        /* A rate without a currency keeps the one set before */
+        sheet.set_rate(Some(9000), None);
+        assert_eq!(sheet.config.currency.as_deref(), Some("EUR"));
+        let html = sheet.generate_invoice_html(Some(8050), from, to, 15);
+        assert!(html.contains("<p>Rate: 80.50 EUR per hour</p>"));
+        assert!(html.contains("<tr><td>2017-04-03</td><td>1.25</td><td>100.63 EUR</td></tr>"));
+        assert!(html.contains("<tr><td>2017-04-04</td><td>2.00</td><td>161.00 EUR</td></tr>"));
+        assert!(html.contains("<tr><th>Total</th><th>3.25</th><th>261.63 EUR</th></tr>"));
+        /* Unrounded, a day is billed to the second */
+        let html = sheet.generate_invoice_html(Some(8050), from, to, 0);
+        assert!(html.contains("<tr><td>2017-04-03</td><td>1.13</td><td>91.23 EUR</td></tr>"));
+    }
+
+    #[test]
+    fn tex_invoice_bills_each_day_to_the_cent() {
+        use chrono::{Local, TimeZone};
+        let at = |d, h, m| Local.ymd(2017, 4, d).and_hms(h, m, 0).timestamp() as u64;
+        let mut sheet = sheet_with(vec![
+            Session::imported(at(3, 9, 0), at(3, 10, 8), BTreeSet::new(), None),
+            Session::imported(at(4, 9, 0), at(4, 10, 52) + 30, BTreeSet::new(), None),
+        ]);
+        let (from, to) = (at(1, 0, 0), at(8, 0, 0));
+        let tex = sheet.invoice_tex("ACME", None, from, to, Round::Nearest15);
+        assert!(tex.contains("2017-04-03 & 1.25 &  \\\\"));
+        assert!(!tex.contains("Rate:"));
+
+        sheet.set_rate(None, Some(String::from("$")));
+        let tex = sheet.invoice_tex("ACME", Some(8050), from, to, Round::Nearest15);
+        assert!(tex.contains("Rate: 80.50 \\$ per hour \\\\"));
+        assert!(tex.contains("2017-04-03 & 1.25 & 100.63 \\$ \\\\"));
+        assert!(tex.contains("2017-04-04 & 2.00 & 161.00 \\$ \\\\"));
+        /* The sum of the rounded days, not 3.25 hours at the rate */
+        assert!(tex.contains("Total & 3.25 & 261.63 \\$ \\\\"));
+    }
+
+    #[test]
+    fn timewarrior_intervals_become_sessions() {
+        let dir = TempTrkDir::new();
+        let export = dir.path().join("timew.json");
+        fs::write(
+            &export,
+            r#"[
+{"id":3,"start":"20170403T090000Z","end":"20170403T100000Z","tags":["bugfix"]},
+{"id":2,"start":"20170404T090000Z","end":"20170404T093000Z","tags":["feature","ui"],"annotation":"review"},
+{"id":1,"start":"20170405T090000Z"}
+]"#,
+        )
+        .unwrap();
+        let existing = Session::imported(1491211800, 1491215400, BTreeSet::new(), None);
+        let mut sheet = sheet_with(vec![existing]);
+
+        /* The first interval overlaps the existing session */
+        assert!(sheet.import_timewarrior(&export, false).is_err());
+        assert_eq!(sheet.session_count(), 1);
+
+        /* The open interval is left out */
+        assert_eq!(sheet.import_timewarrior(&export, true).unwrap(), 2);
+        let starts: Vec<u64> = sheet.sessions.iter().map(|session| session.start).collect();
+        assert_eq!(starts, vec![1491210000, 1491211800, 1491296400]);
+        let last = &sheet.sessions[2];
+        assert_eq!(last.end, 1491298200);
+        assert_eq!((sheet.start, sheet.end), (1491209999, 1491298201));
+        let branches: Vec<&String> = last.branches().iter().collect();
+        assert_eq!(branches, vec!["feature", "ui"]);
+        assert_eq!(parse_utc_basic("2017-04-03T09:00"), None);
+    }
+
+    #[test]
+    fn toggl_csv_reads_back() {
+        let dir = TempTrkDir::new();
+        let mut session = Session::new(Some(1491549156));
+        session
+            .push_event(
+                Some(1491549200),
+                Some(String::from("Fix, then test")),
+                EventType::Note,
+            )
+            .unwrap();
+        session
+            .push_event(Some(1491550000), None, EventType::Pause)
+            .unwrap();
+        session
+            .push_event(Some(1491550600), None, EventType::Resume)
+            .unwrap();
+        session.add_branch(String::from("master"));
+        session.add_branch(String::from("ui"));
+        session.finalize(Some(1491556356)).unwrap();
+        let mut sheet = sheet_with(vec![session]);
+        sheet.config.user_name = Some(String::from("Tester"));
+        let out = dir.path().join("toggl.csv");
+        assert!(sheet.export_toggl_csv(&out));
+
+        let records = parse_csv(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(records.len(), 2);
+        let header: Vec<&str> = records[0].1.iter().map(|h| h.as_str()).collect();
+        assert_eq!(
+            header,
+            vec![
+                "User",
+                "Email",
+                "Client",
+                "Project",
+                "Description",
+                "Start date",
+                "Start time",
+                "End date",
+                "End time",
+                "Duration",
+                "Tags"
+            ]
+        );
+        let row = &records[1].1;
+        assert_eq!(row[0], "Tester");
+        assert_eq!(row[4], "Fix, then test");
+        let at = |date: &str, time: &str| parse_timestamp(&format!("{}T{}", date, time)).unwrap();
+        assert_eq!(at(&row[5], &row[6]), 1491549156);
+        /* A second after the given end, see Session::finalize */
+        assert_eq!(at(&row[7], &row[8]), 1491556357);
+        /* From start to end, less the ten minute pause */
+        assert_eq!(row[9], "01:50:01");
+        let tags: Vec<&str> = row[10].split(',').map(|tag| tag.trim()).collect();
+        assert_eq!(tags, vec!["master", "ui"]);
+    }
+
+    #[test]
+    fn git_hook_is_chained_and_taken_out_again() {
+        use std::process::Command;
+        let dir = TempTrkDir::new();
+        let repo = dir.path().join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        let init = Command::new("git")
+            .args(&["init", "-q"])
+            .current_dir(&repo)
+            .status()
+            .unwrap();
+        assert!(init.success());
+        /* Like a worktree, the checkout's .git is a file pointing to the repository */
+        let checkout = dir.path().join("checkout");
+        fs::create_dir_all(&checkout).unwrap();
+        let gitdir = format!("gitdir: {}\n", repo.join(".git").display());
+        fs::write(checkout.join(".git"), gitdir).unwrap();
+        let hook = repo.join(".git").join("hooks").join("post-commit");
+        fs::write(&hook, "#!/bin/sh\necho committed\n").unwrap();
+
+        let mut sheet = sheet_with(vec![Session::imported(100, 200, BTreeSet::new(), None)]);
+        sheet.base_dir = checkout;
+        sheet.install_git_hook().unwrap();
+        sheet.install_git_hook().unwrap();
+        let script = fs::read_to_string(&hook).unwrap();
+        assert!(script.starts_with("#!/bin/sh\necho committed\ntrk commit $(git rev-parse HEAD)"));
+        assert_eq!(script.matches("trk commit").count(), 1);
+
+        sheet.uninstall_git_hook().unwrap();
+        assert_eq!(
+            fs::read_to_string(&hook).unwrap(),
+            "#!/bin/sh\necho committed\n"
+        );
+        assert!(sheet.uninstall_git_hook().is_err());
+
+        /* trk would never be reached */
+        fs::write(&hook, "#!/bin/sh\nexit 0\n").unwrap();
+        assert!(sheet.install_git_hook().is_err());
+
+        /* Already calling trk, like the post-commit that comes with it */
+        let shipped = include_str!("../../post-commit");
+        fs::write(&hook, shipped).unwrap();
+        sheet.install_git_hook().unwrap();
+        assert_eq!(fs::read_to_string(&hook).unwrap(), shipped);
+        assert!(!sheet.is_hook_call("# trk commit $(git rev-parse HEAD)"));
+
+        /* Another project's call is not this one's */
+        sheet.project = Some(String::from("acme"));
+        sheet.install_git_hook().unwrap();
+        let script = fs::read_to_string(&hook).unwrap();
+        assert!(script.starts_with(shipped));
+        assert!(script.ends_with(
+            "\nTRK_PROJECT=acme trk commit $(git rev-parse HEAD) # added by trk install_git_hook\n"
+        ));
+        assert_eq!(script.matches("trk commit").count(), 2);
+        sheet.install_git_hook().unwrap();
+        assert_eq!(fs::read_to_string(&hook).unwrap(), script);
+        sheet.uninstall_git_hook().unwrap();
+        assert_eq!(fs::read_to_string(&hook).unwrap(), shipped);
+    }
+
+    #[test]
+    fn events_record_the_checked_out_branch() {
+        use std::process::Command;
+        let dir = TempTrkDir::new();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(&[
+                    "-c",
+                    "user.name=Tester",
+                    "-c",
+                    "user.email=tester@example.com",
+                ])
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+        git(&["checkout", "-q", "-b", "feature"]);
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.new_session(None).unwrap();
+        git(&["checkout", "-q", "--detach"]);
+        sheet
+            .note(None, String::from("on a detached HEAD"))
+            .unwrap();
+        /* Entered for the past, it might have been on any branch */
+        git(&["checkout", "-q", "-b", "later"]);
+        let last = sheet.sessions[0].last_activity();
+        sheet.pause(Some(last + 1), None).unwrap();
+
+        let session = &sheet.sessions[0];
+        let branches = session.branches();
+        assert_eq!(branches.len(), 2);
+        assert!(branches.contains("feature"));
+        assert!(!branches.contains("later"));
+        let detached = branches.iter().find(|&name| name != "feature").unwrap();
+        assert!(detached.chars().all(|c| c.is_ascii_hexdigit()));
+        /* Recorded as switches, so no work goes unrecorded */
+        assert!(session.has_branch_switches());
+        assert!(!session.work_per_branch().contains_key(&None));
+        let kinds = |session: &Session| -> Vec<String> {
+            session
+                .event_list()
+                .lines()
+                .map(|line| line[24..].split_whitespace().next().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(kinds(session), vec!["branch", "branch", "note", "pause"]);
+
+        /* The switch comes after the resume, not during the pause */
+        sheet.resume(None).unwrap();
+        assert!(sheet.sessions[0].branches().contains("later"));
+        assert_eq!(
+            kinds(&sheet.sessions[0])[4..],
+            ["resume".to_string(), "branch".to_string()]
+        );
+    }
+
+    #[test]
+    fn loading_writes_nothing() {
+        let dir = TempTrkDir::new();
+        Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        let trk = dir.path().join(".trk");
+        for name in &[".gitignore", "style.css", "no_git_info.css"] {
+            fs::remove_file(trk.join(name)).unwrap();
+        }
+        /* Read-only commands only hold the shared lock, see main */
+        let mut sheet = Timesheet::load(dir.path()).unwrap();
+        for name in &[".gitignore", "style.css", "no_git_info.css"] {
+            assert!(!trk.join(name).exists());
+        }
+        sheet.write_files().unwrap();
+        for name in &[".gitignore", "style.css", "no_git_info.css"] {
+            assert!(trk.join(name).exists());
+        }
+    }
+
+    #[test]
+    fn pending_commits_keep_their_time_in_a_repository() {
+        use std::process::Command;
+        let dir = TempTrkDir::new();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(&[
+                    "-c",
+                    "user.name=Tester",
+                    "-c",
+                    "user.email=tester@example.com",
+                ])
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+        git(&["checkout", "-q", "-b", "feature"]);
+        let now = get_seconds();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.start = now - 1000;
+        sheet
+            .write_pending(&[(now - 300, String::from("abc"))])
+            .unwrap();
+        sheet.new_session(None).unwrap();
+
+        let session = &sheet.sessions[0];
+        assert_eq!(session.start, now - 301);
+        assert_eq!(session.commit_count(), 1);
+        let events: Vec<String> = session.event_list().lines().map(String::from).collect();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].contains(&format!("{}  commit", ts_to_date(now - 300))));
+        assert!(events[1].contains("branch"));
+        assert!(session.branches().contains("feature"));
+    }
+
+    #[test]
+    fn fresh_session_works_from_its_start() {
+        let now = get_seconds();
+        let session = Session::new(Some(now - 5));
+        assert!(session.is_running());
+        /* Not the stored end of start + 1, and no second lost or gained */
+        assert!((5..=7).contains(&session.work_time()));
+        assert!((5..=7).contains(&session.elapsed()));
+        assert_eq!(session.pause_time(), 0);
+        let sheet = sheet_with(vec![session]);
+        assert!((5..=7).contains(&sheet.work_time()));
+    }
+
+    #[test]
+    fn unwritable_report_is_an_error() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempTrkDir::new();
+        let mut sheet = Timesheet::init(dir.path(), Some("Tester")).unwrap();
+        sheet.new_session(None).unwrap();
+        sheet.no_open(true);
+        let _ = fs::remove_file(dir.path().join("session.html"));
+
+        let writable = |mode: u32| {
+            fs::set_permissions(dir.path(), fs::Permissions::from_mode(mode)).unwrap();
+        };
+        writable(0o555);
+        /* root writes into read-only directories anyway */
+        let root = fs::write(dir.path().join("probe"), "").is_ok();
+        let reported = sheet.report_last_session();
+        writable(0o755);
+        if !root {
+            match reported {
+                Err(TrkError::Report(ref file, _)) => assert_eq!(file, "session.html"),
+                other => panic!("expected a report error, got {:?}", other),
+            }
+            assert!(!dir.path().join("session.html").exists());
+        }
+
+        /* Nothing can be written where a directory is in the way. As root the
+         * report was written above, so it goes first. */
+        let _ = fs::remove_file(dir.path().join("session.html"));
+        fs::create_dir(dir.path().join("session.html")).unwrap();
+        match sheet.report_last_session() {
+            Err(TrkError::Report(ref file, _)) => assert_eq!(file, "session.html"),
+            other => panic!("expected a report error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overlapping_sessions_are_found_and_trimmed() {
+        let mut sheet = sheet_with(vec![
+            Session::imported(1000, 2000, BTreeSet::new(), None),
+            Session::imported(1500, 2500, BTreeSet::new(), None),
+            Session::imported(3000, 4000, BTreeSet::new(), None),
+        ]);
+        assert_eq!(sheet.overlapping_sessions(), vec![(0, 1)]);
+        let problems = sheet.problems();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("Sessions 0 ("));
+        assert!(problems[0].contains(") and 1 ("));
+
+        assert!(sheet.repair().is_empty());
+        assert!(sheet.overlapping_sessions().is_empty());
+        assert_eq!(sheet.sessions[0].end, 1500);
+        assert_eq!(sheet.sessions[1].start, 1500);
+    }
+
+    #[test]
+    fn anonymized_export_keeps_no_names_or_settings() {
+        let mut session = Session::new(Some(1000));
+        session
+            .push_event(
+                Some(1100),
+                Some(String::from("Fixed the parser")),
+                EventType::Commit {
+                    hash: String::from("abc123"),
+                },
+            )
+            .unwrap();
+        session.set_commit_branch(String::from("commit-branch"));
+        session.add_branch(String::from("secret-feature"));
+        session.finalize(Some(2000)).unwrap();
+        let mut sheet = sheet_with(vec![session]);
+        sheet.config.user_name = Some(String::from("Tester"));
+        sheet.config.anon_salt = Some(String::from("pepper"));
+        sheet.config.default_note = Some(String::from("For ACME"));
+        sheet.config.issue_url = Some(String::from("https://acme.example/{}"));
+        sheet.config.rate_cents_per_hour = Some(9000);
+        sheet.config.currency = Some(String::from("EUR"));
+
+        let anon = sheet.anonymized(None);
+        let json = to_string(&anon).unwrap();
+        for secret in &["Tester", "pepper", "ACME", "acme.example", "EUR"] {
+            assert!(!json.contains(secret), "{} in {}", secret, json);
+        }
+        assert_eq!(anon.config.rate_cents_per_hour, None);
+        for secret in &["secret-feature", "commit-branch", "abc123", "parser"] {
+            assert!(!json.contains(secret), "{} in {}", secret, json);
+        }
+        /* The same salt from the settings gives the same hashes */
+        assert!(json.contains(&salted_hash("pepper", "abc123")));
+        assert!(json.contains(&salted_hash("pepper", "commit-branch")));
+        assert_eq!(anon.work_time(), sheet.work_time());
+    }
+
+    #[test]
+    fn imported_sessions_widen_the_sheet() {
+        let mut sheet = sheet_with(vec![Session::imported(5000, 6000, BTreeSet::new(), None)]);
+        /* As if loaded after write_files */
+        sheet.cached_stats = Some(sheet.finished_stats());
+        let csv = "start,end,branches,note\n1000,2000,,\n7000,8000,master,late\n";
+        assert_eq!(sheet.import_csv(csv, false), Ok(2));
+        assert_eq!((sheet.start, sheet.end), (999, 8001));
+        let starts: Vec<u64> = sheet.sessions.iter().map(|session| session.start).collect();
+        assert_eq!(starts, vec![1000, 5000, 7000]);
     }
 }
@@ -2,6 +2,10 @@ pub trait HasTEX {
     fn to_tex(&self) -> String;
 }
 
+pub trait HasMarkdown {
+    fn to_markdown(&self) -> String;
+}
+
 pub trait HasHTML {
     fn to_html(&self) -> String;
 }
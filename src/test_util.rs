@@ -0,0 +1,49 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/* The current directory is global to the process, so tests using it take turns */
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+static DIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/** An empty temporary directory that is the current directory while this lives.
+ * It is removed again and the previous current directory restored on drop. */
+pub struct TempTrkDir {
+    path: PathBuf,
+    previous: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TempTrkDir {
+    pub fn new() -> TempTrkDir {
+        /* A failed test poisons the lock, which doesn't matter for the next one */
+        let lock = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = env::temp_dir().join(format!(
+            "trk-test-{}-{}",
+            process::id(),
+            DIR_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        let previous = env::current_dir().unwrap();
+        env::set_current_dir(&path).unwrap();
+        TempTrkDir {
+            path,
+            previous,
+            _lock: lock,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempTrkDir {
+    fn drop(&mut self) {
+        env::set_current_dir(&self.previous).unwrap();
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
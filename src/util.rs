@@ -1,14 +1,27 @@
 use chrono::Duration;
-use chrono::{Local, TimeZone};
-use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{
+    Date, DateTime, Datelike, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone,
+};
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{self, SystemTime, UNIX_EPOCH};
 
 use nom;
 use nom::IResult::Done;
 
-/* For running git and html-tidy */
+use url::Url;
+
+#[cfg(feature = "idle")]
+use user_idle;
+
+/* For running git */
 use std::process::Command;
 
 use std::env;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
 
 /* For from::utf8 */
 use std::str;
@@ -20,6 +33,178 @@ pub fn get_seconds() -> u64 {
         .as_secs()
 }
 
+/* A salt that differs between runs */
+pub fn random_salt() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!("{}{}", now.as_secs(), now.subsec_nanos())
+}
+
+/* Round constants of SHA-256, the first 32 bits of the fractional parts of
+ * the cube roots of the first 64 primes */
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/* SHA-256 digest of data (FIPS 180-4) */
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let mut v = state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let choice = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let majority = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(majority);
+            v = [
+                t1.wrapping_add(t2),
+                v[0],
+                v[1],
+                v[2],
+                v[3].wrapping_add(t1),
+                v[4],
+                v[5],
+                v[6],
+            ];
+        }
+        for (word, added) in state.iter_mut().zip(v.iter()) {
+            *word = word.wrapping_add(*added);
+        }
+    }
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/* HMAC-SHA256 of message under key (RFC 2104) */
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+/** Stable stand-in for value, the same for equal salt and value with any
+ * build of trk: the first 8 bytes of HMAC-SHA256 keyed with salt, in hex.
+ * Without the salt it can't be told which value it stands for. */
+pub fn salted_hash(salt: &str, value: &str) -> String {
+    hmac_sha256(salt.as_bytes(), value.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/** The first length characters of a commit hash, all of it if it is shorter.
+ * Hashes come from the command line and imports, so they needn't be hex. */
+pub fn short_hash(hash: &str, length: usize) -> &str {
+    match hash.char_indices().nth(length) {
+        Some((end, _)) => &hash[..end],
+        None => hash,
+    }
+}
+
+/** Quotes a CSV field if it contains separators, quotes or line breaks */
+pub fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/** Splits csv content into records of fields, each with the line number it starts on.
+ * Quoted fields may contain separators, doubled quotes and line breaks. */
+pub fn parse_csv(content: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() => in_quotes = true,
+            '"' => return Err(format!("Line {}: stray quote in unquoted field.", line)),
+            '\n' if in_quotes => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !in_quotes => record.push(field.split_off(0)),
+            '\r' if !in_quotes => {}
+            '\n' => {
+                record.push(field.split_off(0));
+                records.push((record_line, record.split_off(0)));
+                line += 1;
+                record_line = line;
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!("Line {}: unterminated quote.", record_line));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+    /* Skip blank lines */
+    Ok(records
+        .into_iter()
+        .filter(|&(_, ref fields)| !(fields.len() == 1 && fields[0].trim().is_empty()))
+        .collect())
+}
+
 pub fn ts_to_date(timestamp: u64) -> String {
     Local
         .timestamp(timestamp as i64, 0)
@@ -27,21 +212,455 @@ pub fn ts_to_date(timestamp: u64) -> String {
         .to_string()
 }
 
-pub fn sec_to_hms_string(seconds: u64) -> String {
-    let hours = seconds / 3600;
-    let minutes = (seconds - hours * 3600) / 60;
-    let seconds = seconds - minutes * 60 - hours * 3600;
-    match (hours, minutes, seconds) {
-        (0, 0, 1) => String::from("1 second"),
-        (0, 0, s) => format!("{} seconds", s),
-        (0, 1, _) => String::from("1 minute"),
-        (0, m, _) => format!("{} minutes", m),
-        /* Range matching: slightly dubious feature here */
-        (1, 0..=4, _) => String::from("1 hour"),
-        (h, 0..=4, _) => format!("{} hours", h),
-        (h, 56..=59, _) => format!("{} hours", h + 1),
-        (h, m, _) => format!("{} hours and {} minutes", h, m),
+/** Local date and time of day apart, e.g. ("2017-04-07", "09:12:36") */
+pub fn ts_to_date_and_time(timestamp: u64) -> (String, String) {
+    let datetime = Local.timestamp(timestamp as i64, 0);
+    (
+        datetime.format("%Y-%m-%d").to_string(),
+        datetime.format("%H:%M:%S").to_string(),
+    )
+}
+
+/** Duration as HH:MM:SS, with more digits for hours if need be */
+pub fn sec_to_hh_mm_ss(seconds: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/** timestamp rounded to the nearest multiple of minutes, halves up.
+ * Unchanged for 0 minutes. For display only, storage keeps the seconds. */
+pub fn round_ts(timestamp: u64, minutes: u64) -> u64 {
+    if minutes == 0 {
+        return timestamp;
+    }
+    let step = minutes * 60;
+    (timestamp + step / 2) / step * step
+}
+
+/** Money amount like "85", "85.5" or "85.50" in cents */
+pub fn parse_cents(amount: &str) -> Option<u64> {
+    let (whole, fraction) = amount.trim().split_once('.').unwrap_or((amount.trim(), ""));
+    let digits = |text: &str| text.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() || fraction.len() > 2 || !digits(whole) || !digits(fraction) {
+        return None;
+    }
+    let cents = format!("{:0<2}", fraction).parse::<u64>().ok()?;
+    whole
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(100)?
+        .checked_add(cents)
+}
+
+/** e.g. "1234.50 EUR", or without currency "1234.50" */
+pub fn format_cents(cents: u64, currency: Option<&str>) -> String {
+    let amount = format!("{}.{:02}", cents / 100, cents % 100);
+    match currency {
+        Some(currency) => format!("{} {}", amount, currency),
+        None => amount,
+    }
+}
+
+/** Seconds since the last keyboard or mouse input, if built with the idle
+ * feature and the system can tell */
+#[cfg(feature = "idle")]
+pub fn system_idle_seconds() -> Option<u64> {
+    user_idle::UserIdle::get_time()
+        .ok()
+        .map(|idle| idle.as_seconds())
+}
+
+#[cfg(not(feature = "idle"))]
+pub fn system_idle_seconds() -> Option<u64> {
+    None
+}
+
+/** Lets whoever may read the file at path also run it, e.g. a git hook.
+ * The permissions it had are kept otherwise. */
+#[cfg(unix)]
+pub fn make_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)?.permissions().mode();
+    std::fs::set_permissions(
+        path,
+        std::fs::Permissions::from_mode(mode | (mode & 0o444) >> 2),
+    )
+}
+
+#[cfg(not(unix))]
+pub fn make_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/** Local calendar day of timestamp, e.g. 2017-10-29 */
+pub fn ts_to_day(timestamp: u64) -> String {
+    Local
+        .timestamp(timestamp as i64, 0)
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/** Local day of the week of timestamp, 0 for Monday */
+pub fn ts_weekday(timestamp: u64) -> u32 {
+    Local
+        .timestamp(timestamp as i64, 0)
+        .weekday()
+        .num_days_from_monday()
+}
+
+/** First local midnight after timestamp. Days around DST changes last 23
+ * or 25 hours, so this asks chrono instead of adding 86400, unless the
+ * time zone has no start for that day. Always after timestamp. */
+pub fn next_local_midnight(timestamp: u64) -> u64 {
+    local_midnight(Local.timestamp(timestamp as i64, 0).date().succ())
+        .filter(|&midnight| midnight > timestamp)
+        .unwrap_or(timestamp + 86400)
+}
+
+/** Local midnight starting the day that contains timestamp, never after it */
+pub fn local_day_start(timestamp: u64) -> u64 {
+    local_midnight(Local.timestamp(timestamp as i64, 0).date())
+        .filter(|&midnight| midnight <= timestamp)
+        .unwrap_or(timestamp - timestamp % 86400)
+}
+
+/** Offset of local time at timestamp from UTC, e.g. +02:00 */
+pub fn utc_offset(timestamp: u64) -> String {
+    Local
+        .timestamp(timestamp as i64, 0)
+        .format("%:z")
+        .to_string()
+}
+
+/* Start of day, which is 01:00 where DST begins at midnight. None if
+ * neither exists or it is before the epoch. */
+fn local_midnight(day: Date<Local>) -> Option<u64> {
+    day.and_hms_opt(0, 0, 0)
+        .or_else(|| day.and_hms_opt(1, 0, 0))
+        .map(|midnight| midnight.timestamp())
+        .filter(|&midnight| midnight >= 0)
+        .map(|midnight| midnight as u64)
+}
+
+/** Text with html's special characters escaped, for element content as
+ * well as attribute values */
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/** Text with LaTeX's special characters escaped */
+pub fn tex_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/** Text with Markdown's special characters escaped, so notes show as typed */
+pub fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_{}[]<>()#+-!|~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/** text as a Markdown code span. Backslashes don't escape in there, so the
+ * fence has more backticks than text has in a row, and is set apart by
+ * spaces if text begins or ends with one. */
+pub fn markdown_code(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest + 1);
+    let space = if text.starts_with('`') || text.ends_with('`') {
+        " "
+    } else {
+        ""
+    };
+    format!("{}{}{}{}{}", fence, space, text, space, fence)
+}
+
+/* Whether paint adds colors. Off unless set_color says so, so files stay plain. */
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+pub enum Color {
+    Green,
+    Yellow,
+    Cyan,
+}
+
+/* Whether NO_COLOR asks for plain output, which by no-color.org only a
+ * value that isn't empty does */
+fn no_color(value: Option<&OsStr>) -> bool {
+    value.map_or(false, |value| !value.is_empty())
+}
+
+/** Colors terminal output "always", "never" or, for "auto", if stdout is
+ * a terminal and NO_COLOR isn't set to something */
+pub fn set_color(when: &str) -> Result<(), String> {
+    let on = match when {
+        "always" => true,
+        "never" => false,
+        "auto" => !no_color(env::var_os("NO_COLOR").as_deref()) && io::stdout().is_terminal(),
+        _ => {
+            return Err(format!(
+                "What do you mean by {}? Should be either 'auto', 'always' or 'never'.",
+                when
+            ))
+        }
+    };
+    COLOR.store(on, Ordering::Relaxed);
+    Ok(())
+}
+
+/** text in color if colors are on, for terminal output only */
+pub fn paint(text: &str, color: Color) -> String {
+    if !COLOR.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    let code = match color {
+        Color::Green => 32,
+        Color::Yellow => 33,
+        Color::Cyan => 36,
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/** Compact duration like 45s, 20m or 1h5m */
+pub fn sec_to_short_string(seconds: u64) -> String {
+    match (seconds / 3600, seconds % 3600 / 60) {
+        (0, 0) => format!("{}s", seconds),
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h{}m", h, m),
+    }
+}
+
+/* Calendar periods to group sessions by */
+pub enum Period {
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    /** Label of the local period containing timestamp, e.g. 2017-W14, 2017-04 or 2017 */
+    pub fn label(&self, timestamp: u64) -> String {
+        let format = match *self {
+            Period::Week => "%G-W%V",
+            Period::Month => "%Y-%m",
+            Period::Year => "%Y",
+        };
+        Local
+            .timestamp(timestamp as i64, 0)
+            .format(format)
+            .to_string()
+    }
+
+    /** Start of the local period containing timestamp and start of the one before */
+    pub fn starts(&self, timestamp: u64) -> (u64, u64) {
+        let today = Local.timestamp(timestamp as i64, 0).date();
+        let (start, previous) = match *self {
+            Period::Week => {
+                let start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+                (start.clone(), start - Duration::days(7))
+            }
+            Period::Month => {
+                let start = today.with_day(1).unwrap();
+                let previous = if start.month() == 1 {
+                    start
+                        .with_year(start.year() - 1)
+                        .and_then(|d| d.with_month(12))
+                } else {
+                    start.with_month(start.month() - 1)
+                };
+                (start.clone(), previous.unwrap())
+            }
+            Period::Year => {
+                let start = today.with_ordinal(1).unwrap();
+                (start.clone(), start.with_year(start.year() - 1).unwrap())
+            }
+        };
+        /* Neither after timestamp nor the previous after the start, whatever the time zone */
+        let start = local_midnight(start)
+            .filter(|&start| start <= timestamp)
+            .unwrap_or(timestamp);
+        let previous = local_midnight(previous)
+            .filter(|&previous| previous <= start)
+            .unwrap_or(start);
+        (start, previous)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Year => "year",
+        }
+    }
+}
+
+/** Units format_duration can break a duration into, largest first */
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum TimeUnit {
+    Days,
+    Hours,
+    Minutes,
+    Seconds,
+}
+
+/** Rounding of a duration before format_duration cuts it down to units.
+ * Halves round up, e.g. 7m30s is 15 minutes to the nearest quarter. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Round {
+    None,
+    Nearest15,
+    NearestHour,
+}
+
+impl Round {
+    /** "none", "15m" or "1h" */
+    pub fn from_name(name: &str) -> Option<Round> {
+        match name {
+            "none" => Some(Round::None),
+            "15m" => Some(Round::Nearest15),
+            "1h" => Some(Round::NearestHour),
+            _ => None,
+        }
+    }
+
+    /** Granularity in minutes, 0 for none */
+    pub fn minutes(self) -> u64 {
+        match self {
+            Round::None => 0,
+            Round::Nearest15 => 15,
+            Round::NearestHour => 60,
+        }
+    }
+
+    pub fn apply(self, seconds: u64) -> u64 {
+        round_ts(seconds, self.minutes())
+    }
+}
+
+/** How format_duration renders: rounded how, down to which unit (the rest
+ * is cut off), how many units at most, and "2d 3h" (compact) or
+ * "2 days and 3 hours" */
+#[derive(Clone, Copy, Debug)]
+pub struct DurationFmt {
+    pub round: Round,
+    pub smallest: TimeUnit,
+    pub max_units: usize,
+    pub compact: bool,
+}
+
+impl Default for DurationFmt {
+    fn default() -> DurationFmt {
+        DurationFmt {
+            round: Round::None,
+            smallest: TimeUnit::Seconds,
+            max_units: 2,
+            compact: false,
+        }
+    }
+}
+
+/** Duration in days, hours, minutes and seconds as described by fmt.
+ * Counting starts at the largest non-zero unit, zero units are left out. */
+pub fn format_duration(seconds: u64, fmt: DurationFmt) -> String {
+    let seconds = fmt.round.apply(seconds);
+    let units = [
+        (TimeUnit::Days, 86400, "d", "day"),
+        (TimeUnit::Hours, 3600, "h", "hour"),
+        (TimeUnit::Minutes, 60, "m", "minute"),
+        (TimeUnit::Seconds, 1, "s", "second"),
+    ];
+    let units: Vec<_> = units
+        .iter()
+        .filter(|&&(unit, _, _, _)| unit <= fmt.smallest)
+        .collect();
+    let name = |count: u64, short: &str, long: &str| {
+        if fmt.compact {
+            format!("{}{}", count, short)
+        } else if count == 1 {
+            format!("1 {}", long)
+        } else {
+            format!("{} {}s", count, long)
+        }
+    };
+
+    let mut rest = seconds;
+    let mut parts = Vec::new();
+    let mut counted = 0;
+    for &&(_, size, short, long) in &units {
+        let count = rest / size;
+        rest %= size;
+        if count > 0 || counted > 0 {
+            counted += 1;
+            if count > 0 {
+                parts.push(name(count, short, long));
+            }
+            if counted == fmt.max_units.max(1) {
+                break;
+            }
+        }
+    }
+    if parts.is_empty() {
+        let &&(_, _, short, long) = units.last().unwrap();
+        return name(0, short, long);
     }
+    if fmt.compact {
+        return parts.join(" ");
+    }
+    let last = parts.pop().unwrap();
+    if parts.is_empty() {
+        last
+    } else {
+        format!("{} and {}", parts.join(", "), last)
+    }
+}
+
+/** e.g. "45 seconds", "20 minutes" or "1 day and 2 hours" */
+pub fn sec_to_hms_string(seconds: u64) -> String {
+    let smallest = if seconds < 60 {
+        TimeUnit::Seconds
+    } else {
+        TimeUnit::Minutes
+    };
+    format_duration(
+        seconds,
+        DurationFmt {
+            smallest,
+            ..DurationFmt::default()
+        },
+    )
 }
 
 /* For parsing time in HH:MM format. */
@@ -63,42 +682,301 @@ pub fn parse_hhmm_to_seconds(timestr: &str) -> Option<u64> {
     }
 }
 
-pub fn set_to_trk_dir() -> bool {
+/** Parses a relative duration like 30m, 2h, 1h30m or 90s into seconds.
+ * None if it can't be read, or is too long to count in seconds. */
+pub fn parse_duration_units(input: &str) -> Option<u64> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in input.chars() {
+        if c.is_digit(10) {
+            number.push(c);
+            continue;
+        }
+        let factor = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let value = match number.parse::<u64>() {
+            Ok(value) => value,
+            Err(..) => return None,
+        };
+        total = match value
+            .checked_mul(factor)
+            .and_then(|part| total.checked_add(part))
+        {
+            Some(total) => total,
+            None => return None,
+        };
+        number.clear();
+    }
+    /* Trailing digits without a unit are ambiguous */
+    if number.is_empty() && total > 0 {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+fn local_to_seconds(datetime: &NaiveDateTime) -> Option<u64> {
+    match Local.from_local_datetime(datetime) {
+        LocalResult::Single(datetime) if datetime.timestamp() >= 0 => {
+            Some(datetime.timestamp() as u64)
+        }
+        _ => None,
+    }
+}
+
+/** Parses a point in time given as Unix seconds (1491549156),
+ * ISO 8601 (2017-04-07T09:12), a time of today (09:12) or yesterday
+ * (yesterday 14:00), or relative to now (-30m, -1h30m, or +30m for a
+ * planned resume).
+ * Times without an offset are interpreted in the local timezone. */
+pub fn parse_timestamp(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let parsed = if input.starts_with('-') {
+        parse_duration_units(&input[1..]).and_then(|ago| get_seconds().checked_sub(ago))
+    } else if input.starts_with('+') {
+        parse_duration_units(&input[1..]).and_then(|ahead| get_seconds().checked_add(ahead))
+    } else if !input.is_empty() && input.chars().all(|c| c.is_digit(10)) {
+        input.parse::<u64>().ok()
+    } else if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        let timestamp = datetime.timestamp();
+        if timestamp >= 0 {
+            Some(timestamp as u64)
+        } else {
+            None
+        }
+    } else if let Ok(time) = NaiveTime::parse_from_str(input, "%H:%M") {
+        local_to_seconds(&Local::now().naive_local().date().and_time(time))
+    } else if input.starts_with("yesterday ") {
+        NaiveTime::parse_from_str(input["yesterday ".len()..].trim(), "%H:%M")
+            .ok()
+            .and_then(|time| {
+                local_to_seconds(&Local::now().naive_local().date().pred().and_time(time))
+            })
+    } else {
+        ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+            .iter()
+            .filter_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+            .next()
+            .and_then(|datetime| local_to_seconds(&datetime))
+    };
+    parsed.ok_or_else(|| {
+        format!(
+            "Could not read time '{}'. Try one of: 1491549156 (Unix seconds), \
+             2017-04-07T09:12 (ISO 8601), 09:12 (today), yesterday 14:00, \
+             -30m, -1h30m (ago) or +30m (ahead).",
+            input
+        )
+    })
+}
+
+/** Like parse_timestamp for the end of a range, which may also be a date
+ * like 2017-04-07. That is the last second of the day, so that the range
+ * takes in all of it. */
+pub fn parse_until(input: &str) -> Result<u64, String> {
+    match NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d") {
+        /* Noon exists on any day, midnight not always */
+        Ok(date) => local_to_seconds(&date.and_hms(12, 0, 0))
+            .map(|noon| next_local_midnight(noon) - 1)
+            .ok_or_else(|| format!("Could not read date '{}'.", input.trim())),
+        Err(..) => parse_timestamp(input),
+    }
+}
+
+/** Parses a UTC time in ISO 8601 basic format as Timewarrior writes it,
+ * e.g. 20240115T090000Z. */
+pub fn parse_utc_basic(input: &str) -> Option<u64> {
+    NaiveDateTime::parse_from_str(input.trim(), "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|datetime| datetime.timestamp())
+        .filter(|&timestamp| timestamp >= 0)
+        .map(|timestamp| timestamp as u64)
+}
+
+/** Parses how long ago something was: HH:MM (02:30), units (30m, 2h,
+ * 1h30m), or any point in time that parse_timestamp reads, e.g.
+ * yesterday 14:00 or 2024-01-15T09:00:00. Returns Unix seconds. */
+pub fn parse_relative(input: &str) -> Option<u64> {
+    let input = input.trim();
+    match parse_hhmm_to_seconds(input).or_else(|| parse_duration_units(input)) {
+        Some(ago) => get_seconds().checked_sub(ago),
+        None => parse_timestamp(input).ok(),
+    }
+}
+
+/** Name of the repository at repo_url, i.e. the last path segment minus .git.
+ * Handles scp-like urls (git@host:user/repo.git) as well. */
+pub fn repo_name(repo_url: &str) -> Option<String> {
+    let path = match Url::parse(repo_url) {
+        Ok(url) => url.path().to_string(),
+        Err(..) => repo_url.rsplit(':').next().unwrap_or("").to_string(),
+    };
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .map(|name| name.trim_end_matches(".git"))
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+}
+
+/** Web page of commit hash in the repository at repo_url, following
+ * the conventions of GitHub, GitLab and Bitbucket (and GitHub's for other
+ * hosts). ssh and scp-like urls (git@host:user/repo.git) are taken to
+ * have the page at https://host/user/repo. */
+pub fn commit_url(repo_url: &str, hash: &str) -> Option<String> {
+    let repo_url = repo_url.trim().trim_end_matches('/');
+    let base = match Url::parse(repo_url) {
+        Ok(url) => match (url.scheme(), url.host_str()) {
+            ("http", _) | ("https", _) => repo_url.to_string(),
+            ("ssh", Some(host)) | ("git", Some(host)) => format!("https://{}{}", host, url.path()),
+            _ => return None,
+        },
+        Err(..) => {
+            let (host, path) = repo_url.split_once(':')?;
+            let host = host.rsplit('@').next().unwrap_or(host);
+            format!("https://{}/{}", host, path.trim_start_matches('/'))
+        }
+    };
+    let base = base.trim_end_matches(".git");
+    let host = Url::parse(base).ok()?.host_str()?.to_string();
+    let page = if host.contains("bitbucket") {
+        "commits"
+    } else if host.contains("gitlab") {
+        "-/commit"
+    } else {
+        "commit"
+    };
+    Some(format!("{}/{}/{}", base, page, hash))
+}
+
+/** The nearest directory containing a .trk directory,
+ * starting at the current directory and going up */
+pub fn find_trk_root() -> Option<PathBuf> {
     let mut path = env::current_dir().unwrap();
     loop {
-        path.push(".trk");
-        if path.exists() {
-            path.pop();
-            env::set_current_dir(&path).unwrap();
-            return true;
-        } else {
-            path.pop();
-            if !path.pop() {
-                println!("Fatal: not a .trk directory (or subdirectory of one).");
-                return false;
+        if path.join(".trk").is_dir() {
+            return Some(path);
+        }
+        if !path.pop() {
+            return None;
+        }
+    }
+}
+
+/** Advisory lock on .trk/lock, held until dropped. Commands that change
+ * the timesheet hold it exclusively from loading it to writing it back,
+ * commands that only read it share it. */
+pub struct TrkLock {
+    _file: File,
+}
+
+impl TrkLock {
+    /** Waits up to timeout for the lock, Err(WouldBlock) if another trk
+     * process keeps holding it */
+    pub fn acquire(
+        trk_dir: &Path,
+        exclusive: bool,
+        timeout: time::Duration,
+    ) -> io::Result<TrkLock> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(trk_dir.join("lock"))?;
+        let started = time::Instant::now();
+        loop {
+            let locked = if exclusive {
+                file.try_lock()
+            } else {
+                file.try_lock_shared()
+            };
+            match locked {
+                Ok(()) => return Ok(TrkLock { _file: file }),
+                Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                    thread::sleep(time::Duration::from_millis(50))
+                }
+                Err(TryLockError::WouldBlock) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(TryLockError::Error(e)) => return Err(e),
             }
         }
     }
 }
 
-pub fn git_init_trk() -> bool {
-    if !set_to_trk_dir() {
-        println!(
-            "Could not initialise trk internal git repo!\
-                 (couldn't find upper level .trk dir)."
-        );
-        return false;
+/** Directory of the global timesheet spanning all repositories:
+ * $XDG_DATA_HOME/trk, or ~/.local/share/trk if that isn't set */
+pub fn global_trk_root() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(ref data_home) if !data_home.is_empty() => Some(PathBuf::from(data_home)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")),
     }
+    .map(|data_home| data_home.join("trk"))
+}
 
-    let mut path = env::current_dir().unwrap();
-    path.push(".trk");
-    if path.exists() {
-        env::set_current_dir(&path).unwrap();
+/** Name of the git repository around the current directory,
+ * or the name of the directory itself outside of one */
+pub fn current_repo_name() -> Option<String> {
+    let output = Command::new("git")
+        .args(&["rev-parse", "--show-toplevel"])
+        .output();
+    let dir = match output {
+        Ok(ref output) if output.status.success() => {
+            PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+        }
+        _ => env::current_dir().ok()?,
+    };
+    dir.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+pub fn set_to_trk_dir() -> bool {
+    match find_trk_root() {
+        Some(path) => {
+            env::set_current_dir(&path).unwrap();
+            true
+        }
+        None => {
+            println!(
+                "Fatal: not a .trk directory (or subdirectory of one). \
+                 Run 'trk init' in the project's root first."
+            );
+            false
+        }
+    }
+}
+
+/* git pathspec of the timesheets of all projects, timesheet.json and
+ * timesheet-<project>.json, but not their backups */
+const TRK_SHEETS: &str = "timesheet*.json";
+
+/* .trk directory of root, says so and None if it isn't there */
+fn trk_git_dir(root: &Path, action: &str) -> Option<PathBuf> {
+    let path = root.join(".trk");
+    if path.is_dir() {
+        Some(path)
     } else {
-        println!("Couldn't access .trk sub directory to initialise trk internal git repo.");
-        return false;
+        println!(
+            "Couldn't access {} to {} trk internal git repo.",
+            path.display(),
+            action
+        );
+        None
     }
-    let output = Command::new("git").arg("init").output();
+}
+
+/** Makes root/.trk a git repository tracking the timesheets. The git
+ * commands run there rather than in the current directory, which stays
+ * in the repository trk was called in, see --global. */
+pub fn git_init_trk(root: &Path) -> bool {
+    let path = match trk_git_dir(root, "initialise") {
+        Some(path) => path,
+        None => return false,
+    };
+    let output = Command::new("git").arg("init").current_dir(&path).output();
     match output {
         Ok(_) => {}
         Err(_) => {
@@ -108,7 +986,8 @@ pub fn git_init_trk() -> bool {
     }
     let output = Command::new("git")
         .arg("add")
-        .arg("timesheet.json")
+        .arg(TRK_SHEETS)
+        .current_dir(&path)
         .output();
     match output {
         Ok(_) => {}
@@ -117,36 +996,30 @@ pub fn git_init_trk() -> bool {
             return false;
         }
     }
-
-    /* Reset current_dir to previous value */
-    path.pop();
-    env::set_current_dir(&path).unwrap();
     true
 }
 
-pub fn git_commit_trk(message: &str) -> bool {
-    if !set_to_trk_dir() {
-        println!(
-            "Could not commit to trk internal git repo!\
-                 (couldn't find upper level .trk dir)."
-        );
-        return false;
-    }
-
-    let mut p = env::current_dir().unwrap();
-    p.push(".trk");
-    if p.exists() {
-        env::set_current_dir(&p).unwrap();
-    } else {
-        println!("Couldn't access .trk sub directory to commit to trk internal git repo.");
-        return false;
-    }
+pub fn git_commit_trk(root: &Path, message: &str) -> bool {
+    let path = match trk_git_dir(root, "commit to") {
+        Some(path) => path,
+        None => return false,
+    };
+    /* A new project's timesheet isn't known to git yet */
     let output = Command::new("git")
-        .arg("commit")
-        .arg("timesheet.json")
-        .arg("-m")
-        .arg(message)
-        .output();
+        .arg("add")
+        .arg(TRK_SHEETS)
+        .current_dir(&path)
+        .output()
+        .and_then(|_| {
+            Command::new("git")
+                .arg("commit")
+                .arg("-m")
+                .arg(message)
+                .arg("--")
+                .arg(TRK_SHEETS)
+                .current_dir(&path)
+                .output()
+        });
     match output {
         Ok(_) => {}
         Err(_) => {
@@ -154,31 +1027,15 @@ pub fn git_commit_trk(message: &str) -> bool {
             return false;
         }
     }
-
-    /* Reset current_dir to previous value */
-    p.pop();
-    env::set_current_dir(&p).unwrap();
     true
 }
 
-pub fn git_pull() -> bool {
-    if !set_to_trk_dir() {
-        println!(
-            "Could not pull from git repo!\
-                 (couldn't find upper level .trk dir)."
-        );
-        return false;
-    }
-
-    let mut p = env::current_dir().unwrap();
-    p.push(".trk");
-    if p.exists() {
-        env::set_current_dir(&p).unwrap();
-    } else {
-        println!("Couldn't access .trk sub directory to pull from upstream .trk git repo.");
-        return false;
-    }
-    let output = Command::new("git").arg("pull").output();
+pub fn git_pull(root: &Path) -> bool {
+    let path = match trk_git_dir(root, "pull into") {
+        Some(path) => path,
+        None => return false,
+    };
+    let output = Command::new("git").arg("pull").current_dir(&path).output();
     match output {
         Ok(_) => {}
         Err(_) => {
@@ -186,30 +1043,15 @@ pub fn git_pull() -> bool {
             return false;
         }
     }
-
-    /* Reset current_dir to previous value */
-    p.pop();
-    env::set_current_dir(&p).unwrap();
     true
 }
-pub fn git_push() -> bool {
-    if !set_to_trk_dir() {
-        println!(
-            "Could not push to git repo!\
-                 (couldn't find upper level .trk dir)."
-        );
-        return false;
-    }
 
-    let mut p = env::current_dir().unwrap();
-    p.push(".trk");
-    if p.exists() {
-        env::set_current_dir(&p).unwrap();
-    } else {
-        println!("Couldn't access .trk sub directory to push to upstream .trk git repo.");
-        return false;
-    }
-    let output = Command::new("git").arg("push").output();
+pub fn git_push(root: &Path) -> bool {
+    let path = match trk_git_dir(root, "push from") {
+        Some(path) => path,
+        None => return false,
+    };
+    let output = Command::new("git").arg("push").current_dir(&path).output();
     match output {
         Ok(_) => {}
         Err(_) => {
@@ -217,10 +1059,6 @@ pub fn git_push() -> bool {
             return false;
         }
     }
-
-    /* Reset current_dir to previous value */
-    p.pop();
-    env::set_current_dir(&p).unwrap();
     true
 }
 
@@ -266,17 +1104,672 @@ pub fn git_commit_message(hash: &str) -> Option<String> {
     }
 }
 
-pub fn format_file(filename: &str) {
-    if Command::new("tidy")
-        .arg("--tidy-mark")
-        .arg("no")
-        .arg("-i")
+/** (name, email) of the author of commit hash */
+pub fn git_commit_author(hash: &str) -> Option<(String, String)> {
+    let output = Command::new("git")
+        .args(&["show", "-s", "--format=%an%n%ae", hash])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output.lines();
+    match (lines.next(), lines.next()) {
+        (Some(name), Some(email)) => Some((name.to_string(), email.to_string())),
+        _ => None,
+    }
+}
+
+/** Committer time of hash in Unix seconds, which differs from now
+ * if the commit is tracked later or was rebased */
+pub fn git_commit_time(hash: &str) -> Option<u64> {
+    let output = Command::new("git")
+        .args(&["show", "-s", "--format=%ct", hash])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/** Compiles tex into a pdf next to it. Err if pdflatex is missing or can't be run. */
+pub fn run_pdflatex(tex: &Path) -> Result<bool, String> {
+    let dir = tex.parent().unwrap_or_else(|| Path::new("."));
+    Command::new("pdflatex")
+        .arg("-interaction=nonstopmode")
+        .arg("-output-directory")
+        .arg(dir)
+        .arg(tex)
+        .output()
+        .map(|output| output.status.success())
+        .map_err(|e| e.to_string())
+}
+
+/** Hooks directory of the git repository around dir. Asks git, so that it is
+ * also right for worktrees, whose .git is a file pointing elsewhere, and with
+ * core.hooksPath set. None outside of git. */
+pub fn git_hooks_dir(dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(&["rev-parse", "--git-path", "hooks"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let hooks = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(dir.join(hooks))
+}
+
+/** Branch checked out in the repository around the working directory, or
+ * the short commit id on a detached HEAD. None outside of git or before
+ * the first commit. */
+pub fn git_current_branch() -> Option<String> {
+    let rev_parse = |args: &[&str]| {
+        let output = Command::new("git")
+            .arg("rev-parse")
+            .args(args)
+            .output()
+            .ok()?;
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if output.status.success() && !name.is_empty() {
+            Some(name)
+        } else {
+            None
+        }
+    };
+    match rev_parse(&["--abbrev-ref", "HEAD"]) {
+        Some(ref name) if name == "HEAD" => rev_parse(&["--short", "HEAD"]),
+        branch => branch,
+    }
+}
+
+/** (files changed, lines added, lines removed) by a commit, from git show --numstat,
+ * merges compared to their first parent. None for empty commits. */
+pub fn git_commit_stat(hash: &str) -> Option<(u32, u32, u32)> {
+    let output = match Command::new("git")
+        .arg("show")
+        .arg("--numstat")
+        .arg("--format=")
+        /* Merges show no numstat otherwise */
         .arg("-m")
-        .arg(filename)
+        .arg("--first-parent")
+        .arg(hash)
         .output()
-        .is_ok()
     {
+        Ok(output) => output,
+        Err(..) => return None,
+    };
+    if !output.status.success() {
+        let output = String::from_utf8_lossy(&output.stderr);
+        println!("git show --numstat <hash> failed. {}", output);
+        return None;
+    }
+    parse_numstat(&String::from_utf8_lossy(&output.stdout))
+}
+
+/* Lines of added<tab>removed<tab>path. Binary files count as changed files
+ * without lines, "-" for both. None without any file. */
+fn parse_numstat(numstat: &str) -> Option<(u32, u32, u32)> {
+    let mut stat = (0, 0, 0);
+    for line in numstat.lines().filter(|line| !line.trim().is_empty()) {
+        let mut columns = line.split('\t');
+        let added = columns.next().and_then(|n| n.parse::<u32>().ok());
+        let removed = columns.next().and_then(|n| n.parse::<u32>().ok());
+        stat.0 += 1;
+        stat.1 += added.unwrap_or(0);
+        stat.2 += removed.unwrap_or(0);
+    }
+    if stat.0 == 0 {
+        None
     } else {
-        println!("tidy-html not found!");
+        Some(stat)
+    }
+}
+
+/** file:// URL of path, with spaces and other special characters escaped.
+ * Works for paths that aren't valid UTF-8, but they have to be absolute. */
+pub fn file_url(path: &Path) -> Result<Url, String> {
+    Url::from_file_path(path).map_err(|()| format!("{} is not an absolute path", path.display()))
+}
+
+/** Opens the file with the command in TRK_BROWSER, e.g. "firefox --new-window",
+ * or else in the default browser */
+pub fn open_file(path: &Path) -> Result<(), String> {
+    match env::var("TRK_BROWSER") {
+        Ok(ref browser) if !browser.trim().is_empty() => open_with(browser, path),
+        _ => file_url(path).and_then(|url| open_in_browser(&url)),
+    }
+}
+
+/* Starts browser with its arguments and the path, without waiting for it to close */
+fn open_with(browser: &str, path: &Path) -> Result<(), String> {
+    let mut words = browser.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    Command::new(program)
+        .args(words)
+        .arg(path)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("could not run {}: {}", program, e))
+}
+
+/* Program opening a file or URL with the default application, and its arguments */
+#[cfg(target_os = "macos")]
+const OPENER: &[&str] = &["open"];
+#[cfg(target_os = "windows")]
+const OPENER: &[&str] = &["cmd", "/C", "start", ""];
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const OPENER: &[&str] = &["xdg-open"];
+
+/** Opens url in the default browser. If the opener fails, e.g. while the desktop
+ * is still starting, it is tried twice more after waiting a little longer each time. */
+pub fn open_in_browser(url: &Url) -> Result<(), String> {
+    let mut wait = 250;
+    for attempt in 1.. {
+        let status = Command::new(OPENER[0])
+            .args(&OPENER[1..])
+            .arg(url.as_str())
+            .status()
+            /* Not worth retrying if there is no opener at all */
+            .map_err(|e| format!("could not run {}: {}", OPENER[0], e))?;
+        if status.success() {
+            break;
+        }
+        if attempt == 3 {
+            return Err(format!("{} failed with {}", OPENER[0], status));
+        }
+        thread::sleep(time::Duration::from_millis(wait));
+        wait *= 2;
+    }
+    Ok(())
+}
+
+/* Elements without a closing tag */
+const VOID_ELEMENTS: [&str; 12] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "wbr",
+];
+
+/* (closing tags the line starts with, opening tags, closing tags) of one line.
+ * Tags inside <style> and <script> don't count, raw says whether the line
+ * begins inside one and is updated for the next. */
+fn tag_balance(line: &str, raw: &mut Option<String>) -> (usize, usize, usize) {
+    let (mut leading, mut opens, mut closes) = (0, 0, 0);
+    let mut at_start = true;
+    let mut rest = line;
+    while let Some(lt) = rest.find('<') {
+        if !rest[..lt].trim().is_empty() {
+            at_start = false;
+        }
+        let tag = &rest[lt + 1..];
+        let end = tag.find('>').map_or(tag.len(), |gt| gt + 1);
+        rest = &tag[end..];
+        let closing = tag.starts_with('/');
+        let name: String = tag[closing as usize..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if let Some(ref inside) = *raw {
+            if !(closing && name == *inside) {
+                at_start = false;
+                continue;
+            }
+        }
+        if name.is_empty() || VOID_ELEMENTS.contains(&name.as_str()) {
+            at_start = false;
+        } else if closing {
+            closes += 1;
+            *raw = None;
+            if at_start {
+                leading += 1;
+            }
+        } else if !tag[..end].ends_with("/>") {
+            opens += 1;
+            at_start = false;
+            if name == "style" || name == "script" {
+                *raw = Some(name);
+            }
+        }
+    }
+    (leading, opens, closes)
+}
+
+/** Reindents html by two spaces per open element, one line of the input per
+ * line of the output without blank ones. Lines are only trimmed, so the page
+ * looks the same, and the same html always gives the same output. */
+pub fn indent_html(html: &str) -> String {
+    let mut indented = String::with_capacity(html.len());
+    let mut depth: usize = 0;
+    let mut raw = None;
+    for line in html.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (leading, opens, closes) = tag_balance(line, &mut raw);
+        for _ in 0..depth.saturating_sub(leading) {
+            indented.push_str("  ");
+        }
+        indented.push_str(line);
+        indented.push('\n');
+        depth = (depth + opens).saturating_sub(closes);
+    }
+    indented
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_duration_table() {
+        let verbose = DurationFmt::default();
+        let compact = DurationFmt {
+            compact: true,
+            ..verbose
+        };
+        let hours = DurationFmt {
+            smallest: TimeUnit::Hours,
+            max_units: 3,
+            ..verbose
+        };
+        let three = DurationFmt {
+            max_units: 3,
+            ..verbose
+        };
+        let cases = [
+            (0, verbose, "0 seconds"),
+            (1, verbose, "1 second"),
+            (59, verbose, "59 seconds"),
+            (60, verbose, "1 minute"),
+            (61, verbose, "1 minute and 1 second"),
+            (3600, verbose, "1 hour"),
+            (3661, verbose, "1 hour and 1 minute"),
+            (3661, three, "1 hour, 1 minute and 1 second"),
+            (90061, verbose, "1 day and 1 hour"),
+            (183840, three, "2 days, 3 hours and 4 minutes"),
+            (183840, compact, "2d 3h"),
+            (86401, verbose, "1 day"),
+            (3599, hours, "0 hours"),
+            (183840, hours, "2 days and 3 hours"),
+            (0, compact, "0s"),
+        ];
+        for &(seconds, fmt, expected) in &cases {
+            assert_eq!(
+                format_duration(seconds, fmt),
+                expected,
+                "{} seconds",
+                seconds
+            );
+        }
+    }
+
+    #[test]
+    fn file_url_escapes_spaces() {
+        let url = file_url(Path::new("/home/me/my project/timesheet.html")).unwrap();
+        assert_eq!(url.as_str(), "file:///home/me/my%20project/timesheet.html");
+        assert_eq!(
+            url.to_file_path().unwrap(),
+            Path::new("/home/me/my project/timesheet.html")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_url_of_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = Path::new(OsStr::from_bytes(b"/tmp/caf\xe9/timesheet.html"));
+        assert_eq!(
+            file_url(path).unwrap().as_str(),
+            "file:///tmp/caf%E9/timesheet.html"
+        );
+        assert!(file_url(Path::new("timesheet.html")).is_err());
+    }
+
+    #[test]
+    fn commit_urls_follow_the_host() {
+        let cases = [
+            (
+                "https://github.com/medium-endian/trk.git",
+                "https://github.com/medium-endian/trk/commit/abc123",
+            ),
+            (
+                "git@github.com:medium-endian/trk.git",
+                "https://github.com/medium-endian/trk/commit/abc123",
+            ),
+            (
+                "https://gitlab.com/group/sub/project/",
+                "https://gitlab.com/group/sub/project/-/commit/abc123",
+            ),
+            (
+                "ssh://git@bitbucket.org/team/repo.git",
+                "https://bitbucket.org/team/repo/commits/abc123",
+            ),
+        ];
+        for &(repo_url, expected) in &cases {
+            assert_eq!(commit_url(repo_url, "abc123").as_deref(), Some(expected));
+        }
+        assert_eq!(commit_url("file:///srv/repo.git", "abc123"), None);
+        assert_eq!(commit_url("not a url", "abc123"), None);
+    }
+
+    #[test]
+    fn round_ts_boundaries() {
+        assert_eq!(round_ts(1234, 0), 1234);
+        assert_eq!(round_ts(0, 5), 0);
+        assert_eq!(round_ts(149, 5), 0);
+        assert_eq!(round_ts(150, 5), 300);
+        assert_eq!(round_ts(300, 5), 300);
+        assert_eq!(round_ts(449, 5), 300);
+        assert_eq!(round_ts(450, 5), 600);
+        assert_eq!(round_ts(3599, 60), 3600);
+    }
+
+    #[test]
+    fn sec_to_hms_string_drops_seconds_above_a_minute() {
+        assert_eq!(sec_to_hms_string(45), "45 seconds");
+        assert_eq!(sec_to_hms_string(125), "2 minutes");
+        assert_eq!(sec_to_hms_string(3661), "1 hour and 1 minute");
+    }
+
+    #[test]
+    fn format_duration_rounds_at_the_halves() {
+        let quarters = DurationFmt {
+            round: Round::Nearest15,
+            smallest: TimeUnit::Minutes,
+            ..DurationFmt::default()
+        };
+        let hours = DurationFmt {
+            round: Round::NearestHour,
+            ..quarters
+        };
+        let cases = [
+            (449, quarters, "0 minutes"),
+            (450, quarters, "15 minutes"),
+            (1349, quarters, "15 minutes"),
+            (1350, quarters, "30 minutes"),
+            (2 * 3600 + 449, quarters, "2 hours"),
+            (2 * 3600 + 3149, quarters, "2 hours and 45 minutes"),
+            (2 * 3600 + 3150, quarters, "3 hours"),
+            (1799, hours, "0 minutes"),
+            (1800, hours, "1 hour"),
+            (5399, hours, "1 hour"),
+            (5400, hours, "2 hours"),
+            (86400 + 1799, hours, "1 day"),
+        ];
+        for &(seconds, fmt, expected) in &cases {
+            assert_eq!(
+                format_duration(seconds, fmt),
+                expected,
+                "{} seconds",
+                seconds
+            );
+        }
+        assert_eq!(Round::None.apply(1349), 1349);
+    }
+
+    #[test]
+    fn sec_to_hms_string_boundaries() {
+        let cases = [
+            (59, "59 seconds"),
+            (60, "1 minute"),
+            (61, "1 minute"),
+            (3599, "59 minutes"),
+            (3600, "1 hour"),
+            (3661, "1 hour and 1 minute"),
+            (86461, "1 day"),
+        ];
+        for &(seconds, expected) in &cases {
+            assert_eq!(sec_to_hms_string(seconds), expected, "{} seconds", seconds);
+        }
+    }
+
+    #[test]
+    fn lock_is_shared_by_readers_and_exclusive_for_writers() {
+        use test_util::TempTrkDir;
+        let dir = TempTrkDir::new();
+        let no_wait = time::Duration::from_millis(0);
+        let reader = TrkLock::acquire(dir.path(), false, no_wait).unwrap();
+        let other_reader = TrkLock::acquire(dir.path(), false, no_wait).unwrap();
+        let blocked = TrkLock::acquire(dir.path(), true, no_wait);
+        assert_eq!(
+            blocked.err().map(|e| e.kind()),
+            Some(io::ErrorKind::WouldBlock)
+        );
+        drop(reader);
+        drop(other_reader);
+
+        let writer = TrkLock::acquire(dir.path(), true, no_wait).unwrap();
+        assert!(TrkLock::acquire(dir.path(), false, no_wait).is_err());
+        drop(writer);
+        assert!(TrkLock::acquire(dir.path(), true, no_wait).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn make_executable_only_adds_execute_bits() {
+        use std::os::unix::fs::PermissionsExt;
+        use test_util::TempTrkDir;
+        let dir = TempTrkDir::new();
+        let path = dir.path().join("hook");
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        make_executable(&path).unwrap();
+        assert_eq!(mode(&path), 0o750);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o4700)).unwrap();
+        make_executable(&path).unwrap();
+        assert_eq!(mode(&path), 0o4700);
+    }
+
+    #[test]
+    fn markdown_code_fences_any_backticks() {
+        assert_eq!(markdown_code("main"), "`main`");
+        assert_eq!(markdown_code("a`b"), "``a`b``");
+        assert_eq!(markdown_code("`x``"), "``` `x`` ```");
+    }
+
+    #[test]
+    fn short_hash_cuts_at_characters() {
+        assert_eq!(short_hash("0123456789abcdef", 7), "0123456");
+        assert_eq!(short_hash("abc", 7), "abc");
+        assert_eq!(
+            short_hash("\u{e4}\u{f6}\u{fc}\u{df}\u{e9}\u{e8}\u{ea}\u{eb}", 7),
+            "\u{e4}\u{f6}\u{fc}\u{df}\u{e9}\u{e8}\u{ea}"
+        );
+    }
+
+    #[test]
+    fn only_a_non_empty_no_color_turns_color_off() {
+        assert!(!no_color(None));
+        assert!(!no_color(Some(OsStr::new(""))));
+        assert!(no_color(Some(OsStr::new("1"))));
+    }
+
+    #[test]
+    fn day_bounds_enclose_the_timestamp() {
+        for &ts in &[0, 1, 3600, 86399, 1491523200, get_seconds()] {
+            let (start, next) = (local_day_start(ts), next_local_midnight(ts));
+            assert!(start <= ts && ts < next, "{}: {} to {}", ts, start, next);
+            assert!(next - start <= 25 * 3600);
+        }
+    }
+
+    #[test]
+    fn trk_git_repo_is_made_in_the_given_root() {
+        use test_util::TempTrkDir;
+        let dir = TempTrkDir::new();
+        let root = dir.path().join("global");
+        std::fs::create_dir_all(root.join(".trk")).unwrap();
+        assert!(git_init_trk(&root));
+        assert!(root.join(".trk").join(".git").is_dir());
+        /* Neither moved into the root nor made a repository here */
+        assert_eq!(env::current_dir().unwrap(), dir.path());
+        assert!(!dir.path().join(".git").exists());
+        assert!(!git_init_trk(dir.path()));
+    }
+
+    #[test]
+    fn until_a_date_takes_in_the_whole_day() {
+        let day_start =
+            local_to_seconds(&NaiveDate::from_ymd(2017, 4, 7).and_hms(0, 0, 0)).unwrap();
+        let until = parse_until("2017-04-07").unwrap();
+        assert_eq!(until, next_local_midnight(day_start) - 1);
+        assert_eq!(ts_to_day(until), "2017-04-07");
+        assert_eq!(
+            parse_until("2017-04-07T09:12"),
+            parse_timestamp("2017-04-07T09:12")
+        );
+        assert!(parse_until("2017-02-30").is_err());
+    }
+
+    #[test]
+    fn parse_relative_forms() {
+        use chrono::{Local, TimeZone};
+        let now = get_seconds();
+        let near = |parsed: Option<u64>, expected: u64| {
+            let parsed = parsed.unwrap();
+            assert!((expected..=expected + 2).contains(&parsed));
+        };
+        near(parse_relative("30m"), now - 1800);
+        near(parse_relative("2h"), now - 7200);
+        near(parse_relative("1h30m"), now - 5400);
+        near(parse_relative("01:30"), now - 5400);
+        assert_eq!(
+            parse_relative("2024-01-15T09:00:00"),
+            Some(Local.ymd(2024, 1, 15).and_hms(9, 0, 0).timestamp() as u64)
+        );
+        let yesterday = Local::now().naive_local().date().pred().and_hms(14, 0, 0);
+        assert_eq!(
+            parse_relative("yesterday 14:00"),
+            Some(Local.from_local_datetime(&yesterday).unwrap().timestamp() as u64)
+        );
+        assert_eq!(parse_relative("1491549156"), Some(1491549156));
+        assert_eq!(parse_relative("soon"), None);
+        assert_eq!(parse_relative("yesterday"), None);
+        assert_eq!(parse_duration_units("99999999999999999999d"), None);
+        assert_eq!(parse_duration_units("18446744073709551615m"), None);
+        assert_eq!(parse_duration_units("18446744073709551615s1s"), None);
+        assert!(parse_timestamp("+18446744073709551615s").is_err());
+    }
+
+    #[test]
+    fn indent_html_nests_by_elements() {
+        let html = "<!DOCTYPE html>\n<html>\n<head>\n<style>\ndiv > p { color: red; }\n</style>\n\
+                    <link rel=\"stylesheet\" href=\"style.css\">\n</head>\n<body>\n\
+                    <div class=\"entry\">09:12: Note: a<br>\n   b\n<hr>\n\n</div>\n\
+                    <section><p>Worked</p>\n<div>\n<p>x</p></div></section>\n</body>\n</html>";
+        let expected = "<!DOCTYPE html>\n<html>\n  <head>\n    <style>\n      div > p { color: red; }\n\
+                        \x20   </style>\n    <link rel=\"stylesheet\" href=\"style.css\">\n  </head>\n\
+                        \x20 <body>\n    <div class=\"entry\">09:12: Note: a<br>\n      b\n      <hr>\n\
+                        \x20   </div>\n    <section><p>Worked</p>\n      <div>\n        <p>x</p></div></section>\n\
+                        \x20 </body>\n</html>\n";
+        assert_eq!(indent_html(html), expected);
+        assert_eq!(indent_html(expected), expected);
+    }
+
+    #[test]
+    fn open_with_runs_the_given_command() {
+        let path = Path::new("timesheet.html");
+        assert!(open_with("true --ignored", path).is_ok());
+        let e = open_with("no-such-browser-for-trk", path).unwrap_err();
+        assert!(e.starts_with("could not run no-such-browser-for-trk"));
+    }
+
+    #[test]
+    fn numstat_counts_files_and_lines() {
+        let numstat = "10\t2\tsrc/main.rs\n-\t-\tsheet.png\n30\t10\tREADME.md\n";
+        assert_eq!(parse_numstat(numstat), Some((3, 40, 12)));
+        assert_eq!(parse_numstat(""), None);
+        assert_eq!(parse_numstat("\n\n"), None);
+    }
+
+    #[test]
+    fn cents_are_parsed_and_formatted() {
+        assert_eq!(parse_cents("85"), Some(8500));
+        assert_eq!(parse_cents("85.5"), Some(8550));
+        assert_eq!(parse_cents(" 85.05 "), Some(8505));
+        for bad in &["", ".5", "85.555", "-1", "8,50", "eighty"] {
+            assert_eq!(parse_cents(bad), None, "{}", bad);
+        }
+        assert_eq!(format_cents(123450, Some("EUR")), "1234.50 EUR");
+        assert_eq!(format_cents(5, None), "0.05");
+    }
+
+    fn hex(digest: [u8; 32]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha256_nist_vectors() {
+        /* FIPS 180-2, appendix B, and the NIST examples of the empty message */
+        assert_eq!(
+            hex(sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        /* 448 bits, so the padding takes a second block */
+        assert_eq!(
+            hex(sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hex(sha256(&[b'a'; 1_000_000])),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn hmac_sha256_rfc_4231_vectors() {
+        /* Test cases 1 to 4, 6 and 7. 5 checks truncated output only. */
+        assert_eq!(
+            hex(hmac_sha256(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(hmac_sha256(&[0xaa; 20], &[0xdd; 50])),
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"
+        );
+        let key: Vec<u8> = (1..26).collect();
+        assert_eq!(
+            hex(hmac_sha256(&key, &[0xcd; 50])),
+            "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"
+        );
+        assert_eq!(
+            hex(hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_eq!(
+            hex(hmac_sha256(
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger \
+                  than block-size data. The key needs to be hashed before being \
+                  used by the HMAC algorithm."
+            )),
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"
+        );
+    }
+
+    #[test]
+    fn salted_hash_is_a_truncated_hmac_sha256() {
+        assert_eq!(
+            salted_hash("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e"
+        );
+        assert_ne!(
+            salted_hash("other", "what do ya want for nothing?"),
+            "5bdcc146bf60754e"
+        );
     }
 }